@@ -6,8 +6,8 @@ use sp_finality_grandpa::AuthorityId as GrandpaId;
 use sp_runtime::traits::{IdentifyAccount, Verify};
 use std::{collections::BTreeMap, str::FromStr};
 use webb_runtime::{
-	AccountId, AuraConfig, BalancesConfig, EVMConfig, GenesisConfig, GrandpaConfig, Signature, SudoConfig,
-	SystemConfig, WASM_BINARY,
+	AccountId, AuraConfig, BalancesConfig, EVMConfig, GenesisConfig, GrandpaConfig, MerkleConfig, Signature,
+	SudoConfig, SystemConfig, WASM_BINARY,
 };
 
 // The URL for the telemetry server.
@@ -166,5 +166,9 @@ fn testnet_genesis(
 		pallet_ethereum: Default::default(),
 		pallet_evm: EVMConfig { accounts: evm_accounts },
 		pallet_dynamic_fee: Default::default(),
+		merkle: MerkleConfig {
+			verifying_key: None,
+			trees: vec![],
+		},
 	}
 }
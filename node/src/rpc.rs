@@ -52,7 +52,7 @@ pub struct GrandpaDeps<B> {
 }
 
 /// Full client dependencies.
-pub struct FullDeps<C, P, SC, B> {
+pub struct FullDeps<C, P, SC, B, S> {
 	/// The client instance to use.
 	pub client: Arc<C>,
 	/// Transaction pool instance.
@@ -77,11 +77,13 @@ pub struct FullDeps<C, P, SC, B> {
 	pub backend: Arc<fc_db::Backend<Block>>,
 	/// Maximum number of logs in a query.
 	pub max_past_logs: u32,
+	/// Off-chain storage the off-chain workers cache the merkle trees in.
+	pub offchain_storage: Option<S>,
 }
 
 /// Instantiate all Full RPC extensions.
-pub fn create_full<C, P, SC, B>(
-	deps: FullDeps<C, P, SC, B>,
+pub fn create_full<C, P, SC, B, S>(
+	deps: FullDeps<C, P, SC, B, S>,
 	subscription_task_executor: SubscriptionTaskExecutor,
 ) -> jsonrpc_core::IoHandler<sc_rpc_api::Metadata>
 where
@@ -96,10 +98,12 @@ where
 	C::Api: fp_rpc::EthereumRuntimeRPCApi<Block>,
 	C::Api: BlockBuilder<Block>,
 	C::Api: merkle::MerkleApi<Block>,
+	C::Api: merkle::MerkleManagerApi<Block, AccountId>,
 	P: TransactionPool<Block = Block> + 'static,
 	SC: SelectChain<Block> + 'static,
 	B: sc_client_api::Backend<Block> + Send + Sync + 'static,
 	B::State: sc_client_api::backend::StateBackend<sp_runtime::traits::HashFor<Block>>,
+	S: sp_core::offchain::OffchainStorage + 'static,
 {
 	use fc_rpc::{
 		EthApi, EthApiServer, EthDevSigner, EthFilterApi, EthFilterApiServer, EthPubSubApi, EthPubSubApiServer,
@@ -122,6 +126,7 @@ where
 		enable_dev_signer,
 		grandpa,
 		max_past_logs,
+		offchain_storage,
 	} = deps;
 
 	let GrandpaDeps {
@@ -188,6 +193,8 @@ where
 
 	io.extend_with(Web3ApiServer::to_delegate(Web3Api::new(client.clone())));
 
+	let merkle_subscriptions = SubscriptionManager::new(Arc::new(subscription_task_executor.clone()));
+
 	io.extend_with(EthPubSubApiServer::to_delegate(EthPubSubApi::new(
 		pool.clone(),
 		client.clone(),
@@ -199,7 +206,20 @@ where
 		overrides,
 	)));
 
-	io.extend_with(MerkleApi::to_delegate(MerkleClient::new(client.clone())));
+	let merkle = MerkleClient::with_config(
+		client.clone(),
+		merkle_subscriptions,
+		merkle_rpc::DEFAULT_MAX_LEAF_RANGE,
+		merkle_rpc::DEFAULT_MAX_BATCH_LEAVES,
+		merkle_rpc::DEFAULT_MAX_BATCH_PROOFS,
+		merkle_rpc::DEFAULT_MAX_EXPORT_BYTES,
+		merkle_rpc::DEFAULT_MAX_PROOF_DEPTH,
+		merkle_rpc::DEFAULT_MAX_REBUILD_LEAVES,
+	);
+	io.extend_with(MerkleApi::to_delegate(match offchain_storage {
+		Some(storage) => merkle.with_offchain_storage(storage),
+		None => merkle,
+	}));
 
 	io.extend_with(sc_finality_grandpa_rpc::GrandpaApi::to_delegate(
 		GrandpaRpcHandler::new(
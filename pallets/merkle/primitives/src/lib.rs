@@ -0,0 +1,114 @@
+//! # Merkle Primitives
+//!
+//! Hashing logic of the Merkle trees, shared by the Merkle pallet and the
+//! clients of the chain. It doesn't depend on the runtime, so light clients
+//! and other tools can verify the membership proofs exactly the way the chain
+//! builds the trees.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(test)]
+mod tests;
+
+use bulletproofs::BulletproofGens;
+use bulletproofs_gadgets::poseidon::{
+	builder::{Poseidon, PoseidonBuilder},
+	PoseidonSbox, Poseidon_hash_2,
+};
+use codec::{Decode, Encode};
+use curve25519_dalek::scalar::Scalar;
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Hash functions for MerkleTree
+///
+/// Only `PoseidonDefault` trees can be used with the zero-knowledge membership
+/// proofs, `Blake2` and `Sha256` are cheaper alternatives for the trees which
+/// don't need them.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Clone, Encode, Decode, PartialEq, Eq, Debug)]
+pub enum HashFunction {
+	PoseidonDefault,
+	PoseidonExp3,
+	PoseidonExp5,
+	PoseidonExp17,
+	MiMC,
+	Blake2,
+	Sha256,
+}
+
+impl HashFunction {
+	/// Whether the trees can be built with this hash function
+	pub fn is_supported(&self) -> bool {
+		matches!(
+			self,
+			HashFunction::PoseidonDefault | HashFunction::Blake2 | HashFunction::Sha256
+		)
+	}
+}
+
+/// Poseidon parameters of the `PoseidonDefault` trees. The generators are only
+/// used for proving, they don't change the hashes, so a single one is enough.
+pub fn default_poseidon() -> Poseidon {
+	PoseidonBuilder::new(6)
+		.bulletproof_gens(BulletproofGens::new(1, 1))
+		.sbox(PoseidonSbox::Exponentiation17)
+		.build()
+}
+
+/// Hashes two nodes of the tree into their parent, `hash_params` are only
+/// used by the Poseidon hash functions.
+pub fn hash(hasher: &HashFunction, left: Scalar, right: Scalar, hash_params: &Poseidon) -> Scalar {
+	let mut input = [0u8; 64];
+	input[..32].copy_from_slice(left.as_bytes());
+	input[32..].copy_from_slice(right.as_bytes());
+	match hasher {
+		HashFunction::Blake2 => {
+			let mut output = [0u8; 32];
+			output.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], &input).as_bytes());
+			Scalar::from_bytes_mod_order(output)
+		}
+		HashFunction::Sha256 => {
+			let mut output = [0u8; 32];
+			output.copy_from_slice(&Sha256::digest(&input));
+			Scalar::from_bytes_mod_order(output)
+		}
+		_ => Poseidon_hash_2(left, right, hash_params),
+	}
+}
+
+/// Verifies the membership path of the leaf against the root of a tree built
+/// with `hasher`.
+///
+/// `path` holds the sibling nodes from the leaf up to the root, and for each
+/// of them `directions` is `true` if the sibling is on the right side. Fails
+/// if any of the nodes isn't a canonical scalar, the lengths don't match or
+/// the trees can't be built with `hasher`.
+pub fn verify_path(
+	leaf: [u8; 32],
+	path: &[[u8; 32]],
+	directions: &[bool],
+	root: [u8; 32],
+	hasher: HashFunction,
+) -> bool {
+	if !hasher.is_supported() || path.len() != directions.len() {
+		return false;
+	}
+	let hash_params = default_poseidon();
+	let mut node = match Scalar::from_canonical_bytes(leaf) {
+		Some(leaf) => leaf,
+		None => return false,
+	};
+	for (sibling, is_right) in path.iter().zip(directions) {
+		let sibling = match Scalar::from_canonical_bytes(*sibling) {
+			Some(sibling) => sibling,
+			None => return false,
+		};
+		node = match is_right {
+			true => hash(&hasher, node, sibling, &hash_params),
+			false => hash(&hasher, sibling, node, &hash_params),
+		};
+	}
+	node.to_bytes() == root
+}
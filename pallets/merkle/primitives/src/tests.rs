@@ -0,0 +1,93 @@
+use super::*;
+
+const BLAKE2_OF_ZEROS: [u8; 32] = [
+	14, 185, 35, 176, 203, 210, 77, 245, 68, 1, 217, 152, 83, 31, 238, 173, 53, 164, 122, 153, 244, 222, 237, 32, 93,
+	228, 175, 129, 18, 15, 151, 97,
+];
+const SHA256_OF_ZEROS: [u8; 32] = [
+	245, 165, 253, 66, 209, 106, 32, 48, 39, 152, 239, 110, 211, 9, 151, 155, 67, 0, 61, 35, 32, 217, 240, 232, 234,
+	152, 49, 169, 39, 89, 251, 75,
+];
+
+#[test]
+fn should_hash_with_chosen_function() {
+	let hash_params = default_poseidon();
+	let zero = Scalar::zero();
+	assert_eq!(
+		hash(&HashFunction::Blake2, zero, zero, &hash_params),
+		Scalar::from_bytes_mod_order(BLAKE2_OF_ZEROS)
+	);
+	assert_eq!(
+		hash(&HashFunction::Sha256, zero, zero, &hash_params),
+		Scalar::from_bytes_mod_order(SHA256_OF_ZEROS)
+	);
+	assert_eq!(
+		hash(&HashFunction::PoseidonDefault, zero, zero, &hash_params),
+		Poseidon_hash_2(zero, zero, &hash_params)
+	);
+}
+
+#[test]
+fn should_verify_path() {
+	let hash_params = default_poseidon();
+	let hashers = [
+		HashFunction::PoseidonDefault,
+		HashFunction::Blake2,
+		HashFunction::Sha256,
+	];
+	for hasher in hashers.iter() {
+		let leaves: Vec<Scalar> = (1..5u64).map(Scalar::from).collect();
+		let node0 = hash(hasher, leaves[0], leaves[1], &hash_params);
+		let node1 = hash(hasher, leaves[2], leaves[3], &hash_params);
+		let root = hash(hasher, node0, node1, &hash_params).to_bytes();
+
+		let leaf = leaves[2].to_bytes();
+		let path = [leaves[3].to_bytes(), node0.to_bytes()];
+		assert!(verify_path(leaf, &path, &[true, false], root, hasher.clone()));
+		assert!(!verify_path(leaf, &path, &[false, true], root, hasher.clone()));
+		assert!(!verify_path(
+			leaves[3].to_bytes(),
+			&path,
+			&[true, false],
+			root,
+			hasher.clone()
+		));
+		assert!(!verify_path(
+			leaf,
+			&path,
+			&[true, false],
+			node1.to_bytes(),
+			hasher.clone()
+		));
+	}
+}
+
+#[test]
+fn should_not_verify_invalid_path() {
+	let hash_params = default_poseidon();
+	let leaf = Scalar::one();
+	let sibling = Scalar::zero();
+	let root = hash(&HashFunction::Blake2, leaf, sibling, &hash_params).to_bytes();
+	let path = [sibling.to_bytes()];
+
+	assert!(verify_path(leaf.to_bytes(), &path, &[true], root, HashFunction::Blake2));
+	// Every sibling needs a direction
+	assert!(!verify_path(
+		leaf.to_bytes(),
+		&path,
+		&[true, true],
+		root,
+		HashFunction::Blake2
+	));
+	// Trees can't be built with the unsupported hash functions
+	assert!(!verify_path(leaf.to_bytes(), &path, &[true], root, HashFunction::MiMC));
+	// Nodes have to be canonical scalars
+	assert!(!verify_path([255u8; 32], &path, &[true], root, HashFunction::Blake2));
+	assert!(!verify_path(
+		leaf.to_bytes(),
+		&[[255u8; 32]],
+		&[true],
+		root,
+		HashFunction::Blake2
+	));
+}
@@ -1,18 +1,394 @@
 #![allow(clippy::clippy::too_many_arguments, clippy::unnecessary_mut_passed)]
 
-use std::sync::Arc;
+use std::{convert::TryFrom, sync::Arc};
 
-use jsonrpc_core::{Error, ErrorCode, Result};
+use codec::{Codec, Decode, Encode};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use jsonrpc_core::{
+	futures::{Future, Sink},
+	Error, ErrorCode, Result,
+};
 use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::{manager::SubscriptionManager, typed::Subscriber, SubscriptionId};
+use log::warn;
+use sc_client_api::{BlockImportNotification, BlockchainEvents};
+use serde::{Deserialize, Serialize};
 use sp_api::ProvideRuntimeApi;
 use sp_blockchain::HeaderBackend;
-use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use sp_core::{bytes::to_hex, hashing::blake2_256, offchain::OffchainStorage, Bytes};
+use sp_offchain::STORAGE_PREFIX;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor},
+};
 
-use merkle::MerkleApi as MerkleRuntimeApi;
+use merkle::{
+	offchain::{node_key, tree_key, CachedTree},
+	utils::keys::ScalarData,
+	LeafChange, MerkleApi as MerkleRuntimeApi, MerkleManagerApi as MerkleManagerRuntimeApi,
+};
+
+/// Errors returned by the merkle RPC methods.
+///
+/// Each variant maps to a stable `ServerError` code, and carries the error
+/// data describing what was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MerkleRpcError {
+	/// The runtime call failed, code `1500`.
+	RuntimeError(String),
+	/// More leaves were requested than allowed in a single call, code `1512`.
+	TooManyLeaves(String),
+	/// The start of the leaf range is past its end, or one of its bounds is
+	/// past `u32::MAX`, code `1513`.
+	InvalidRange(String),
+	/// The tree doesn't exist or isn't initialized yet, code `1514`.
+	TreeNotFound(String),
+	/// The leaf isn't in the tree, code `1515`.
+	LeafNotFound(String),
+	/// More proofs were submitted than allowed in a single call, code `1516`.
+	TooManyProofs(String),
+	/// The leaves and the nodes of the tree were pruned, code `1517`.
+	TreePruned(String),
+	/// The block isn't known or isn't finalized yet, code `1518`.
+	UnknownBlock(String),
+	/// More nodes were requested than allowed in a single call, code `1519`.
+	TooManyNodes(String),
+	/// Building the proof would take more work than allowed in a single call,
+	/// code `1520`.
+	ProofTooExpensive(String),
+	/// The nodes of the tree aren't migrated to the storage version of the
+	/// pallet yet, code `1521`.
+	MigrationPending(String),
+}
+
+impl MerkleRpcError {
+	/// The `ServerError` code of the error.
+	pub fn code(&self) -> i64 {
+		match self {
+			MerkleRpcError::RuntimeError(_) => 1500,
+			MerkleRpcError::TooManyLeaves(_) => 1512,
+			MerkleRpcError::InvalidRange(_) => 1513,
+			MerkleRpcError::TreeNotFound(_) => 1514,
+			MerkleRpcError::LeafNotFound(_) => 1515,
+			MerkleRpcError::TooManyProofs(_) => 1516,
+			MerkleRpcError::TreePruned(_) => 1517,
+			MerkleRpcError::UnknownBlock(_) => 1518,
+			MerkleRpcError::TooManyNodes(_) => 1519,
+			MerkleRpcError::ProofTooExpensive(_) => 1520,
+			MerkleRpcError::MigrationPending(_) => 1521,
+		}
+	}
+}
+
+impl From<MerkleRpcError> for Error {
+	fn from(e: MerkleRpcError) -> Self {
+		let code = ErrorCode::ServerError(e.code());
+		let (message, data) = match e {
+			MerkleRpcError::RuntimeError(data) => ("RuntimeError", data),
+			MerkleRpcError::TooManyLeaves(data) => ("TooManyLeaves", data),
+			MerkleRpcError::InvalidRange(data) => ("InvalidRange", data),
+			MerkleRpcError::TreeNotFound(data) => ("TreeNotFound", data),
+			MerkleRpcError::LeafNotFound(data) => ("LeafNotFound", data),
+			MerkleRpcError::TooManyProofs(data) => ("TooManyProofs", data),
+			MerkleRpcError::TreePruned(data) => ("TreePruned", data),
+			MerkleRpcError::UnknownBlock(data) => ("UnknownBlock", data),
+			MerkleRpcError::TooManyNodes(data) => ("TooManyNodes", data),
+			MerkleRpcError::ProofTooExpensive(data) => ("ProofTooExpensive", data),
+			MerkleRpcError::MigrationPending(data) => ("MigrationPending", data),
+		};
+		Error {
+			code,
+			message: message.into(),
+			data: Some(data.into()),
+		}
+	}
+}
+
+/// Membership proof of a leaf in the tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MembershipProof {
+	/// Sibling nodes on the path from the leaf to the root.
+	pub path: Vec<[u8; 32]>,
+	/// For each sibling in `path`, `true` if it is on the right side.
+	pub directions: Vec<bool>,
+}
+
+/// Membership proof of a leaf along with the root it verifies against.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootedMembershipProof {
+	/// Root of the tree the proof was built for.
+	pub root: [u8; 32],
+	/// Sibling nodes on the path from the leaf to the root.
+	pub path: Vec<[u8; 32]>,
+	/// For each sibling in `path`, `true` if it is on the right side.
+	pub directions: Vec<bool>,
+}
+
+/// Outcome of the dry run of a withdrawal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WithdrawCheck {
+	/// The withdrawal would be accepted.
+	Valid,
+	/// The proof is made against a root which is neither cached nor retained
+	/// in the root history of the tree.
+	UnknownRoot,
+	/// The nullifier of the proof was already used.
+	NullifierUsed,
+	/// The proof doesn't decode, is in an unsupported version or doesn't
+	/// verify against the tree.
+	ProofInvalid,
+	/// The tree has fewer leaves than its minimum anonymity set, so no
+	/// withdrawal is accepted yet.
+	AnonymitySetTooSmall,
+	/// The relayer fee is larger than the denomination of the tree.
+	FeeExceedsDeposit,
+}
+
+impl From<merkle::WithdrawCheck> for WithdrawCheck {
+	fn from(check: merkle::WithdrawCheck) -> Self {
+		match check {
+			merkle::WithdrawCheck::Valid => WithdrawCheck::Valid,
+			merkle::WithdrawCheck::UnknownRoot => WithdrawCheck::UnknownRoot,
+			merkle::WithdrawCheck::NullifierUsed => WithdrawCheck::NullifierUsed,
+			merkle::WithdrawCheck::ProofInvalid => WithdrawCheck::ProofInvalid,
+			merkle::WithdrawCheck::AnonymitySetTooSmall => WithdrawCheck::AnonymitySetTooSmall,
+			merkle::WithdrawCheck::FeeExceedsDeposit => WithdrawCheck::FeeExceedsDeposit,
+		}
+	}
+}
+
+/// What happens to the leaves inserted into a full tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OnFull {
+	/// The leaves are rejected.
+	Reject,
+	/// The tree rolls over into a successor, which the leaves are inserted
+	/// into.
+	Rollover,
+}
+
+impl From<merkle::OnFull> for OnFull {
+	fn from(on_full: merkle::OnFull) -> Self {
+		match on_full {
+			merkle::OnFull::Reject => OnFull::Reject,
+			merkle::OnFull::Rollover => OnFull::Rollover,
+		}
+	}
+}
+
+/// Stored configuration and state of a tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeMetadata {
+	/// Depth of the tree.
+	pub depth: u8,
+	/// Number of leaves inserted into the tree.
+	pub leaf_count: u32,
+	/// Whether the tree is stopped.
+	pub is_stopped: bool,
+	/// Name of the hash function used by the tree, e.g. `PoseidonDefault`.
+	pub hasher: String,
+	/// Number of the most recent roots retained in the root history, any of
+	/// which the proofs can be made against.
+	pub root_history_size: u32,
+	/// Whether the leaves and the nodes of the tree were pruned, only its
+	/// root and metadata are kept.
+	pub is_pruned: bool,
+	/// Amount every leaf of the tree locks as a deposit, or `None` if the
+	/// leaves are plain commitments.
+	pub denomination: Option<u128>,
+	/// Version of the commitment format the leaves of the tree are encoded
+	/// in, the clients should check it against the version of their circuit.
+	pub commitment_version: u16,
+	/// Whether the leaves of the tree can only be appended, otherwise the
+	/// manager can update them.
+	pub is_append_only: bool,
+	/// Number of leaves which can still be added before the tree is full.
+	pub leaves_remaining: u32,
+	/// Number of leaves the tree must have before the withdrawals from it are
+	/// accepted.
+	pub min_anonymity_set: u32,
+	/// Whether the inputs are hashed into the leaves on insert, otherwise
+	/// they are stored as the leaves.
+	pub hash_leaf_on_insert: bool,
+	/// Id of the asset the deposits are made in, or `None` if they are made
+	/// in the native currency.
+	pub asset_id: Option<u64>,
+	/// What happens to the leaves inserted once the tree is full.
+	pub on_full: OnFull,
+	/// Id of the tree the tree rolled over into, which the new leaves go
+	/// to, or `None` if it didn't roll over.
+	pub successor_tree_id: Option<u32>,
+	/// Whether the nodes of the tree are migrated to the storage version of
+	/// the pallet, its membership proofs are only built once they are.
+	pub is_migrated: bool,
+}
+
+/// Storage version of the merkle pallet and the progress of its migration.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageVersion {
+	/// Name of the current storage version, e.g. `V2_0_0`.
+	pub version: String,
+	/// Whether some of the trees are still to be migrated, their nodes and
+	/// membership proofs can't be relied on until they are.
+	pub migration_pending: bool,
+}
+
+/// Public input of the zero-knowledge membership proofs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PublicInput {
+	/// Root the proof is made against.
+	Root,
+	/// Hash of the nullifier of the proof.
+	NullifierHash,
+	/// Recipient the proof is made for.
+	Recipient,
+	/// Relayer the proof is made for.
+	Relayer,
+}
+
+impl From<merkle::PublicInput> for PublicInput {
+	fn from(input: merkle::PublicInput) -> Self {
+		match input {
+			merkle::PublicInput::Root => PublicInput::Root,
+			merkle::PublicInput::NullifierHash => PublicInput::NullifierHash,
+			merkle::PublicInput::Recipient => PublicInput::Recipient,
+			merkle::PublicInput::Relayer => PublicInput::Relayer,
+		}
+	}
+}
+
+/// Verifying key of a tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifierKey {
+	/// Raw bytes of the key, which also hold the parameters of the Poseidon
+	/// hasher of the tree.
+	pub key: Bytes,
+	/// Blake2-256 hash of the key bytes.
+	pub hash: [u8; 32],
+	/// Order the public inputs are appended to the transcript of the proof
+	/// in, labelled `public_input`, before the prover is created. None of
+	/// them are appended if it's empty.
+	pub public_input_layout: Vec<PublicInput>,
+}
+
+/// Manager of a tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeManager<AccountId> {
+	/// Account id of the manager.
+	pub account_id: AccountId,
+	/// Whether the manager is required to execute the guarded calls, if not
+	/// anyone can add the leaves and the nullifiers.
+	pub required: bool,
+}
+
+/// A chunk of the leaves of a tree, as returned by `merkle_exportTree`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeExport<BlockHash> {
+	/// Leaves of the tree in index order, starting from the requested index.
+	pub leaves: Vec<[u8; 32]>,
+	/// Index to continue the export from, or `None` if the export reached the
+	/// last leaf of the tree.
+	pub next: Option<u32>,
+	/// Hash of the block the leaves were read at.
+	pub at: BlockHash,
+}
+
+/// The leaves of a tree along with the block they were read at, as returned
+/// by `merkle_treeLeavesWithBlock`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeavesWithBlock<BlockHash> {
+	/// Hash of the block the leaves were read at.
+	pub block_hash: BlockHash,
+	/// Leaves of the tree in index order, starting from the requested index.
+	pub leaves: Vec<[u8; 32]>,
+}
+
+/// The methods and limits of the node, as returned by `merkle_capabilities`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MerkleCapabilities {
+	/// Names of the merkle RPC methods the node supports, including the
+	/// subscriptions.
+	pub methods: Vec<String>,
+	/// Number of leaves from which the ranges requested at once are
+	/// rejected, so at most one leaf less can be requested.
+	pub max_leaf_range: u32,
+	/// Maximum number of leaves across all the ranges of a batch.
+	pub max_batch_leaves: u32,
+	/// Maximum number of proofs that can be verified at once.
+	pub max_batch_proofs: u32,
+	/// Maximum size in bytes of the leaves exported at once, as serialized in
+	/// the JSON response.
+	pub max_export_bytes: u32,
+	/// Maximum depth of the trees the membership proofs are built for.
+	pub max_proof_depth: u8,
+	/// Maximum number of leaves of the trees the membership proofs are
+	/// rebuilt for by `merkle_rebuildPath`.
+	pub max_rebuild_leaves: u32,
+	/// Number of the most recent roots retained in the root history of every
+	/// tree, as of the queried block.
+	pub root_history_size: u32,
+}
+
+/// A leaf pushed to the `merkle_subscribeLeaves` subscribers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeafNotification<BlockHash> {
+	/// Id of the tree the leaf was inserted into.
+	pub tree_id: u32,
+	/// Index of the leaf in the tree.
+	pub leaf_index: u32,
+	/// The leaf itself.
+	pub leaf: [u8; 32],
+	/// Hash of the block that inserted or replaced the leaf.
+	pub block_hash: BlockHash,
+}
+
+/// A leaf whose insertion was rolled back by a reorg, pushed to the
+/// `merkle_subscribeLeaves` subscribers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevertedLeaf<BlockHash> {
+	/// Id of the tree the leaf was inserted into.
+	pub tree_id: u32,
+	/// Index of the leaf in the tree, which is free again.
+	pub leaf_index: u32,
+	/// Hash of the retracted block that inserted the leaf.
+	pub block_hash: BlockHash,
+}
+
+/// An event pushed to the `merkle_subscribeLeaves` subscribers, tagged with
+/// its `type`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum LeafEvent<BlockHash> {
+	/// A leaf was inserted by a block of the best chain.
+	LeafInserted(LeafNotification<BlockHash>),
+	/// A leaf was inserted by a block which is no longer in the best chain.
+	LeafReverted(RevertedLeaf<BlockHash>),
+	/// A leaf was replaced by a block of the best chain, or restored once the
+	/// block which replaced it is no longer in the best chain, along with the
+	/// hash of the block the leaf is the one of.
+	LeafUpdated(LeafNotification<BlockHash>),
+}
 
 /// Merkle RPC methods.
 #[rpc]
-pub trait MerkleApi<BlockHash> {
+pub trait MerkleApi<BlockHash, AccountId> {
+	/// RPC Metadata
+	type Metadata;
+
 	/// Get The MerkleTree leaves.
 	///
 	/// This method calls into a runtime with `Merkle` pallet included and
@@ -20,33 +396,878 @@ pub trait MerkleApi<BlockHash> {
 	/// Optionally, a block hash at which the runtime should be queried can be
 	/// specified.
 	///
-	/// Returns the (full) a Vec<[u8; 32]> of the leaves.
+	/// The range `from..to` is clamped to the number of leaves in the tree,
+	/// an empty range returns no leaves and a reversed range is rejected.
+	///
+	/// Returns the (full) a Vec<[u8; 32]> of the leaves, or a `TreeNotFound`
+	/// error if the tree doesn't exist and a `TreePruned` error if its leaves
+	/// were pruned, so neither is mistaken for an empty tree.
 	#[rpc(name = "merkle_treeLeaves")]
 	fn tree_leaves(&self, tree_id: u32, from: usize, to: usize, at: Option<BlockHash>) -> Result<Vec<[u8; 32]>>;
+
+	/// Get The MerkleTree leaves at a block number.
+	///
+	/// Same as `merkle_treeLeaves`, except the leaves are read at the
+	/// finalized block with the given number instead of a block hash.
+	///
+	/// Returns an `UnknownBlock` error if the block isn't finalized yet.
+	#[rpc(name = "merkle_treeLeavesAt")]
+	fn tree_leaves_at(&self, tree_id: u32, from: usize, to: usize, block_number: u32) -> Result<Vec<[u8; 32]>>;
+
+	/// Get The MerkleTree leaves along with the block they were read at.
+	///
+	/// Same as `merkle_treeLeaves`, except the hash of the block the leaves
+	/// were read at is returned too, which is the best block if none is
+	/// specified. Passing it as `at` to the following calls keeps the pages of
+	/// a large tree consistent while new blocks arrive.
+	#[rpc(name = "merkle_treeLeavesWithBlock")]
+	fn tree_leaves_with_block(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<BlockHash>,
+	) -> Result<LeavesWithBlock<BlockHash>>;
+
+	/// Get The MerkleTree leaves as hex strings.
+	///
+	/// Same as `merkle_treeLeaves`, except each leaf is encoded as a
+	/// lowercase, `0x`-prefixed hex string of 64 digits.
+	///
+	/// Returns the Vec<String> of the leaves.
+	#[rpc(name = "merkle_treeLeavesHex")]
+	fn tree_leaves_hex(&self, tree_id: u32, from: usize, to: usize, at: Option<BlockHash>) -> Result<Vec<String>>;
+
+	/// Get The MerkleTree leaves as a single SCALE-encoded value.
+	///
+	/// Same as `merkle_treeLeaves`, except the leaves are returned as the
+	/// SCALE-encoded `Vec<[u8; 32]>`, which is about half the size of the
+	/// hex strings on the wire and decodes in one step.
+	///
+	/// Returns the Bytes of the encoded leaves.
+	#[rpc(name = "merkle_treeLeavesEncoded")]
+	fn tree_leaves_encoded(&self, tree_id: u32, from: usize, to: usize, at: Option<BlockHash>) -> Result<Bytes>;
+
+	/// Get The MerkleTree leaves along with their indices.
+	///
+	/// Same as `merkle_treeLeaves`, except each leaf is paired with its index
+	/// in the tree, so the leaves missing from the range don't shift the
+	/// ones after them.
+	///
+	/// Returns the Vec<(u32, [u8; 32])> of the (index, leaf) pairs.
+	#[rpc(name = "merkle_treeLeavesIndexed")]
+	fn tree_leaves_indexed(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<BlockHash>,
+	) -> Result<Vec<(u32, [u8; 32])>>;
+
+	/// Get the checksum of a range of the MerkleTree leaves.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// folds the leaves in the range `from..to` into a single hash in one
+	/// runtime call, starting from the zero scalar and hashing the checksum
+	/// so far with each leaf in order, using the hash function of the tree.
+	/// A client which computes the same over its copy of the leaves, as
+	/// returned by `merkle_treeLeaves`, can tell whether the page is stale or
+	/// corrupted without fetching it again. Optionally, a block hash at which
+	/// the runtime should be queried can be specified.
+	///
+	/// The range is checked and clamped the same as by `merkle_treeLeaves`.
+	/// Returns a `TreeNotFound` error if the tree doesn't exist or isn't
+	/// initialized and a `TreePruned` error if its leaves were pruned.
+	#[rpc(name = "merkle_leavesChecksum")]
+	fn leaves_checksum(&self, tree_id: u32, from: usize, to: usize, at: Option<BlockHash>) -> Result<[u8; 32]>;
+
+	/// Get the nodes at a level of a MerkleTree along with their indices.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the stored nodes at `level` in the range `from..to`,
+	/// where the level `0` holds the leaves and the level `depth` the root, so
+	/// any path can be rebuilt without replaying the leaves. The positions
+	/// without a node are the default nodes of the level, as returned by
+	/// `merkle_defaultNodes`. Optionally, a block hash at which the runtime
+	/// should be queried can be specified.
+	///
+	/// The range is clamped to the filled positions of the level and capped
+	/// to the same size as the leaf ranges. Returns a `TreeNotFound` error if
+	/// the tree doesn't exist, isn't initialized or isn't that deep.
+	#[rpc(name = "merkle_treeNodes")]
+	fn tree_nodes(
+		&self,
+		tree_id: u32,
+		level: u8,
+		from: usize,
+		to: usize,
+		at: Option<BlockHash>,
+	) -> Result<Vec<(u32, [u8; 32])>>;
+
+	/// Get the leaves of several MerkleTrees at once.
+	///
+	/// Takes the `(tree_id, from, to)` ranges to query and returns the leaves
+	/// of each of them, in the same order, as `merkle_treeLeaves` would. All
+	/// the ranges are resolved at the same block.
+	///
+	/// Each range is capped individually and the total number of requested
+	/// leaves is capped across the whole batch. If any of the ranges is
+	/// rejected, e.g. for an unknown or pruned tree, the whole batch is
+	/// rejected with the index of the offending range prefixed to the error
+	/// data, as in `Index2:TreeId5`.
+	#[rpc(name = "merkle_batchTreeLeaves")]
+	fn batch_tree_leaves(&self, ranges: Vec<(u32, usize, usize)>, at: Option<BlockHash>) -> Result<Vec<Vec<[u8; 32]>>>;
+
+	/// Export all the leaves of a MerkleTree.
+	///
+	/// Reads the leaves starting from the index `from` (or `0`) through the
+	/// runtime in chunks of the allowed range size, and returns as many of
+	/// them as fit into the response size ceiling. The ceiling is on the size
+	/// of the leaves serialized as JSON arrays, budgeting
+	/// `MAX_LEAF_JSON_BYTES` per leaf. Optionally, a block hash
+	/// at which the runtime should be queried can be specified.
+	///
+	/// If the ceiling is hit before the last leaf, `next` holds the index to
+	/// continue from. To drain the tree, call again with `from` set to `next`
+	/// and `at` set to the returned `at`, so every chunk is read at the same
+	/// block, until `next` is `None`.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_exportTree")]
+	fn export_tree(&self, tree_id: u32, from: Option<u32>, at: Option<BlockHash>) -> Result<TreeExport<BlockHash>>;
+
+	/// Get The MerkleTree root.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the current root of the merkletree.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns the root as [u8; 32], or a `TreeNotFound` error if the tree
+	/// doesn't exist or isn't initialized yet.
+	#[rpc(name = "merkle_treeRoot")]
+	fn tree_root(&self, tree_id: u32, at: Option<BlockHash>) -> Result<[u8; 32]>;
+
+	/// Get the index of the next leaf of a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the index the next inserted leaf will occupy, which is
+	/// the number of leaves in the tree, in a single storage read without
+	/// fetching the leaves, so it can bound the pages of `merkle_treeLeaves`
+	/// up front. The index of each inserted leaf is confirmed by the
+	/// `LeafAdded` event.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_nextLeafIndex")]
+	fn next_leaf_index(&self, tree_id: u32, at: Option<BlockHash>) -> Result<u32>;
+
+	/// Get a past MerkleTree root.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the root at `root_index` in the root history of the
+	/// tree, where `0` is the current root, `1` the one before it and so on.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns `None` if the root is no longer in the history, or a
+	/// `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_rootAtIndex")]
+	fn root_at_index(&self, tree_id: u32, root_index: u32, at: Option<BlockHash>) -> Result<Option<[u8; 32]>>;
+
+	/// Get the number of past MerkleTree roots retained in the root history.
+	///
+	/// The roots can be queried with `merkle_rootAtIndex` with indices from
+	/// `0` up to this value.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_cachedRootCount")]
+	fn cached_root_count(&self, tree_id: u32, at: Option<BlockHash>) -> Result<u32>;
+
+	/// Check whether a root is known to the tree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// checks whether the root is still retained in the root history of the
+	/// tree, so the clients don't build a proof against a root which has
+	/// already rotated out. Optionally, a block hash at which the runtime
+	/// should be queried can be specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_isKnownRoot")]
+	fn is_known_root(&self, tree_id: u32, root: [u8; 32], at: Option<BlockHash>) -> Result<bool>;
+
+	/// Check whether a nullifier was already used.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// checks whether the nullifier was already used in the tree, so the
+	/// clients don't have to build a proof for a withdrawal which would be
+	/// rejected. Optionally, a block hash at which the runtime should be
+	/// queried can be specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_isNullifierUsed")]
+	fn is_nullifier_used(&self, tree_id: u32, nullifier: [u8; 32], at: Option<BlockHash>) -> Result<bool>;
+
+	/// Get the index of a leaf by its commitment.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to find the index the leaf was last inserted at, so the
+	/// clients can confirm their deposit landed without scanning the leaves.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns `None` if the tree doesn't hold the leaf, a `TreeNotFound` error
+	/// if the tree doesn't exist, or a `TreePruned` error if its leaves were
+	/// pruned.
+	#[rpc(name = "merkle_leafIndexOf")]
+	fn leaf_index_of(&self, tree_id: u32, leaf: [u8; 32], at: Option<BlockHash>) -> Result<Option<u32>>;
+
+	/// Get the membership proof of a leaf.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to build the path from the leaf at `leaf_index` up to the
+	/// root. Optionally, a block hash at which the runtime should be queried
+	/// can be specified.
+	///
+	/// The proof verifies against the root returned by `merkle_treeRoot` at
+	/// the same block. The work of building it grows with the depth of the
+	/// tree, so the trees deeper than the configured limit are rejected
+	/// before the runtime is called, as are the leaves past the end of the
+	/// tree.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist, a
+	/// `TreePruned` error if its leaves and nodes were pruned, a
+	/// `ProofTooExpensive` error if the tree is too deep, a `LeafNotFound`
+	/// error if the leaf isn't in the tree, or a `MigrationPending` error if
+	/// the nodes of the tree aren't migrated yet after a runtime upgrade.
+	#[rpc(name = "merkle_membershipProof")]
+	fn membership_proof(&self, tree_id: u32, leaf_index: u32, at: Option<BlockHash>) -> Result<MembershipProof>;
+
+	/// Get the membership proof of a leaf from the off-chain cache.
+	///
+	/// Builds the path from the nodes cached by the off-chain worker of the
+	/// node, without calling into the runtime. The proof verifies against the
+	/// returned root, the root of the tree when the worker last cached it. If
+	/// the nodes aren't cached or don't include the leaf yet, the proof is
+	/// built by the runtime at the best block instead, within the same
+	/// limits as `merkle_membershipProof`.
+	///
+	/// Returns a `LeafNotFound` error if the leaf isn't in the tree, a
+	/// `ProofTooExpensive` error if the tree is too deep to build the proof
+	/// in the runtime, or a `MigrationPending` error if the nodes of the tree
+	/// aren't migrated yet after a runtime upgrade.
+	#[rpc(name = "merkle_offchainProof")]
+	fn offchain_proof(&self, tree_id: u32, leaf_index: u32) -> Result<RootedMembershipProof>;
+
+	/// Rebuild the membership proof of a leaf from the leaves of the tree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// recomputes the path of the leaf from the stored leaves alone, without
+	/// the stored nodes or the off-chain cache, along with the root the leaves
+	/// hash to. It's much slower than `merkle_membershipProof`, but always
+	/// right, so the proofs built from the nodes can be checked against it.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Takes the same limits and returns the same errors as
+	/// `merkle_membershipProof`. Every leaf of the tree is hashed, so a
+	/// `ProofTooExpensive` error is also returned for the trees with more
+	/// leaves than the configured limit.
+	#[rpc(name = "merkle_rebuildPath")]
+	fn rebuild_path(&self, tree_id: u32, leaf_index: u32, at: Option<BlockHash>) -> Result<RootedMembershipProof>;
+
+	/// Get the default nodes of a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the roots of the empty subtrees of each height, from
+	/// the empty leaf at index `0` up to the root of the empty tree at index
+	/// `depth`. The chain uses them for the unfilled positions of the tree,
+	/// so a partially filled tree can be rebuilt off-chain with the same
+	/// root. They depend on the hash function of the tree.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist or isn't
+	/// initialized yet.
+	#[rpc(name = "merkle_defaultNodes")]
+	fn default_nodes(&self, tree_id: u32, at: Option<BlockHash>) -> Result<Vec<[u8; 32]>>;
+
+	/// Get the metadata of a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the depth, the number of leaves, the hash function,
+	/// the size of the root history and the denomination of the tree and the
+	/// asset it's in, along with whether it is stopped or pruned and the
+	/// number of leaves required before the withdrawals are accepted.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_treeMetadata")]
+	fn tree_metadata(&self, tree_id: u32, at: Option<BlockHash>) -> Result<TreeMetadata>;
+
+	/// Get the id of a MerkleTree by its name.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to find the tree which was given the name when it was created,
+	/// so the same tree can be referred to by name on every chain.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if no tree has that name.
+	#[rpc(name = "merkle_treeIdByName")]
+	fn tree_id_by_name(&self, name: String, at: Option<BlockHash>) -> Result<u32>;
+
+	/// Get the ids of the MerkleTrees of an asset.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to list the trees whose deposits are made in the asset with
+	/// `asset_id`, in ascending order, so the clients can find the trees of
+	/// each denomination of a token. The trees in the native currency have
+	/// no asset and aren't listed.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	#[rpc(name = "merkle_treesByAsset")]
+	fn trees_by_asset(&self, asset_id: u64, at: Option<BlockHash>) -> Result<Vec<u32>>;
+
+	/// Get the chain of MerkleTrees a tree rolled over into.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to follow the successors of the tree, each created when the
+	/// one before it was full, so the clients can find every leaf inserted
+	/// through the tree. The chain starts with the tree itself and ends with
+	/// the tree the new leaves go to.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_treeChain")]
+	fn tree_chain(&self, tree_id: u32, at: Option<BlockHash>) -> Result<Vec<u32>>;
+
+	/// Get the manager of a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the manager account of the tree, along with whether
+	/// the manager is required for the guarded calls.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_treeManager")]
+	fn tree_manager(&self, tree_id: u32, at: Option<BlockHash>) -> Result<TreeManager<AccountId>>;
+
+	/// Get the verifying key of a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the raw verifying key the proofs of the tree are
+	/// checked against, along with its hash, so the clients can cache the
+	/// parameters and tell when `set_verifying_key` replaced them. The order
+	/// the tree binds the public inputs of the proofs in is returned too, so
+	/// the clients arrange them the same.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist or isn't
+	/// initialized.
+	#[rpc(name = "merkle_verifierKey")]
+	fn verifier_key(&self, tree_id: u32, at: Option<BlockHash>) -> Result<VerifierKey>;
+
+	/// Verify a batch of zero-knowledge membership proofs of a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to verify each of the SCALE-encoded `ZkMembershipProof`
+	/// proofs, the same type `verify_zk_membership` takes, against the cached
+	/// roots and the used nullifiers of the tree, so relayers can filter out
+	/// the invalid proofs before submitting them. The relayer and its fee are
+	/// decoded along with the proof and bound to it, so a proof whose fee was
+	/// changed is invalid, as are the proofs in an unsupported version.
+	/// Returns whether each proof is valid, in the same order.
+	/// Optionally, a block hash at which the runtime should be queried can be
+	/// specified.
+	///
+	/// Returns a `TooManyProofs` error if more than the allowed number of
+	/// proofs is submitted, or a `TreeNotFound` error if the tree doesn't
+	/// exist.
+	#[rpc(name = "merkle_verifyProofs")]
+	fn verify_proofs(&self, tree_id: u32, proofs: Vec<Bytes>, at: Option<BlockHash>) -> Result<Vec<bool>>;
+
+	/// Dry run a withdrawal from a MerkleTree.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// runs the same checks as `verify_zk_membership` on the SCALE-encoded
+	/// `ZkMembershipProof`, through the same code as the withdrawal: the fee,
+	/// the minimum anonymity set, the used nullifiers, the root history and
+	/// the verifying key of the tree. Nothing is written, the nullifier isn't
+	/// used, so the proofs can be debugged before they are submitted.
+	/// Returns the first check which failed, or `valid`. Optionally, a block
+	/// hash at which the runtime should be queried can be specified.
+	///
+	/// Returns a `TreeNotFound` error if the tree doesn't exist.
+	#[rpc(name = "merkle_dryRunWithdraw")]
+	fn dry_run_withdraw(&self, tree_id: u32, proof: Bytes, at: Option<BlockHash>) -> Result<WithdrawCheck>;
+
+	/// Get the methods and limits of the node.
+	///
+	/// Lists the merkle RPC methods the node supports along with the limits
+	/// it was configured with, so the clients can adapt to the node instead
+	/// of failing on unknown methods. The root history size is read from the
+	/// runtime, as it may change with a runtime upgrade. Optionally, a block
+	/// hash at which the runtime should be queried can be specified.
+	#[rpc(name = "merkle_capabilities")]
+	fn capabilities(&self, at: Option<BlockHash>) -> Result<MerkleCapabilities>;
+
+	/// Get the storage version of the merkle pallet.
+	///
+	/// This method calls into a runtime with `Merkle` pallet included and
+	/// attempts to get the current storage version of the pallet, along with
+	/// whether the trees are still being migrated to it after a runtime
+	/// upgrade. The trees are migrated a bounded number of nodes per block,
+	/// so the operators can hold back the clients until no migration is
+	/// pending. Optionally, a block hash at which the runtime should be
+	/// queried can be specified.
+	#[rpc(name = "merkle_storageVersion")]
+	fn storage_version(&self, at: Option<BlockHash>) -> Result<StorageVersion>;
+
+	/// Subscribe to the leaves inserted into or replaced in the trees.
+	///
+	/// Every time a new best block is imported, the leaves it inserted are
+	/// pushed to the subscriber as `leafInserted` events and the ones it
+	/// replaced as `leafUpdated` events, in the order of the events of the
+	/// block, along with the hash of that block. The leaves are read from the
+	/// events of the block with a single runtime call. Optionally, only the
+	/// leaves of the tree with `tree_id` are pushed. If the leaves of a block
+	/// can't be read from the runtime, a `RuntimeError` is pushed instead.
+	///
+	/// When the new best block switches to another fork, a `leafReverted`
+	/// event is pushed first for every leaf inserted by the retracted blocks,
+	/// and a `leafUpdated` event back to the previous leaf for every leaf they
+	/// replaced, newest first, followed by the events of the enacted blocks,
+	/// so the subscribers keep the view of the best chain without resyncing.
+	#[pubsub(subscription = "merkle_leaves", subscribe, name = "merkle_subscribeLeaves")]
+	fn subscribe_leaves(
+		&self,
+		metadata: Self::Metadata,
+		subscriber: Subscriber<LeafEvent<BlockHash>>,
+		tree_id: Option<u32>,
+	);
+
+	/// Unsubscribe from the inserted leaves.
+	#[pubsub(subscription = "merkle_leaves", unsubscribe, name = "merkle_unsubscribeLeaves")]
+	fn unsubscribe_leaves(&self, metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
 }
 
+/// Default number of leaves from which the ranges requested at once are
+/// rejected, so at most 511 leaves can be requested.
+pub const DEFAULT_MAX_LEAF_RANGE: usize = 512;
+
+/// Default maximum number of leaves that can be requested at once across all
+/// the ranges of a `merkle_batchTreeLeaves` call.
+pub const DEFAULT_MAX_BATCH_LEAVES: usize = 4096;
+
+/// Default maximum number of proofs that can be verified in a single
+/// `merkle_verifyProofs` call.
+pub const DEFAULT_MAX_BATCH_PROOFS: usize = 16;
+
+/// Default maximum size in bytes of the leaves returned by a single
+/// `merkle_exportTree` call.
+pub const DEFAULT_MAX_EXPORT_BYTES: usize = 1 << 20;
+
+/// Largest size in bytes of a leaf serialized in the JSON response, as an
+/// array of 32 numbers of up to three digits, with the 31 commas between
+/// them, the brackets and the comma separating it from the next leaf.
+pub const MAX_LEAF_JSON_BYTES: usize = 32 * 3 + 31 + 2 + 1;
+
+/// Default maximum depth of the trees the membership proofs are built for
+/// by the runtime.
+pub const DEFAULT_MAX_PROOF_DEPTH: u8 = 32;
+
+/// Default maximum number of leaves of the trees the membership proofs are
+/// rebuilt for by `merkle_rebuildPath`, which hashes every leaf of the tree.
+pub const DEFAULT_MAX_REBUILD_LEAVES: usize = 1 << 16;
+
+/// Names of the methods of the `MerkleApi`, reported by `merkle_capabilities`.
+pub const MERKLE_METHODS: &[&str] = &[
+	"merkle_treeLeaves",
+	"merkle_treeLeavesAt",
+	"merkle_treeLeavesWithBlock",
+	"merkle_treeLeavesHex",
+	"merkle_treeLeavesEncoded",
+	"merkle_treeLeavesIndexed",
+	"merkle_leavesChecksum",
+	"merkle_treeNodes",
+	"merkle_batchTreeLeaves",
+	"merkle_exportTree",
+	"merkle_treeRoot",
+	"merkle_nextLeafIndex",
+	"merkle_rootAtIndex",
+	"merkle_cachedRootCount",
+	"merkle_isKnownRoot",
+	"merkle_isNullifierUsed",
+	"merkle_leafIndexOf",
+	"merkle_membershipProof",
+	"merkle_offchainProof",
+	"merkle_rebuildPath",
+	"merkle_defaultNodes",
+	"merkle_treeMetadata",
+	"merkle_treeIdByName",
+	"merkle_treesByAsset",
+	"merkle_treeChain",
+	"merkle_treeManager",
+	"merkle_verifierKey",
+	"merkle_verifyProofs",
+	"merkle_dryRunWithdraw",
+	"merkle_capabilities",
+	"merkle_storageVersion",
+	"merkle_subscribeLeaves",
+	"merkle_unsubscribeLeaves",
+];
+
 /// A struct that implements the `MerkleApi`.
 pub struct MerkleClient<C, M> {
 	client: Arc<C>,
+	subscriptions: SubscriptionManager,
+	max_leaf_range: usize,
+	max_batch_leaves: usize,
+	max_batch_proofs: usize,
+	max_export_bytes: usize,
+	max_proof_depth: u8,
+	max_rebuild_leaves: usize,
+	offchain: Option<OffchainReader>,
 	_marker: std::marker::PhantomData<M>,
 }
 
+/// Reads the values of the persistent off-chain storage of the node.
+type OffchainReader = Arc<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
 impl<C, M> MerkleClient<C, M> {
-	/// Create new `Merkle` instance with the given reference to the client.
-	pub fn new(client: Arc<C>) -> Self {
+	/// Create new `Merkle` instance with the given reference to the client
+	/// and the manager driving the subscriptions.
+	pub fn new(client: Arc<C>, subscriptions: SubscriptionManager) -> Self {
+		Self::with_config(
+			client,
+			subscriptions,
+			DEFAULT_MAX_LEAF_RANGE,
+			DEFAULT_MAX_BATCH_LEAVES,
+			DEFAULT_MAX_BATCH_PROOFS,
+			DEFAULT_MAX_EXPORT_BYTES,
+			DEFAULT_MAX_PROOF_DEPTH,
+			DEFAULT_MAX_REBUILD_LEAVES,
+		)
+	}
+
+	/// Create new `Merkle` instance which allows requesting fewer than
+	/// `max_leaf_range` leaves at once, at most `max_batch_leaves` leaves in a
+	/// single batch, verifying at most `max_batch_proofs` proofs at once,
+	/// exporting at most `max_export_bytes` bytes of leaves at once,
+	/// building the membership proofs of the trees at most `max_proof_depth`
+	/// deep and rebuilding them for the trees of at most `max_rebuild_leaves`
+	/// leaves.
+	pub fn with_config(
+		client: Arc<C>,
+		subscriptions: SubscriptionManager,
+		max_leaf_range: usize,
+		max_batch_leaves: usize,
+		max_batch_proofs: usize,
+		max_export_bytes: usize,
+		max_proof_depth: u8,
+		max_rebuild_leaves: usize,
+	) -> Self {
 		Self {
 			client,
+			subscriptions,
+			max_leaf_range,
+			max_batch_leaves,
+			max_batch_proofs,
+			max_export_bytes,
+			max_proof_depth,
+			max_rebuild_leaves,
+			offchain: None,
 			_marker: Default::default(),
 		}
 	}
+
+	/// Serves `merkle_offchainProof` from the nodes cached by the
+	/// off-chain worker in the given storage. Without it, the proofs are
+	/// always built by the runtime.
+	pub fn with_offchain_storage<S: OffchainStorage + 'static>(mut self, storage: S) -> Self {
+		self.offchain = Some(Arc::new(move |key: &[u8]| storage.get(STORAGE_PREFIX, key)));
+		self
+	}
+
+	/// Builds the membership proof of the leaf from the nodes of the tree
+	/// cached off-chain, along with the root they hash to, if any.
+	fn cached_proof(&self, tree_id: u32, leaf_index: u32) -> Option<(ScalarData, Vec<(bool, ScalarData)>)> {
+		let read = self.offchain.as_ref()?;
+		let read_tree = || CachedTree::decode(&mut &read(&tree_key(&tree_id))?[..]).ok();
+		let tree = read_tree()?;
+		let path = tree.membership_proof(leaf_index, |level, index| {
+			ScalarData::decode(&mut &read(&node_key(&tree_id, level, index))?[..]).ok()
+		})?;
+		// the worker clears the state of the tree before writing its nodes, so
+		// the nodes read are the ones of the root if the state didn't change
+		if read_tree()? != tree {
+			return None;
+		}
+		Some((tree.root, path))
+	}
 }
 
-impl<C, Block> MerkleApi<<Block as BlockT>::Hash> for MerkleClient<C, Block>
+impl<C, Block> MerkleClient<C, Block>
 where
 	Block: BlockT,
-	C: HeaderBackend<Block> + ProvideRuntimeApi<Block> + Send + Sync + 'static,
+	C: HeaderBackend<Block> + ProvideRuntimeApi<Block>,
 	C::Api: MerkleRuntimeApi<Block>,
 {
+	/// Resolves the number of a finalized block to its hash. The blocks past
+	/// the finalized head are rejected, rather than read at the best block.
+	fn finalized_hash(&self, block_number: u32) -> Result<<Block as BlockT>::Hash> {
+		let unknown_block = || MerkleRpcError::UnknownBlock(format!("BlockNumber{}", block_number));
+		let number = NumberFor::<Block>::from(block_number);
+		if number > self.client.info().finalized_number {
+			return Err(unknown_block().into());
+		}
+		self.client
+			.hash(number)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| unknown_block().into())
+	}
+
+	/// Checks that the tree exists at the given block, so the methods don't
+	/// answer for an unknown tree as if it were an empty one, and returns its
+	/// metadata.
+	fn ensure_tree_exists(&self, at: &BlockId<Block>, tree_id: u32) -> Result<merkle::TreeMetadata> {
+		self.client
+			.runtime_api()
+			.get_tree_metadata(at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)).into())
+	}
+
+	/// Checks that the leaves and the nodes of the tree are still stored at
+	/// the given block, so the methods don't answer for a pruned tree as if
+	/// it were an empty one.
+	fn ensure_tree_not_pruned(&self, at: &BlockId<Block>, tree_id: u32) -> Result<()> {
+		if self.ensure_tree_exists(at, tree_id)?.is_pruned {
+			return Err(MerkleRpcError::TreePruned(format!("TreeId{}", tree_id)).into());
+		}
+		Ok(())
+	}
+
+	/// Checks that the membership proof of the leaf can be built in the
+	/// runtime at the given block, so a request for a pruned or too deep
+	/// tree, or for a leaf past its end, doesn't make the runtime load the
+	/// hasher of the tree and build the path. Returns the metadata of the
+	/// tree.
+	fn ensure_leaf_provable(&self, at: &BlockId<Block>, tree_id: u32, leaf_index: u32) -> Result<merkle::TreeMetadata> {
+		let metadata = self.ensure_tree_exists(at, tree_id)?;
+		if metadata.is_pruned {
+			return Err(MerkleRpcError::TreePruned(format!("TreeId{}", tree_id)).into());
+		}
+		if metadata.depth > self.max_proof_depth {
+			return Err(MerkleRpcError::ProofTooExpensive(format!("MaxDepth{}", self.max_proof_depth)).into());
+		}
+		if leaf_index >= metadata.leaf_count {
+			return Err(leaf_not_found(tree_id, leaf_index).into());
+		}
+		Ok(metadata)
+	}
+
+	/// Builds the membership proof of the leaf in the runtime at the given
+	/// block, once the tree and the leaf are checked. The proof of a tree
+	/// whose nodes aren't migrated yet is refused, its siblings would be
+	/// taken for empty subtrees.
+	fn runtime_membership_proof(
+		&self,
+		at: &BlockId<Block>,
+		tree_id: u32,
+		leaf_index: u32,
+	) -> Result<Vec<(bool, ScalarData)>> {
+		if !self.ensure_leaf_provable(at, tree_id, leaf_index)?.is_migrated {
+			return Err(MerkleRpcError::MigrationPending(format!("TreeId{}", tree_id)).into());
+		}
+		self.client
+			.runtime_api()
+			.get_membership_proof(at, tree_id, leaf_index)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| leaf_not_found(tree_id, leaf_index).into())
+	}
+
+	/// Collects the leaves of the tree in the range `from..to` along with
+	/// their indices, skipping the ones which are missing.
+	///
+	/// The range is clamped to the number of leaves in the tree, an empty
+	/// range returns no leaves and a reversed range is rejected, as is an
+	/// unknown or pruned tree.
+	fn indexed_leaves(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Vec<(u32, [u8; 32])>> {
+		let (start, end) = range_bounds(from, to)?;
+		if to - from >= self.max_leaf_range {
+			return Err(MerkleRpcError::TooManyLeaves(format!("MaxRange{}", self.max_leaf_range)).into());
+		}
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_not_pruned(&at, tree_id)?;
+		if from == to {
+			return Ok(Vec::new());
+		}
+		// the whole range is read in a single runtime call, which never walks
+		// past the last inserted leaf.
+		let leaves = api
+			.get_leaves_range(&at, tree_id, start, end)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.into_iter()
+			.zip(start..)
+			.filter_map(|(leaf, i)| leaf.map(|leaf| (i, leaf.0.to_bytes())))
+			.collect();
+		Ok(leaves)
+	}
+}
+
+/// Converts the bounds of the requested range into the leaf indices of the
+/// runtime. A reversed range is rejected, as are the bounds past `u32::MAX`,
+/// rather than truncated.
+fn range_bounds(from: usize, to: usize) -> std::result::Result<(u32, u32), MerkleRpcError> {
+	if from > to {
+		return Err(MerkleRpcError::InvalidRange(format!("from({}) > to({})", from, to)));
+	}
+	let index =
+		|bound: usize| u32::try_from(bound).map_err(|_| MerkleRpcError::InvalidRange(format!("{} > u32::MAX", bound)));
+	Ok((index(from)?, index(to)?))
+}
+
+/// The error for the leaf which isn't in the tree.
+fn leaf_not_found(tree_id: u32, leaf_index: u32) -> MerkleRpcError {
+	MerkleRpcError::LeafNotFound(format!("TreeId{}LeafIndex{}", tree_id, leaf_index))
+}
+
+/// Prefixes the data of the error with the index of the range of the batch
+/// it was returned for, e.g. `Index2:TreeId5`.
+fn in_batch(index: usize, mut e: Error) -> Error {
+	let data = e.data.as_ref().and_then(|data| data.as_str()).unwrap_or_default();
+	e.data = Some(format!("Index{}:{}", index, data).into());
+	e
+}
+
+/// Turns the path of a membership proof into the siblings and their
+/// directions, as returned to the clients.
+fn split_path(path: Vec<(bool, ScalarData)>) -> (Vec<bool>, Vec<[u8; 32]>) {
+	path.into_iter()
+		.map(|(is_right, node)| (is_right, node.0.to_bytes()))
+		.unzip()
+}
+
+/// Collects the leaves inserted into or replaced in the trees by the block
+/// `hash`, in the order of its events. The runtime reads the events of the
+/// block once, rather than the leaves of every tree.
+fn block_leaves<C, Block>(
+	client: &C,
+	hash: <Block as BlockT>::Hash,
+	tree_id: Option<u32>,
+) -> Result<Vec<LeafChange<u32>>>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi<Block>,
+	C::Api: MerkleRuntimeApi<Block>,
+{
+	let leaves = client
+		.runtime_api()
+		.get_block_leaves(&BlockId::hash(hash))
+		.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+	Ok(leaves
+		.into_iter()
+		.filter(|leaf| tree_id.map_or(true, |tree_id| leaf.tree_id == tree_id))
+		.collect())
+}
+
+/// Collects the leaf events of the new best block: the leaves of the blocks
+/// retracted by a reorg are reverted, newest first, then the leaves of the
+/// enacted blocks and of the new best block itself are inserted or updated,
+/// in block order. The leaves replaced by a retracted block are updated back
+/// to the leaves of its parent. Fails if any of the runtime calls fails, so
+/// the leaves of a block are never reported partially.
+fn leaf_events<C, Block>(
+	client: &C,
+	notification: &BlockImportNotification<Block>,
+	tree_id: Option<u32>,
+) -> Result<Vec<LeafEvent<<Block as BlockT>::Hash>>>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi<Block>,
+	C::Api: MerkleRuntimeApi<Block>,
+{
+	let mut events = Vec::new();
+	let mut enacted = Vec::new();
+	if let Some(route) = &notification.tree_route {
+		let common = route.common_block().hash;
+		// the retracted blocks go from the old best block down to the common
+		// ancestor
+		let retracted = route.retracted();
+		for (i, block) in retracted.iter().enumerate() {
+			let parent = retracted.get(i + 1).map_or(common, |parent| parent.hash);
+			for change in block_leaves::<_, Block>(client, block.hash, tree_id)?.into_iter().rev() {
+				if !change.replaced {
+					events.push(LeafEvent::LeafReverted(RevertedLeaf {
+						tree_id: change.tree_id,
+						leaf_index: change.leaf_index,
+						block_hash: block.hash,
+					}));
+					continue;
+				}
+				let range = change.leaf_index..change.leaf_index + 1;
+				let previous = client
+					.runtime_api()
+					.get_leaves_range(&BlockId::hash(parent), change.tree_id, range.start, range.end)
+					.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+				if let Some(Some(leaf)) = previous.into_iter().next() {
+					events.push(LeafEvent::LeafUpdated(LeafNotification {
+						tree_id: change.tree_id,
+						leaf_index: change.leaf_index,
+						leaf: leaf.0.to_bytes(),
+						block_hash: parent,
+					}));
+				}
+			}
+		}
+		enacted.extend(route.enacted().iter().map(|block| block.hash));
+	}
+	enacted.push(notification.hash);
+	for hash in enacted {
+		events.extend(
+			block_leaves::<_, Block>(client, hash, tree_id)?
+				.into_iter()
+				.map(|change| {
+					let leaf = LeafNotification {
+						tree_id: change.tree_id,
+						leaf_index: change.leaf_index,
+						leaf: change.leaf.0.to_bytes(),
+						block_hash: hash,
+					};
+					match change.replaced {
+						true => LeafEvent::LeafUpdated(leaf),
+						false => LeafEvent::LeafInserted(leaf),
+					}
+				}),
+		);
+	}
+	Ok(events)
+}
+
+impl<C, Block, AccountId> MerkleApi<<Block as BlockT>::Hash, AccountId> for MerkleClient<C, Block>
+where
+	Block: BlockT,
+	AccountId: Codec + Send + Sync + 'static,
+	C: HeaderBackend<Block> + BlockchainEvents<Block> + ProvideRuntimeApi<Block> + Send + Sync + 'static,
+	C::Api: MerkleRuntimeApi<Block>,
+	C::Api: MerkleManagerRuntimeApi<Block, AccountId>,
+{
+	type Metadata = sc_rpc::Metadata;
+
 	fn tree_leaves(
 		&self,
 		tree_id: u32,
@@ -54,22 +1275,498 @@ where
 		to: usize,
 		at: Option<<Block as BlockT>::Hash>,
 	) -> Result<Vec<[u8; 32]>> {
+		let leaves = self
+			.indexed_leaves(tree_id, from, to, at)?
+			.into_iter()
+			.map(|(_, leaf)| leaf)
+			.collect();
+		Ok(leaves)
+	}
+
+	fn tree_leaves_at(&self, tree_id: u32, from: usize, to: usize, block_number: u32) -> Result<Vec<[u8; 32]>> {
+		let hash = self.finalized_hash(block_number)?;
+		self.tree_leaves(tree_id, from, to, Some(hash))
+	}
+
+	fn tree_leaves_with_block(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<LeavesWithBlock<<Block as BlockT>::Hash>> {
+		// resolved once, so the leaves and the hash are of the same block
+		let block_hash = at.unwrap_or_else(|| self.client.info().best_hash);
+		let leaves = self.tree_leaves(tree_id, from, to, Some(block_hash))?;
+		Ok(LeavesWithBlock { block_hash, leaves })
+	}
+
+	fn tree_leaves_hex(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Vec<String>> {
+		let leaves = self
+			.indexed_leaves(tree_id, from, to, at)?
+			.into_iter()
+			.map(|(_, leaf)| to_hex(&leaf, false))
+			.collect();
+		Ok(leaves)
+	}
+
+	fn tree_leaves_encoded(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Bytes> {
+		let leaves: Vec<[u8; 32]> = self
+			.indexed_leaves(tree_id, from, to, at)?
+			.into_iter()
+			.map(|(_, leaf)| leaf)
+			.collect();
+		Ok(Bytes(leaves.encode()))
+	}
+
+	fn tree_leaves_indexed(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Vec<(u32, [u8; 32])>> {
+		self.indexed_leaves(tree_id, from, to, at)
+	}
+
+	fn leaves_checksum(
+		&self,
+		tree_id: u32,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<[u8; 32]> {
+		let (start, end) = range_bounds(from, to)?;
+		if to - from >= self.max_leaf_range {
+			return Err(MerkleRpcError::TooManyLeaves(format!("MaxRange{}", self.max_leaf_range)).into());
+		}
 		let api = self.client.runtime_api();
 		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
-		if to - from >= 512 {
-			return Err(Error {
-				code: ErrorCode::ServerError(1512), // Too many leaves
-				message: "TooManyLeaves".into(),
-				data: Some("MaxRange512".into()),
-			});
+		self.ensure_tree_not_pruned(&at, tree_id)?;
+		let checksum = api
+			.get_leaves_checksum(&at, tree_id, start, end)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+		Ok(checksum.0.to_bytes())
+	}
+
+	fn tree_nodes(
+		&self,
+		tree_id: u32,
+		level: u8,
+		from: usize,
+		to: usize,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Vec<(u32, [u8; 32])>> {
+		let (start, end) = range_bounds(from, to)?;
+		if to - from >= self.max_leaf_range {
+			return Err(MerkleRpcError::TooManyNodes(format!("MaxRange{}", self.max_leaf_range)).into());
 		}
-		let leaves = (from..to)
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_not_pruned(&at, tree_id)?;
+		let nodes = api
+			.get_tree_nodes(&at, tree_id, level, start, end)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}Level{}", tree_id, level)))?
 			.into_iter()
-			.map(|i| api.get_leaf(&at, tree_id, i as u32)) // Result<Option<ScalarData>>
-			.flatten() // Option<ScalarData>
-			.flatten() // ScalarData
-			.map(|v| v.0.to_bytes()) // [u8; 32]
+			.zip(start..)
+			.filter_map(|(node, i)| node.map(|node| (i, node.0.to_bytes())))
 			.collect();
-		Ok(leaves)
+		Ok(nodes)
+	}
+
+	fn batch_tree_leaves(
+		&self,
+		ranges: Vec<(u32, usize, usize)>,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Vec<Vec<[u8; 32]>>> {
+		let total = ranges.iter().fold(0usize, |total, (_, from, to)| {
+			total.saturating_add(to.saturating_sub(*from))
+		});
+		if total > self.max_batch_leaves {
+			return Err(MerkleRpcError::TooManyLeaves(format!("MaxBatch{}", self.max_batch_leaves)).into());
+		}
+		let hash = at.unwrap_or_else(|| self.client.info().best_hash);
+		ranges
+			.into_iter()
+			.enumerate()
+			.map(|(index, (tree_id, from, to))| -> Result<Vec<[u8; 32]>> {
+				let leaves = self
+					.indexed_leaves(tree_id, from, to, Some(hash))
+					.map_err(|e| in_batch(index, e))?
+					.into_iter()
+					.map(|(_, leaf)| leaf)
+					.collect();
+				Ok(leaves)
+			})
+			.collect()
+	}
+
+	fn export_tree(
+		&self,
+		tree_id: u32,
+		from: Option<u32>,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<TreeExport<<Block as BlockT>::Hash>> {
+		let api = self.client.runtime_api();
+		let hash = at.unwrap_or_else(|| self.client.info().best_hash);
+		let leaf_count = api
+			.get_leaf_count(&BlockId::hash(hash), tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))? as usize;
+		let from = (from.unwrap_or_default() as usize).min(leaf_count);
+		// the budget is the serialized size of the leaves, always return at
+		// least one of them so the export makes progress.
+		let max_leaves = (self.max_export_bytes / MAX_LEAF_JSON_BYTES).max(1);
+		let to = leaf_count.min(from.saturating_add(max_leaves));
+		// the ranges of `max_leaf_range` leaves are rejected, so the chunks are one
+		// leaf shorter
+		let chunk_size = self.max_leaf_range.saturating_sub(1).max(1);
+		let mut leaves = Vec::with_capacity(to - from);
+		let mut cursor = from;
+		while cursor < to {
+			let chunk_end = to.min(cursor + chunk_size);
+			let chunk = self.indexed_leaves(tree_id, cursor, chunk_end, Some(hash))?;
+			leaves.extend(chunk.into_iter().map(|(_, leaf)| leaf));
+			cursor = chunk_end;
+		}
+		Ok(TreeExport {
+			leaves,
+			next: if to < leaf_count { Some(to as u32) } else { None },
+			at: hash,
+		})
+	}
+
+	fn tree_root(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<[u8; 32]> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let root = api
+			.get_root(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+		Ok(root.0.to_bytes())
+	}
+
+	fn next_leaf_index(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<u32> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		api.get_leaf_count(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)).into())
+	}
+
+	fn root_at_index(
+		&self,
+		tree_id: u32,
+		root_index: u32,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Option<[u8; 32]>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_exists(&at, tree_id)?;
+		let root = api
+			.get_root_at_index(&at, tree_id, root_index)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+		Ok(root.map(|root| root.0.to_bytes()))
+	}
+
+	fn cached_root_count(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<u32> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_exists(&at, tree_id)?;
+		api.get_cached_root_count(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn is_known_root(&self, tree_id: u32, root: [u8; 32], at: Option<<Block as BlockT>::Hash>) -> Result<bool> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_exists(&at, tree_id)?;
+		api.is_known_root(&at, tree_id, ScalarData::from(root))
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn is_nullifier_used(
+		&self,
+		tree_id: u32,
+		nullifier: [u8; 32],
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<bool> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_exists(&at, tree_id)?;
+		api.is_nullifier_used(&at, tree_id, ScalarData::from(nullifier))
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn leaf_index_of(&self, tree_id: u32, leaf: [u8; 32], at: Option<<Block as BlockT>::Hash>) -> Result<Option<u32>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_not_pruned(&at, tree_id)?;
+		api.get_leaf_index(&at, tree_id, ScalarData::from(leaf))
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn membership_proof(
+		&self,
+		tree_id: u32,
+		leaf_index: u32,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<MembershipProof> {
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let path = self.runtime_membership_proof(&at, tree_id, leaf_index)?;
+		let (directions, path) = split_path(path);
+		Ok(MembershipProof { path, directions })
+	}
+
+	fn offchain_proof(&self, tree_id: u32, leaf_index: u32) -> Result<RootedMembershipProof> {
+		let (root, path) = match self.cached_proof(tree_id, leaf_index) {
+			Some(cached) => cached,
+			// the cache is cold, the runtime builds the proof at the best block
+			None => {
+				let at = BlockId::hash(self.client.info().best_hash);
+				let path = self.runtime_membership_proof(&at, tree_id, leaf_index)?;
+				let root = self
+					.client
+					.runtime_api()
+					.get_root(&at, tree_id)
+					.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+					.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+				(root, path)
+			}
+		};
+		let (directions, path) = split_path(path);
+		Ok(RootedMembershipProof {
+			root: root.0.to_bytes(),
+			path,
+			directions,
+		})
+	}
+
+	fn rebuild_path(
+		&self,
+		tree_id: u32,
+		leaf_index: u32,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<RootedMembershipProof> {
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		if self.ensure_leaf_provable(&at, tree_id, leaf_index)?.leaf_count as usize > self.max_rebuild_leaves {
+			return Err(
+				MerkleRpcError::ProofTooExpensive(format!("MaxRebuildLeaves{}", self.max_rebuild_leaves)).into(),
+			);
+		}
+		let (path, root) = self
+			.client
+			.runtime_api()
+			.rebuild_path(&at, tree_id, leaf_index)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| leaf_not_found(tree_id, leaf_index))?;
+		let (directions, path) = split_path(path);
+		Ok(RootedMembershipProof {
+			root: root.0.to_bytes(),
+			path,
+			directions,
+		})
+	}
+
+	fn default_nodes(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<Vec<[u8; 32]>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let nodes = api
+			.get_default_nodes(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+		Ok(nodes.into_iter().map(|node| node.to_bytes()).collect())
+	}
+
+	fn tree_metadata(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<TreeMetadata> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let metadata = api
+			.get_tree_metadata(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+		Ok(TreeMetadata {
+			depth: metadata.depth,
+			leaf_count: metadata.leaf_count,
+			is_stopped: metadata.is_stopped,
+			hasher: format!("{:?}", metadata.hasher),
+			root_history_size: metadata.root_history_size,
+			is_pruned: metadata.is_pruned,
+			denomination: metadata.denomination,
+			commitment_version: metadata.commitment_version,
+			is_append_only: metadata.is_append_only,
+			leaves_remaining: metadata.leaves_remaining,
+			min_anonymity_set: metadata.min_anonymity_set,
+			hash_leaf_on_insert: metadata.hash_leaf_on_insert,
+			asset_id: metadata.asset_id,
+			on_full: metadata.on_full.into(),
+			successor_tree_id: metadata.successor_tree_id,
+			is_migrated: metadata.is_migrated,
+		})
+	}
+
+	fn tree_id_by_name(&self, name: String, at: Option<<Block as BlockT>::Hash>) -> Result<u32> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		api.get_tree_id_by_name(&at, name.clone().into_bytes())
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("Name{}", name)).into())
+	}
+
+	fn trees_by_asset(&self, asset_id: u64, at: Option<<Block as BlockT>::Hash>) -> Result<Vec<u32>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		api.get_trees_by_asset(&at, asset_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn tree_chain(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<Vec<u32>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let chain = api
+			.get_tree_chain(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+		if chain.is_empty() {
+			return Err(MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)).into());
+		}
+		Ok(chain)
+	}
+
+	fn tree_manager(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<TreeManager<AccountId>> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let (account_id, required) = api
+			.get_manager(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+		Ok(TreeManager { account_id, required })
+	}
+
+	fn verifier_key(&self, tree_id: u32, at: Option<<Block as BlockT>::Hash>) -> Result<VerifierKey> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let key = api
+			.get_verifying_key(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?
+			.ok_or_else(|| MerkleRpcError::TreeNotFound(format!("TreeId{}", tree_id)))?;
+		let public_input_layout = api
+			.get_public_input_layout(&at, tree_id)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+		Ok(VerifierKey {
+			hash: blake2_256(&key),
+			key: Bytes(key),
+			public_input_layout: public_input_layout.into_iter().map(Into::into).collect(),
+		})
+	}
+
+	fn capabilities(&self, at: Option<<Block as BlockT>::Hash>) -> Result<MerkleCapabilities> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let root_history_size = api
+			.get_root_history_size(&at)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+		Ok(MerkleCapabilities {
+			methods: MERKLE_METHODS.iter().map(|method| method.to_string()).collect(),
+			max_leaf_range: self.max_leaf_range as u32,
+			max_batch_leaves: self.max_batch_leaves as u32,
+			max_batch_proofs: self.max_batch_proofs as u32,
+			max_export_bytes: self.max_export_bytes as u32,
+			max_proof_depth: self.max_proof_depth,
+			max_rebuild_leaves: self.max_rebuild_leaves as u32,
+			root_history_size,
+		})
+	}
+
+	fn storage_version(&self, at: Option<<Block as BlockT>::Hash>) -> Result<StorageVersion> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		let status = api
+			.get_storage_status(&at)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)))?;
+		Ok(StorageVersion {
+			version: format!("{:?}", status.version),
+			migration_pending: status.migration_pending,
+		})
+	}
+
+	fn verify_proofs(
+		&self,
+		tree_id: u32,
+		proofs: Vec<Bytes>,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<Vec<bool>> {
+		if proofs.len() > self.max_batch_proofs {
+			return Err(MerkleRpcError::TooManyProofs(format!("MaxBatch{}", self.max_batch_proofs)).into());
+		}
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_exists(&at, tree_id)?;
+		let proofs = proofs.into_iter().map(|proof| proof.0).collect();
+		api.verify_proofs_batch(&at, tree_id, proofs)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn dry_run_withdraw(
+		&self,
+		tree_id: u32,
+		proof: Bytes,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> Result<WithdrawCheck> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+		self.ensure_tree_exists(&at, tree_id)?;
+		api.dry_run_withdraw(&at, tree_id, proof.0)
+			.map(Into::into)
+			.map_err(|e| MerkleRpcError::RuntimeError(format!("{:?}", e)).into())
+	}
+
+	fn subscribe_leaves(
+		&self,
+		_metadata: Self::Metadata,
+		subscriber: Subscriber<LeafEvent<<Block as BlockT>::Hash>>,
+		tree_id: Option<u32>,
+	) {
+		let client = self.client.clone();
+		let leaves = self
+			.client
+			.import_notification_stream()
+			.filter(|notification| future::ready(notification.is_new_best))
+			.map(move |notification| {
+				// a failed runtime call is pushed to the subscriber, rather than
+				// silently skipping the leaves of the block
+				let events = match leaf_events::<_, Block>(&*client, &notification, tree_id) {
+					Ok(events) => events.into_iter().map(Ok).collect(),
+					Err(e) => vec![Err(e)],
+				};
+				stream::iter(events)
+			})
+			.flatten()
+			.map(Ok::<_, ()>)
+			.compat();
+
+		self.subscriptions.add(subscriber, |sink| {
+			sink.sink_map_err(|e| warn!("Error sending notifications: {:?}", e))
+				.send_all(leaves)
+				// we ignore the resulting Stream (if the first stream is over we are unsubscribed)
+				.map(|_| ())
+		});
+	}
+
+	fn unsubscribe_leaves(&self, _metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+		Ok(self.subscriptions.cancel(id))
 	}
 }
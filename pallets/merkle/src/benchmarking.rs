@@ -13,8 +13,13 @@ const VERIFY_DEPTH: u8 = 10;
 
 fn setup_tree<T: Config>(caller: T::AccountId, depth: u32) {
 	let manager_required = true;
-	<Merkle<T> as Tree<T::AccountId, T::BlockNumber, T::TreeId>>::create_tree(caller, manager_required, depth as u8)
-		.unwrap();
+	let params = TreeParams {
+		manager_required,
+		depth: Some(depth as u8),
+		hasher: Some(HashFunction::PoseidonDefault),
+		..Default::default()
+	};
+	<Merkle<T> as Tree<T>>::create_tree(caller, params).unwrap();
 }
 
 fn get_proof(depth: u32) -> Vec<(bool, ScalarData)> {
@@ -35,7 +40,8 @@ benchmarks! {
 		// and calculates the weights on the run
 		let d in 1 .. MAX_DEPTH as u32;
 		let caller = whitelisted_caller();
-	}: _(RawOrigin::Signed(caller), false, Some(d as u8))
+		let params = TreeParams { depth: Some(d as u8), ..Default::default() };
+	}: _(RawOrigin::Signed(caller), params)
 	verify {
 		let next_id: T::TreeId = Merkle::<T>::next_tree_id();
 		let curr_id = next_id - 1u32.into();
@@ -86,6 +92,50 @@ benchmarks! {
 		assert!(stopped);
 	}
 
+	stop_tree {
+		let caller: T::AccountId = whitelisted_caller();
+		setup_tree::<T>(caller.clone(), 32);
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into())
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		assert!(Stopped::<T>::get(tree_id));
+	}
+
+	resume_tree {
+		let caller: T::AccountId = whitelisted_caller();
+		setup_tree::<T>(caller.clone(), 32);
+		Merkle::<T>::stop_tree(RawOrigin::Signed(caller.clone()).into(), 0u32.into()).unwrap();
+	}:
+	// Resuming also checks that the tree isn't pruned
+	_(RawOrigin::Signed(caller.clone()), 0u32.into())
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		assert!(!Stopped::<T>::get(tree_id));
+	}
+
+	set_on_full {
+		let caller: T::AccountId = whitelisted_caller();
+		setup_tree::<T>(caller.clone(), 32);
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into(), OnFull::Rollover)
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		assert_eq!(Merkle::<T>::on_full(tree_id), OnFull::Rollover);
+	}
+
+	set_public_input_layout {
+		// Weights are linear in the number of inputs, each of them is checked
+		// against the ones before it
+		let n in 1 .. MAX_PUBLIC_INPUTS as u32;
+		let caller: T::AccountId = whitelisted_caller();
+		let inputs = [PublicInput::Root, PublicInput::NullifierHash, PublicInput::Recipient, PublicInput::Relayer];
+		let layout = inputs[..n as usize].to_vec();
+		setup_tree::<T>(caller.clone(), 32);
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into(), layout)
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		assert_eq!(Merkle::<T>::public_input_layout(tree_id).len(), n as usize);
+	}
+
 	add_members {
 		// This means that the test will run `NUM_LEAVES` times
 		// Each time it runs, new value of `n` will be set
@@ -103,6 +153,74 @@ benchmarks! {
 		assert_eq!(tree.leaf_count, n);
 	}
 
+	add_leaf {
+		// Weights are linear in the depth of the tree, every level is hashed
+		// once on the way to the root
+		let d in 1 .. MAX_DEPTH as u32;
+		let caller: T::AccountId = whitelisted_caller();
+		let leaf = ScalarData::zero().to_bytes();
+
+		setup_tree::<T>(caller.clone(), d);
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into(), leaf, 0)
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		let tree: MerkleTree = Trees::<T>::get(tree_id).unwrap();
+		assert_eq!(tree.leaf_count, 1);
+	}
+
+	add_leaves {
+		// Weights are linear in the number of leaves in the batch
+		let n in 1 .. NUM_LEAVES;
+		let caller: T::AccountId = whitelisted_caller();
+		let leaves = vec![ScalarData::zero(); n as usize];
+
+		setup_tree::<T>(caller.clone(), 32);
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into(), leaves)
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		let tree: MerkleTree = Trees::<T>::get(tree_id).unwrap();
+		assert_eq!(tree.leaf_count, n);
+	}
+
+	update_leaf {
+		// Weights are linear in the depth of the tree, every node on the path
+		// of the leaf is hashed again
+		let d in 1 .. MAX_DEPTH as u32;
+		let caller: T::AccountId = whitelisted_caller();
+		let params = TreeParams {
+			manager_required: true,
+			depth: Some(d as u8),
+			hasher: Some(HashFunction::PoseidonDefault),
+			append_only: false,
+			..Default::default()
+		};
+		<Merkle<T> as Tree<T>>::create_tree(caller.clone(), params).unwrap();
+		Merkle::<T>::add_leaves(RawOrigin::Signed(caller.clone()).into(), 0u32.into(), vec![ScalarData::zero()]).unwrap();
+		// a canonical scalar, unlike the `[42; 32]` leaves
+		let leaf = [1u8; 32];
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into(), 0, leaf)
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		assert_eq!(Leaves::<T>::get(tree_id, 0), ScalarData::from(leaf));
+	}
+
+	prune_tree {
+		// Weights are linear in the number of leaves cleared from the tree,
+		// along with their indices and the nodes above them, of which the
+		// deepest tree has the most
+		let n in 1 .. NUM_LEAVES;
+		let caller: T::AccountId = whitelisted_caller();
+		let leaves = vec![ScalarData::zero(); n as usize];
+
+		setup_tree::<T>(caller.clone(), 32);
+		Merkle::<T>::add_leaves(RawOrigin::Signed(caller.clone()).into(), 0u32.into(), leaves).unwrap();
+		Merkle::<T>::stop_tree(RawOrigin::Signed(caller.clone()).into(), 0u32.into()).unwrap();
+	}: _(RawOrigin::Signed(caller.clone()), 0u32.into())
+	verify {
+		let tree_id: T::TreeId = 0u32.into();
+		assert!(Merkle::<T>::pruned(tree_id));
+	}
+
 	verify_path {
 		let d in 1 .. VERIFY_DEPTH as u32;
 		let caller: T::AccountId = whitelisted_caller();
@@ -184,6 +302,34 @@ mod bench_tests {
 		});
 	}
 
+	#[test]
+	fn test_stop_tree() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_stop_tree::<Test>());
+		});
+	}
+
+	#[test]
+	fn test_resume_tree() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_resume_tree::<Test>());
+		});
+	}
+
+	#[test]
+	fn test_set_on_full() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_set_on_full::<Test>());
+		});
+	}
+
+	#[test]
+	fn test_set_public_input_layout() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_set_public_input_layout::<Test>());
+		});
+	}
+
 	#[test]
 	fn test_add_members() {
 		new_test_ext().execute_with(|| {
@@ -191,6 +337,34 @@ mod bench_tests {
 		});
 	}
 
+	#[test]
+	fn test_add_leaf() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_add_leaf::<Test>());
+		});
+	}
+
+	#[test]
+	fn test_add_leaves() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_add_leaves::<Test>());
+		});
+	}
+
+	#[test]
+	fn test_update_leaf() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_update_leaf::<Test>());
+		});
+	}
+
+	#[test]
+	fn test_prune_tree() {
+		new_test_ext().execute_with(|| {
+			assert_ok!(test_benchmark_prune_tree::<Test>());
+		});
+	}
+
 	#[test]
 	fn test_verify_path() {
 		new_test_ext().execute_with(|| {
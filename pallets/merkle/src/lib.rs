@@ -57,12 +57,33 @@
 //!   and nullifiers.
 //! - `set_manager` - Set manager account id. Can only be called by the root or
 //!   the current manager.
-//! - `set_stopped` - Sets stopped storage flag. This flag by itself doesn't do
-//!   anything. It's up to higher-level pallets to make appropriate use of it.
+//! - `set_stopped` - Sets stopped storage flag. No leaves can be added to a
+//!   stopped tree, it's up to higher-level pallets to make any other use of it.
 //!   Can only be called by the root or the manager;
+//! - `stop_tree` / `resume_tree` - Freezes and unfreezes the leaves of the
+//!   tree. Can only be called by the root or the manager;
 //! - `add_members` Adds an array of leaves to the tree. Can only be called by
 //!   the manager if the manager is required.
+//! - `add_leaf` Adds a single leaf, which must be a canonical scalar, to the
+//!   tree, emitting the index it was inserted at. The leaf must be in the
+//!   commitment version of the tree. Can only be called by the manager if the
+//!   manager is required.
+//! - `add_leaves` Adds a batch of leaves to the tree, computing the new root
+//!   only once. Can only be called by the manager if the manager is required.
+//! - `update_leaf` Replaces a leaf of a tree which isn't append-only,
+//!   recomputing its path to the root. Can only be called by the root or the
+//!   manager.
 //! - `verify` - Verifies the membership proof.
+//! - `verify_zk_membership` - Verifies the zero-knowledge membership proof and
+//!   marks the nullifier as used. Can only be called by the manager if the
+//!   manager is required.
+//!
+//! ### Off-chain worker
+//!
+//! On every block, the nodes of the trees whose leaves changed in the block
+//! are cached in the persistent off-chain storage, only the ones above the
+//! changed leaves, so the node can serve the membership proofs without calling
+//! into the runtime. See the [`offchain`](crate::offchain) module.
 //!
 //! ## Usage
 //!
@@ -89,6 +110,8 @@ pub mod tests;
 
 #[cfg(feature = "runtime-benchmarks")]
 mod benchmarking;
+pub mod migrations;
+pub mod offchain;
 pub mod weights;
 use crate::utils::keys::from_bytes_to_bp_gens;
 use bulletproofs::{
@@ -100,31 +123,40 @@ use bulletproofs_gadgets::{
 	poseidon::{
 		allocate_statics_for_verifier,
 		builder::{Poseidon, PoseidonBuilder},
-		PoseidonSbox, Poseidon_hash_2,
+		PoseidonSbox,
 	},
 	smt::gen_zero_tree,
 	utils::AllocatedScalar,
 };
-use codec::{Decode, Encode};
+use codec::{Codec, Decode, Encode};
 use curve25519_dalek::scalar::Scalar;
 use frame_support::{
 	dispatch, ensure,
-	traits::{Get, Randomness},
+	traits::{Currency, ExistenceRequirement, Get, Randomness},
+	transactional,
 	weights::Weight,
-	Parameter,
+	PalletId, Parameter,
 };
 use frame_system::ensure_signed;
 
+pub use merkle_primitives::HashFunction;
 use merlin::Transcript;
 
 use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
-use sp_runtime::traits::{AtLeast32Bit, One};
-use sp_std::prelude::*;
-pub use traits::Tree;
+use sp_runtime::{
+	traits::{
+		AccountIdConversion, AtLeast32Bit, AtLeast32BitUnsigned, BlakeTwo256, Hash as HashT, MaybeSerializeDeserialize,
+		One, SaturatedConversion, Saturating, Zero,
+	},
+	RuntimeDebug,
+};
+use sp_std::{convert::TryInto, prelude::*};
+pub use traits::{MerkleHasher, Tree};
 use utils::{
 	keys::{Commitment, ScalarData},
 	permissions::ensure_admin,
 };
+use webb_traits::MultiCurrency;
 use weights::WeightInfo;
 
 /// Default hasher instance used to construct the tree
@@ -136,6 +168,84 @@ pub fn default_hasher(bp_gens: BulletproofGens) -> Poseidon {
 		.build()
 }
 
+/// Version of the format of the `ZkMembershipProof` made by the clients
+pub const ZK_PROOF_VERSION: u16 = 1;
+
+/// Zero-knowledge membership proof along with its public inputs, as taken by
+/// `verify_zk_membership` and, SCALE-encoded, by the batch verification of
+/// the runtime API
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq)]
+pub struct ZkMembershipProof<BlockNumber, AccountId, Balance> {
+	/// Version of the format of the proof, only `ZK_PROOF_VERSION` is
+	/// accepted
+	pub version: u16,
+	/// The cached block for the cached root being proven against
+	pub cached_block: BlockNumber,
+	/// The cached root being proven against
+	pub cached_root: ScalarData,
+	/// The individual scalar commitments (to the randomness and nullifier)
+	pub comms: Vec<Commitment>,
+	/// The nullifier hash with itself
+	pub nullifier_hash: ScalarData,
+	/// The proof in bytes representation
+	pub proof_bytes: Vec<u8>,
+	/// The leaf index scalar commitments to decide on which side to hash
+	pub leaf_index_commitments: Vec<Commitment>,
+	/// The scalar commitments to merkle proof path elements
+	pub proof_commitments: Vec<Commitment>,
+	/// The recipient the deposit is released to, bound to the proof
+	pub recipient: AccountId,
+	/// The relayer paid the fee out of the deposit, bound to the proof along
+	/// with the fee
+	pub relayer: AccountId,
+	/// The fee paid to the relayer out of the deposit
+	pub fee: Balance,
+}
+
+impl<BlockNumber, AccountId, Balance> ZkMembershipProof<BlockNumber, AccountId, Balance> {
+	/// Creates a proof in the current `ZK_PROOF_VERSION`
+	pub fn new(
+		cached_block: BlockNumber,
+		cached_root: ScalarData,
+		comms: Vec<Commitment>,
+		nullifier_hash: ScalarData,
+		proof_bytes: Vec<u8>,
+		leaf_index_commitments: Vec<Commitment>,
+		proof_commitments: Vec<Commitment>,
+		recipient: AccountId,
+		relayer: AccountId,
+		fee: Balance,
+	) -> Self {
+		Self {
+			version: ZK_PROOF_VERSION,
+			cached_block,
+			cached_root,
+			comms,
+			nullifier_hash,
+			proof_bytes,
+			leaf_index_commitments,
+			proof_commitments,
+			recipient,
+			relayer,
+			fee,
+		}
+	}
+}
+
+/// Zero-knowledge membership proof of the trees of the runtime
+pub type ZkProofOf<T> =
+	ZkMembershipProof<<T as frame_system::Config>::BlockNumber, <T as frame_system::Config>::AccountId, BalanceOf<T>>;
+
+/// Type alias for the balance of the currency the deposits are made in
+pub type BalanceOf<T> = <<T as Config>::DepositCurrency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+/// Configuration of the new trees of the runtime
+pub type TreeParamsOf<T> = TreeParams<BalanceOf<T>, <T as Config>::AssetId>;
+
+/// Configuration of the trees of the runtime, as stored
+pub type TreeConfigOf<T> = TreeConfig<BalanceOf<T>, <T as Config>::AssetId, <T as Config>::TreeId>;
+
 pub use pallet::*;
 
 /// Implementation of Merkle pallet
@@ -150,17 +260,41 @@ pub mod pallet {
 	pub trait Config: frame_system::Config {
 		/// The overarching event type.
 		type Event: IsType<<Self as frame_system::Config>::Event> + From<Event<Self>>;
+		/// The hashing backend of the trees, `DefaultHasher` hashes with the
+		/// hash function of each tree
+		type Hasher: MerkleHasher;
 		/// The overarching tree ID type
 		type TreeId: Encode + Decode + Parameter + AtLeast32Bit + Default + Copy;
 		/// The overarching key ID type
 		type KeyId: Encode + Decode + Parameter + AtLeast32Bit + Default + Copy;
-		/// The max depth of trees
+		/// The max depth of trees, leaf indices are `u32` so it can't be more
+		/// than 32
 		type MaxTreeDepth: Get<u8>;
 		/// The amount of blocks to cache roots over
 		type CacheBlockLength: Get<Self::BlockNumber>;
+		/// The max number of leaves that can be added in a single batch
+		type MaxLeavesPerCall: Get<u32>;
+		/// The max number of trees that can be created
+		type MaxTrees: Get<u32>;
+		/// The max length of the names of the trees, in bytes
+		type MaxTreeNameLength: Get<u32>;
+		/// The number of the most recent roots retained in the root history of
+		/// each tree, must be at least 1
+		type RootHistorySize: Get<u32>;
 		/// The generator used to supply randomness to contracts through
 		/// `seal_random`.
 		type Randomness: Randomness<Self::Hash, Self::BlockNumber>;
+		/// Currency the deposits into the trees with a denomination are made
+		/// in
+		type DepositCurrency: Currency<Self::AccountId>;
+		/// Id of the pallet, the account derived from it holds the deposits
+		#[pallet::constant]
+		type DepositPalletId: Get<PalletId>;
+		/// Id of the assets the deposits can be made in instead of the
+		/// `DepositCurrency`
+		type AssetId: Parameter + AtLeast32BitUnsigned + MaybeSerializeDeserialize + Copy;
+		/// Assets the deposits into the trees with an asset id are made in
+		type Assets: MultiCurrency<Self::AccountId, CurrencyId = Self::AssetId, Balance = BalanceOf<Self>>;
 		/// Weight information for extrinsics in this pallet.
 		type WeightInfo: WeightInfo;
 	}
@@ -201,6 +335,44 @@ pub mod pallet {
 		ManagerIsRequired,
 		/// Manager not found for specific tree
 		ManagerDoesntExist,
+		/// Leaf doesn't exist at the specified index
+		LeafDoesntExist,
+		/// Too many leaves added in a single batch
+		TooManyLeaves,
+		/// Hash function isn't supported by the tree or the operation
+		UnsupportedHashFunction,
+		/// Tree is stopped, so no leaves can be added to it
+		TreeIsStopped,
+		/// Leaf bytes aren't a canonical scalar
+		InvalidLeaf,
+		/// Tree isn't stopped, so it can't be pruned
+		TreeIsNotStopped,
+		/// Tree is pruned, its leaves and nodes are no longer stored
+		TreeIsPruned,
+		/// Leaf is encoded in a different commitment version than the tree
+		CommitmentVersionMismatch,
+		/// Tree is append-only, so its leaves can't be updated
+		TreeIsAppendOnly,
+		/// Max number of trees is reached
+		TooManyTrees,
+		/// Name of the tree is longer than allowed
+		TreeNameTooLong,
+		/// Name of the tree is already used by another tree
+		TreeNameTaken,
+		/// Tree has fewer leaves than its minimum anonymity set, so it can't
+		/// be withdrawn from yet
+		AnonymitySetTooSmall,
+		/// Proof is in a version of the format which isn't supported
+		UnsupportedProofVersion,
+		/// Asset is given without a denomination to deposit
+		AssetWithoutDenomination,
+		/// Layout of the public inputs repeats an input or is too long
+		InvalidPublicInputLayout,
+		/// Nodes of the tree aren't migrated to the storage version yet, so its
+		/// membership proofs can't be built
+		MigrationPending,
+		/// Relayer fee is larger than the denomination of the tree
+		FeeExceedsDeposit,
 	}
 
 	#[pallet::event]
@@ -213,6 +385,27 @@ pub mod pallet {
 		NewMembers(T::TreeId, T::AccountId, u32, Vec<ScalarData>),
 		/// New nullifier submitted on verification
 		NewNullifier(T::TreeId, T::AccountId, ScalarData),
+		/// Batch of leaves added to the tree, with the index of the first leaf
+		/// and the number of leaves
+		LeavesAdded(T::TreeId, u32, u32),
+		/// Tree is stopped, no leaves can be added until it's resumed
+		TreeStopped(T::TreeId),
+		/// Tree is resumed
+		TreeResumed(T::TreeId),
+		/// Leaf added to the tree, with the index it was inserted at
+		LeafAdded(T::TreeId, u32, ScalarData),
+		/// Zero-knowledge membership proof verified and the nullifier used,
+		/// with the sender, the nullifier hash and the recipient
+		Withdrawn(T::TreeId, T::AccountId, ScalarData, T::AccountId),
+		/// Leaves and nodes of the stopped tree cleared, its root is kept
+		TreePruned(T::TreeId),
+		/// Leaf of the tree replaced, with its index and the new leaf
+		LeafUpdated(T::TreeId, u32, ScalarData),
+		/// Tree created, with its depth, its hash function and its manager
+		TreeCreated(T::TreeId, u8, HashFunction, T::AccountId),
+		/// Full tree rolled over into its successor, with the id of the
+		/// successor
+		TreeRolledOver(T::TreeId, T::TreeId),
 	}
 
 	/// Old name generated by `decl_event`.
@@ -250,6 +443,23 @@ pub mod pallet {
 	pub type Leaves<T: Config> =
 		StorageDoubleMap<_, Blake2_128Concat, T::TreeId, Blake2_128Concat, u32, ScalarData, ValueQuery>;
 
+	/// The map of (tree_id, leaf commitment) to the index the leaf was last
+	/// inserted at. Only the stored leaves are indexed, so it never outgrows
+	/// the capacity of the tree.
+	#[pallet::storage]
+	#[pallet::getter(fn leaf_index)]
+	pub type LeafIndex<T: Config> =
+		StorageDoubleMap<_, Blake2_128Concat, T::TreeId, Blake2_128Concat, ScalarData, Option<u32>, ValueQuery>;
+
+	/// The map of (tree_id, (level, index)) to the intermediate node of the
+	/// tree. The leaves are the level 0 and are kept in `Leaves`, the nodes are
+	/// kept from the level 1 up to the root. Missing nodes are the roots of
+	/// empty subtrees.
+	#[pallet::storage]
+	#[pallet::getter(fn nodes)]
+	pub type Nodes<T: Config> =
+		StorageDoubleMap<_, Blake2_128Concat, T::TreeId, Blake2_128Concat, (u8, u32), Option<ScalarData>, ValueQuery>;
+
 	/// Map of cached/past Merkle roots at each block number and group. There
 	/// can be more than one root update in a single block. Allows for easy
 	/// pruning since we can remove all keys of the first map past a certain
@@ -259,6 +469,31 @@ pub mod pallet {
 	pub type CachedRoots<T: Config> =
 		StorageDoubleMap<_, Blake2_128Concat, T::BlockNumber, Blake2_128Concat, T::TreeId, Vec<ScalarData>, ValueQuery>;
 
+	/// Ring buffer of the most recent roots of each tree, keyed by the
+	/// position of the root in the buffer
+	#[pallet::storage]
+	#[pallet::getter(fn root_history)]
+	pub type RootHistory<T: Config> =
+		StorageDoubleMap<_, Blake2_128Concat, T::TreeId, Blake2_128Concat, u32, ScalarData, ValueQuery>;
+
+	/// Number of roots each tree added to the root history since it was laid
+	/// out, including the ones which are no longer in the root history
+	#[pallet::storage]
+	#[pallet::getter(fn root_count)]
+	pub type RootCount<T: Config> = StorageMap<_, Blake2_128Concat, T::TreeId, u32, ValueQuery>;
+
+	/// Size of the root history before it became configurable
+	#[pallet::type_value]
+	pub fn DefaultRootHistoryLength() -> u32 {
+		30
+	}
+
+	/// Number of roots the root history of each tree is laid out for, the
+	/// root history is laid out again when `RootHistorySize` changes
+	#[pallet::storage]
+	#[pallet::getter(fn root_history_length)]
+	pub type RootHistoryLength<T: Config> = StorageValue<_, u32, ValueQuery, DefaultRootHistoryLength>;
+
 	/// Maps tree id to the manager of the tree
 	#[pallet::storage]
 	#[pallet::getter(fn get_manager)]
@@ -284,15 +519,137 @@ pub mod pallet {
 	#[pallet::getter(fn stopped)]
 	pub type Stopped<T: Config> = StorageMap<_, Blake2_128Concat, T::TreeId, bool, ValueQuery>;
 
+	/// Configuration of each tree, set when it's created and then by its
+	/// manager, stored next to the tree so it's read at once
+	#[pallet::storage]
+	#[pallet::getter(fn tree_config)]
+	pub type TreeConfigs<T: Config> = StorageMap<_, Blake2_128Concat, T::TreeId, TreeConfigOf<T>, ValueQuery>;
+
+	/// Index of the trees by the hash of their name
+	#[pallet::storage]
+	#[pallet::getter(fn tree_names)]
+	pub type TreeNames<T: Config> = StorageMap<_, Blake2_128Concat, T::Hash, Option<T::TreeId>, ValueQuery>;
+
+	/// Storage version of the pallet, used to run the migrations
+	#[pallet::storage]
+	#[pallet::getter(fn storage_version)]
+	pub type StorageVersion<T: Config> = StorageValue<_, Releases, ValueQuery>;
+
+	/// Tree, level and index of the next node to be built by the lazy
+	/// migration of the trees to the next storage version
+	#[pallet::storage]
+	#[pallet::getter(fn migration_cursor)]
+	pub type MigrationCursor<T: Config> = StorageValue<_, (T::TreeId, u8, u32), ValueQuery>;
+
+	/// Lowest index of the leaves of each tree inserted or replaced in the
+	/// current block, along with the root of the tree before the block, from
+	/// which the off-chain worker caches the nodes of the tree again
+	#[pallet::storage]
+	#[pallet::getter(fn changed_leaves)]
+	pub type ChangedLeaves<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::TreeId, (u32, Option<ScalarData>), OptionQuery>;
+
+	#[pallet::genesis_config]
+	pub struct GenesisConfig<T: Config> {
+		/// Verifying key the trees are initialized with, added as the first key
+		pub verifying_key: Option<Vec<u8>>,
+		/// Trees to create, in id order, along with their manager, depth, hash
+		/// function, denomination and the leaves to insert, which lock no
+		/// deposit, so only a tree without a denomination can have leaves. The
+		/// leaves are canonical scalars. The trees are append-only and require
+		/// their manager.
+		pub trees: Vec<(T::AccountId, u8, HashFunction, Option<BalanceOf<T>>, Vec<[u8; 32]>)>,
+	}
+
+	#[cfg(feature = "std")]
+	impl<T: Config> Default for GenesisConfig<T> {
+		fn default() -> Self {
+			GenesisConfig {
+				verifying_key: None,
+				trees: vec![],
+			}
+		}
+	}
+
+	#[pallet::genesis_build]
+	impl<T: Config> GenesisBuild<T> for GenesisConfig<T> {
+		fn build(&self) {
+			// the trees created from here on keep their nodes
+			StorageVersion::<T>::put(Releases::V2_0_0);
+			let key_id = self
+				.verifying_key
+				.clone()
+				.map(|key| <Pallet<T> as Tree<_>>::add_verifying_key(key).expect("genesis verifying key is added"));
+			for (manager, depth, hasher, denomination, leaves) in &self.trees {
+				// the genesis leaves lock no deposit, nothing would back their withdrawals
+				assert!(
+					denomination.is_none() || leaves.is_empty(),
+					"genesis leaves can't be inserted into a denominated tree"
+				);
+				let params = TreeParams {
+					manager_required: true,
+					depth: Some(*depth),
+					hasher: Some(hasher.clone()),
+					denomination: *denomination,
+					..Default::default()
+				};
+				let tree_id =
+					<Pallet<T> as Tree<_>>::create_tree(manager.clone(), params).expect("genesis tree is valid");
+				let key_id = match key_id {
+					Some(key_id) => key_id,
+					None => {
+						assert!(leaves.is_empty(), "genesis leaves need a verifying key");
+						continue;
+					}
+				};
+				<Pallet<T> as Tree<_>>::initialize_tree(tree_id, key_id).expect("genesis tree is initialized");
+				let leaves: Vec<ScalarData> = leaves
+					.iter()
+					.map(|leaf| ScalarData::from_bytes(*leaf).expect("genesis leaf is canonical"))
+					.collect();
+				for chunk in leaves.chunks(T::MaxLeavesPerCall::get().max(1) as usize) {
+					<Pallet<T> as Tree<_>>::add_members(manager.clone(), tree_id, chunk.to_vec())
+						.expect("genesis leaves fit in the tree");
+				}
+			}
+		}
+	}
+
 	#[pallet::pallet]
 	pub struct Pallet<T>(PhantomData<T>);
 
 	#[pallet::hooks]
 	impl<T: Config> Hooks<T::BlockNumber> for Pallet<T> {
+		fn on_runtime_upgrade() -> Weight {
+			migrations::migrate_tree_configs::<T>()
+				.saturating_add(migrations::backfill_max_leaves::<T>())
+				.saturating_add(migrations::resize_root_history::<T>())
+		}
+
+		fn integrity_test() {
+			assert!(
+				T::RootHistorySize::get() > 0,
+				"root history must keep at least one root"
+			);
+		}
+
+		fn offchain_worker(_n: T::BlockNumber) {
+			// builds the membership proofs outside of the runtime, for the RPC
+			offchain::cache_trees::<T>();
+		}
+
 		fn on_initialize(_n: T::BlockNumber) -> Weight {
+			// the off-chain worker of the previous block is done with its
+			// changed trees
+			let changed = ChangedLeaves::<T>::drain().count() as Weight;
+			// the nodes are built in the quarter of the block at most until the
+			// trees are migrated, so no block is filled by the migration
+			let migration = migrations::migrate_to_v2::<T>(T::BlockWeights::get().max_block / 4);
 			// Returning the weights for `on_finalize` in worst-case scenario where all if
 			// branches are hit
 			<T as Config>::WeightInfo::on_finalize()
+				.saturating_add(T::DbWeight::get().reads_writes(changed, changed))
+				.saturating_add(migration)
 		}
 
 		fn on_finalize(n: T::BlockNumber) {
@@ -320,23 +677,47 @@ pub mod pallet {
 	impl<T: Config> Pallet<T> {
 		/// Creates a new tree and sets a new manager for that tree. The
 		/// initial manager is the sender. Also increments the mixer id counter
-		/// in the storage. If _depth is not provided, max tree depth is
-		/// assumed.
+		/// in the storage. If depth is not provided, max tree depth is
+		/// assumed. The depth is stored in the tree and can't be more than
+		/// `MaxTreeDepth`. If hasher is not provided, Poseidon is assumed. No
+		/// more than `MaxTrees` trees can be created.
+		///
+		/// The tree can be given a unique name of up to `MaxTreeNameLength`
+		/// bytes, so it can be looked up without knowing its id.
+		///
+		/// The id assigned to the tree is emitted in the `TreeCreated` event,
+		/// the post-dispatch info has no room for it.
+		///
+		/// If a denomination is provided, every leaf added to the tree locks
+		/// exactly that amount from the sender, which is released by the
+		/// withdrawal. Otherwise the leaves are plain commitments. The amount
+		/// is in the given asset, or in the `DepositCurrency` if there is
+		/// none.
+		///
+		/// The commitment version is the version of the encoding of the leaves
+		/// of the tree, every leaf added with `add_leaf` must be in the same
+		/// version. The leaves of an append-only tree can't be updated.
+		///
+		/// The withdrawals from the tree are rejected until it has at least
+		/// `min_anonymity_set` leaves, so the first depositors can't be
+		/// singled out.
+		///
+		/// If `hash_leaf_on_insert` is set, every input added to the tree, or
+		/// verified against it, is hashed into the leaf with the `Hasher` of
+		/// the pallet. Otherwise the inputs are stored as the leaves.
+		///
+		/// All of the above is configured by the `params` of the tree.
 		///
 		/// Weights:
-		/// - Dependent on arguments: _depth
+		/// - Dependent on arguments: depth
 		///
 		/// - Base weight: 8_356_000
-		/// - DB weights: 1 read, 3 writes
-		/// - Additional weights: 151_000 * _depth
-		#[pallet::weight(<T as Config>::WeightInfo::create_tree(_depth.map_or(T::MaxTreeDepth::get() as u32, |x| x as u32)))]
-		pub fn create_tree(origin: OriginFor<T>, mgr_required: bool, _depth: Option<u8>) -> DispatchResultWithPostInfo {
+		/// - DB weights: 2 reads, 7 writes
+		/// - Additional weights: 151_000 * depth
+		#[pallet::weight(<T as Config>::WeightInfo::create_tree(params.depth.map_or(T::MaxTreeDepth::get() as u32, |x| x as u32)))]
+		pub fn create_tree(origin: OriginFor<T>, params: TreeParamsOf<T>) -> DispatchResultWithPostInfo {
 			let sender = ensure_signed(origin)?;
-			let depth = match _depth {
-				Some(d) => d,
-				None => T::MaxTreeDepth::get(),
-			};
-			let _ = <Self as Tree<_>>::create_tree(sender, mgr_required, depth)?;
+			let _ = <Self as Tree<_>>::create_tree(sender, params)?;
 			Ok(().into())
 		}
 
@@ -408,28 +789,228 @@ pub mod pallet {
 			Ok(().into())
 		}
 
+		/// Stops the tree, so no leaves can be added to it until it's resumed.
+		///
+		/// Can only be called by the root or the current manager.
+		///
+		/// Weights:
+		/// - Independent of the arguments.
+		///
+		/// - Base weight: 8_000_000
+		/// - DB weights: 1 read, 1 write
+		#[pallet::weight(<T as Config>::WeightInfo::stop_tree())]
+		pub fn stop_tree(origin: OriginFor<T>, tree_id: T::TreeId) -> DispatchResultWithPostInfo {
+			Self::set_stopped(origin, tree_id, true)
+		}
+
+		/// Resumes the stopped tree.
+		///
+		/// Can only be called by the root or the current manager.
+		///
+		/// Weights:
+		/// - Independent of the arguments.
+		///
+		/// - Base weight: 9_000_000
+		/// - DB weights: 2 reads, 1 write
+		#[pallet::weight(<T as Config>::WeightInfo::resume_tree())]
+		pub fn resume_tree(origin: OriginFor<T>, tree_id: T::TreeId) -> DispatchResultWithPostInfo {
+			Self::set_stopped(origin, tree_id, false)
+		}
+
+		/// Sets what happens to the leaves inserted into the tree once it's
+		/// full. With `OnFull::Rollover`, the leaves which don't fit are
+		/// inserted into a successor created with the same configuration,
+		/// manager and verifying key, which rolls over in turn.
+		///
+		/// Can only be called by the root or the current manager.
+		///
+		/// Weights:
+		/// - Independent of the arguments.
+		///
+		/// - Base weight: 9_000_000
+		/// - DB weights: 2 reads, 1 write
+		#[pallet::weight(<T as Config>::WeightInfo::set_on_full())]
+		pub fn set_on_full(origin: OriginFor<T>, tree_id: T::TreeId, on_full: OnFull) -> DispatchResultWithPostInfo {
+			let manager_data = Managers::<T>::get(tree_id).ok_or(Error::<T>::ManagerDoesntExist)?;
+			ensure_admin(origin, &manager_data.account_id)?;
+			<Self as Tree<_>>::set_on_full(manager_data.account_id, tree_id, on_full)?;
+			Ok(().into())
+		}
+
+		/// Sets the order the public inputs of the proofs of the tree are bound
+		/// to them in, which must be the order of the circuit the proofs are
+		/// made with. Each input can appear once, so the layout holds at most
+		/// `MAX_PUBLIC_INPUTS` of them, and the inputs left out aren't bound.
+		///
+		/// Can only be called by the root or the current manager.
+		///
+		/// Weights:
+		/// - Dependent on argument: `layout`
+		///
+		/// - Base weight: 9_214_000
+		/// - DB weights: 2 reads, 1 write
+		/// - Additional weights: 118_000 * layout.len()
+		#[pallet::weight(<T as Config>::WeightInfo::set_public_input_layout(layout.len() as u32))]
+		pub fn set_public_input_layout(
+			origin: OriginFor<T>,
+			tree_id: T::TreeId,
+			layout: Vec<PublicInput>,
+		) -> DispatchResultWithPostInfo {
+			let manager_data = Managers::<T>::get(tree_id).ok_or(Error::<T>::ManagerDoesntExist)?;
+			ensure_admin(origin, &manager_data.account_id)?;
+			<Self as Tree<_>>::set_public_input_layout(manager_data.account_id, tree_id, layout)?;
+			Ok(().into())
+		}
+
+		/// Clears the leaves and the nodes of the stopped tree, keeping its
+		/// root, root history and metadata, so proofs made against it can
+		/// still be checked. A pruned tree can't be resumed.
+		///
+		/// Can only be called by the root or the current manager.
+		///
+		/// Weights:
+		/// - Dependent on the number of leaves in the tree
+		///
+		/// - Base weight: 10_412_000
+		/// - DB weights: 3 reads, 34 writes, and 1 read of the tree for the
+		///   weight itself, the writes including the 31 nodes above the last
+		///   leaf of a tree of the maximum depth
+		/// - Additional weights: 1_186_000 * leaf_count
+		/// - Additional DB weights: 3 writes * leaf_count, clearing the leaf,
+		///   its index and at most one of the nodes above it
+		#[pallet::weight(<T as Config>::WeightInfo::prune_tree(
+			Trees::<T>::get(tree_id).map_or(0, |tree| tree.leaf_count)
+		).saturating_add(T::DbWeight::get().reads(1)))]
+		pub fn prune_tree(origin: OriginFor<T>, tree_id: T::TreeId) -> DispatchResultWithPostInfo {
+			let manager_data = Managers::<T>::get(tree_id).ok_or(Error::<T>::ManagerDoesntExist)?;
+			ensure_admin(origin, &manager_data.account_id)?;
+			<Self as Tree<_>>::prune_tree(manager_data.account_id, tree_id)?;
+			Ok(().into())
+		}
+
 		/// Adds an array of leaf data into the tree and adds calculated root to
 		/// the cache.
 		///
-		/// Can only be called by the manager if a manager is set.
+		/// Can only be called by the manager if a manager is set. Locks the
+		/// denomination of the tree for each leaf, if it has one. No more than
+		/// `MaxLeavesPerCall` leaves can be added at once.
 		///
 		/// Weights:
 		/// - Dependent on argument: `members`
 		///
 		/// - Base weight: 384_629_956_000
-		/// - DB weights: 3 reads, 2 writes
+		/// - DB weights: 4 reads, 3 writes
 		/// - Additional weights: 20_135_984_000 * members.len()
 		#[pallet::weight(<T as Config>::WeightInfo::add_members(members.len() as u32))]
+		#[transactional]
 		pub fn add_members(
 			origin: OriginFor<T>,
 			tree_id: T::TreeId,
 			members: Vec<ScalarData>,
 		) -> DispatchResultWithPostInfo {
 			let sender = ensure_signed(origin)?;
+			Self::take_deposits(&sender, tree_id, members.len() as u32)?;
 			<Self as Tree<_>>::add_members(sender, tree_id, members)?;
 			Ok(().into())
 		}
 
+		/// Adds a single leaf into the tree and adds calculated root to the
+		/// cache. The index the leaf was inserted at is emitted in the
+		/// `LeafAdded` event. The leaf bytes must be a canonical scalar.
+		///
+		/// Can only be called by the manager if a manager is set. Locks the
+		/// denomination of the tree, if it has one. The commitment version
+		/// must be the one the tree was created with, so a leaf made for a
+		/// different circuit is rejected.
+		///
+		/// Weights:
+		/// - Dependent on the depth of the tree
+		///
+		/// - Base weight: 386_204_117_000
+		/// - DB weights: 14 reads, 8 writes, and 1 read of the tree for the
+		///   weight itself
+		/// - Additional weights: 836_412_000 * depth
+		/// - Additional DB weights: 1 write * depth
+		#[pallet::weight(<T as Config>::WeightInfo::add_leaf(
+			Trees::<T>::get(tree_id).map_or(T::MaxTreeDepth::get() as u32, |tree| tree.depth as u32)
+		).saturating_add(T::DbWeight::get().reads(1)))]
+		#[transactional]
+		pub fn add_leaf(
+			origin: OriginFor<T>,
+			tree_id: T::TreeId,
+			leaf: [u8; 32],
+			commitment_version: u16,
+		) -> DispatchResultWithPostInfo {
+			let sender = ensure_signed(origin)?;
+			ensure!(
+				commitment_version == Self::commitment_version(tree_id),
+				Error::<T>::CommitmentVersionMismatch
+			);
+			let leaf = ScalarData::from_bytes(leaf).ok_or(Error::<T>::InvalidLeaf)?;
+			Self::take_deposits(&sender, tree_id, 1)?;
+			<Self as Tree<_>>::add_leaf(sender, tree_id, leaf)?;
+			Ok(().into())
+		}
+
+		/// Replaces the leaf at `index` of the tree, which must not be
+		/// append-only, and recalculates only the nodes on its path to the
+		/// root. The new root is added to the cache and the root history. The
+		/// leaf bytes must be a canonical scalar.
+		///
+		/// Can only be called by the root or the current manager.
+		///
+		/// Weights:
+		/// - Dependent on the depth of the tree
+		///
+		/// - Base weight: 385_870_552_000
+		/// - DB weights: 8 reads, 7 writes, and 1 read of the tree for the
+		///   weight itself
+		/// - Additional weights: 845_127_000 * depth
+		/// - Additional DB weights: 1 read and 1 write * depth
+		#[pallet::weight(<T as Config>::WeightInfo::update_leaf(
+			Trees::<T>::get(tree_id).map_or(T::MaxTreeDepth::get() as u32, |tree| tree.depth as u32)
+		).saturating_add(T::DbWeight::get().reads(1)))]
+		pub fn update_leaf(
+			origin: OriginFor<T>,
+			tree_id: T::TreeId,
+			index: u32,
+			leaf: [u8; 32],
+		) -> DispatchResultWithPostInfo {
+			let manager_data = Managers::<T>::get(tree_id).ok_or(Error::<T>::ManagerDoesntExist)?;
+			ensure_admin(origin, &manager_data.account_id)?;
+			let leaf = ScalarData::from_bytes(leaf).ok_or(Error::<T>::InvalidLeaf)?;
+			<Self as Tree<_>>::update_leaf(manager_data.account_id, tree_id, index, leaf)?;
+			Ok(().into())
+		}
+
+		/// Adds a batch of leaf data into the tree and adds calculated root to
+		/// the cache. Unlike `add_members`, every node on the way to the root
+		/// is calculated only once for the whole batch. Either all the leaves
+		/// get inserted, or none of them.
+		///
+		/// Can only be called by the manager if a manager is set. Locks the
+		/// denomination of the tree for each leaf, if it has one.
+		///
+		/// Weights:
+		/// - Dependent on argument: `leaves`
+		///
+		/// - Base weight: 386_961_004_000
+		/// - DB weights: 11 reads, 6 writes
+		/// - Additional weights: 2_106_318_000 * leaves.len()
+		/// - Additional DB weights: 3 writes * leaves.len()
+		#[pallet::weight(<T as Config>::WeightInfo::add_leaves(leaves.len() as u32))]
+		#[transactional]
+		pub fn add_leaves(
+			origin: OriginFor<T>,
+			tree_id: T::TreeId,
+			leaves: Vec<ScalarData>,
+		) -> DispatchResultWithPostInfo {
+			let sender = ensure_signed(origin)?;
+			Self::take_deposits(&sender, tree_id, leaves.len() as u32)?;
+			<Self as Tree<_>>::add_leaves(sender, tree_id, leaves)?;
+			Ok(().into())
+		}
+
 		/// Verification stub for testing, these verification functions should
 		/// not need to be used directly as extrinsics. Rather, higher-order
 		/// modules should use the module functions to verify and execute
@@ -454,6 +1035,49 @@ pub mod pallet {
 			Ok(().into())
 		}
 
+		/// Verifies the zero-knowledge membership proof against the cached root
+		/// and the verifying key of the tree, then marks the nullifier as used.
+		/// The verifying key can be rotated by the root with
+		/// `set_verifying_key`.
+		///
+		/// Can only be called by the manager if a manager is set. If the tree
+		/// has a denomination, the relayer is paid its fee out of the deposit
+		/// and the rest is released to the recipient. Both are bound to the
+		/// proof, so anyone can submit it, e.g. a relayer on behalf of the
+		/// recipient. A tree without a denomination pays no fee. Proofs in
+		/// another version than `ZK_PROOF_VERSION` are rejected.
+		///
+		/// Weights:
+		/// - Independent of the arguments.
+		///
+		/// - Base weight: 1_078_562_000_000
+		/// - DB weights: 7 reads, 1 write
+		#[pallet::weight(<T as Config>::WeightInfo::verify_zk_membership())]
+		#[transactional]
+		pub fn verify_zk_membership(
+			origin: OriginFor<T>,
+			tree_id: T::TreeId,
+			proof: ZkProofOf<T>,
+		) -> DispatchResultWithPostInfo {
+			let sender = ensure_signed(origin)?;
+			let tree = Trees::<T>::get(tree_id).ok_or(Error::<T>::TreeDoesntExist)?;
+			ensure!(tree.initialized, Error::<T>::NotInitialized);
+			let hash_params = Self::get_poseidon_hasher_for_tree(tree_id)?;
+			let nullifier_hash = proof.nullifier_hash;
+			let (recipient, relayer, fee) = (proof.recipient.clone(), proof.relayer.clone(), proof.fee);
+			Self::check_withdrawal(tree_id, &tree, &hash_params, proof)?;
+			<Self as Tree<_>>::add_nullifier(sender.clone(), tree_id, nullifier_hash)?;
+			if let Some(denomination) = Self::denomination(tree_id) {
+				// pay the relayer and release the rest of the deposit
+				if !fee.is_zero() {
+					Self::transfer_deposit(tree_id, &Self::account_id(), &relayer, fee)?;
+				}
+				Self::transfer_deposit(tree_id, &Self::account_id(), &recipient, denomination - fee)?;
+			}
+			Self::deposit_event(Event::Withdrawn(tree_id, sender, nullifier_hash, recipient));
+			Ok(().into())
+		}
+
 		/// Initializes the merkle tree
 		///
 		/// Can only be called by the manager or root.
@@ -512,9 +1136,99 @@ pub mod pallet {
 }
 
 sp_api::decl_runtime_apis! {
+	/// Version 2 adds the queries of the leaves, nodes, proofs and metadata of
+	/// the trees on top of `get_leaf`.
+	#[api_version(2)]
 	pub trait MerkleApi {
 		/// Get the leaf of tree id at a given index.
 		fn get_leaf(tree_id: u32, index: u32) -> Option<ScalarData>;
+		/// Get the leaves of the tree in the range `from..to`, clamped to the
+		/// number of leaves in the tree, with `None` for the leaves which
+		/// aren't stored.
+		fn get_leaves_range(tree_id: u32, from: u32, to: u32) -> Vec<Option<ScalarData>>;
+		/// Get the checksum of the stored leaves of the tree in the range
+		/// `from..to`, clamped to the number of leaves in the tree, or `None`
+		/// if the tree isn't initialized.
+		fn get_leaves_checksum(tree_id: u32, from: u32, to: u32) -> Option<ScalarData>;
+		/// Get the nodes at the given level of the tree in the range
+		/// `from..to`, clamped to the filled positions of the level, with
+		/// `None` for the nodes which aren't stored. The level `0` holds the
+		/// leaves. `None` if the tree isn't initialized or isn't that deep.
+		fn get_tree_nodes(tree_id: u32, level: u8, from: u32, to: u32) -> Option<Vec<Option<ScalarData>>>;
+		/// Get the number of leaves inserted into the tree with the given id,
+		/// or `None` if the tree doesn't exist. Only the tree itself is read,
+		/// none of its leaves.
+		fn get_leaf_count(tree_id: u32) -> Option<u32>;
+		/// Get the number of trees created so far, tree ids are assigned from
+		/// `0` up to this value.
+		fn get_tree_count() -> u32;
+		/// Get the id of the tree with the given name, or `None` if no tree has
+		/// that name.
+		fn get_tree_id_by_name(name: Vec<u8>) -> Option<u32>;
+		/// Get the current root of the tree with the given id, or `None` if
+		/// the tree doesn't exist or isn't initialized yet.
+		fn get_root(tree_id: u32) -> Option<ScalarData>;
+		/// Get the membership proof path for the leaf at a given index, or
+		/// `None` if the leaf isn't in the tree.
+		fn get_membership_proof(tree_id: u32, leaf_index: u32) -> Option<Vec<(bool, ScalarData)>>;
+		/// Recompute the membership proof path for the leaf at a given index
+		/// from the stored leaves alone, along with the root they hash to, or
+		/// `None` if the leaf isn't in the tree or its leaves aren't stored.
+		fn rebuild_path(tree_id: u32, leaf_index: u32) -> Option<(Vec<(bool, ScalarData)>, ScalarData)>;
+		/// Get the roots of the empty subtrees of the tree, from the empty leaf
+		/// up to the root of the empty tree, or `None` if the tree isn't
+		/// initialized.
+		fn get_default_nodes(tree_id: u32) -> Option<Vec<ScalarData>>;
+		/// Get the metadata of the tree with the given id, or `None` if the
+		/// tree doesn't exist.
+		fn get_tree_metadata(tree_id: u32) -> Option<TreeMetadata>;
+		/// Get the verifying key of the tree with the given id, or `None` if
+		/// the tree isn't initialized.
+		fn get_verifying_key(tree_id: u32) -> Option<Vec<u8>>;
+		/// Get the order the public inputs of the proofs of the tree are bound
+		/// in, none of them are bound if it's empty.
+		fn get_public_input_layout(tree_id: u32) -> Vec<PublicInput>;
+		/// Get the root at the given index in the root history of the tree,
+		/// where `0` is the current root, or `None` if the root is no longer
+		/// in the history.
+		fn get_root_at_index(tree_id: u32, root_index: u32) -> Option<ScalarData>;
+		/// Get the number of roots of the tree retained in the root history.
+		fn get_cached_root_count(tree_id: u32) -> u32;
+		/// Check whether the root is retained in the root history of the tree,
+		/// so a withdrawal can still be proven against it.
+		fn is_known_root(tree_id: u32, root: ScalarData) -> bool;
+		/// Check whether the nullifier was already used in the tree.
+		fn is_nullifier_used(tree_id: u32, nullifier: ScalarData) -> bool;
+		/// Get the index the leaf was last inserted at in the tree, or `None`
+		/// if the tree doesn't hold the leaf.
+		fn get_leaf_index(tree_id: u32, leaf: ScalarData) -> Option<u32>;
+		/// Verify a batch of SCALE-encoded `ZkMembershipProof` proofs of the
+		/// tree, returning whether each of them is valid.
+		fn verify_proofs_batch(tree_id: u32, proofs: Vec<Vec<u8>>) -> Vec<bool>;
+		/// Get the ids of the trees whose deposits are made in the asset.
+		fn get_trees_by_asset(asset_id: u64) -> Vec<u32>;
+		/// Get the ids of the trees the tree rolled over into, starting with
+		/// the tree itself, or none if the tree doesn't exist.
+		fn get_tree_chain(tree_id: u32) -> Vec<u32>;
+		/// Get the storage version of the pallet and whether the trees are
+		/// still being migrated.
+		fn get_storage_status() -> StorageStatus;
+		/// Run the checks of a withdrawal with the SCALE-encoded
+		/// `ZkMembershipProof` of the tree, without using its nullifier.
+		fn dry_run_withdraw(tree_id: u32, proof: Vec<u8>) -> WithdrawCheck;
+		/// Get the leaves inserted into or replaced in the trees by the
+		/// block, read from its events.
+		fn get_block_leaves() -> Vec<LeafChange<u32>>;
+		/// Get the number of the most recent roots retained in the root
+		/// history of every tree.
+		fn get_root_history_size() -> u32;
+	}
+
+	pub trait MerkleManagerApi<AccountId> where AccountId: Codec {
+		/// Get the manager of the tree with the given id and whether the
+		/// manager is required for the guarded calls, or `None` if the tree
+		/// doesn't exist.
+		fn get_manager(tree_id: u32) -> Option<(AccountId, bool)>;
 	}
 }
 
@@ -533,17 +1247,129 @@ impl<T: Config> Manager<T> {
 	}
 }
 
-/// Hash functions for MerkleTree
+/// Storage releases of the pallet
+#[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug)]
+pub enum Releases {
+	/// Initial release
+	V1_0_0,
+	/// Intermediate nodes of the trees are kept in `Nodes`
+	V2_0_0,
+}
+
+impl Default for Releases {
+	fn default() -> Self {
+		Releases::V1_0_0
+	}
+}
+
+/// Public input of the zero-knowledge membership proofs
+#[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug)]
+pub enum PublicInput {
+	/// Root the proof is made against
+	Root,
+	/// Hash of the nullifier of the proof
+	NullifierHash,
+	/// Recipient the proof is made for
+	Recipient,
+	/// Relayer the proof is made for
+	Relayer,
+}
+
+/// Number of the public inputs, the most a layout can hold since none of
+/// them can repeat
+pub const MAX_PUBLIC_INPUTS: usize = 4;
+
+/// What happens to the leaves inserted into a full tree
+#[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug)]
+pub enum OnFull {
+	/// The leaves are rejected
+	Reject,
+	/// The tree rolls over into a successor, which the leaves are inserted
+	/// into
+	Rollover,
+}
+
+impl Default for OnFull {
+	fn default() -> Self {
+		OnFull::Reject
+	}
+}
+
+/// Configuration of a tree, stored next to the `MerkleTree` in `TreeConfigs`
+#[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug)]
+pub struct TreeConfig<Balance, AssetId, TreeId> {
+	/// The amount every leaf of the tree locks as a deposit, if any
+	pub denomination: Option<Balance>,
+	/// The asset the deposits into the tree are made in, `None` for the
+	/// `DepositCurrency`
+	pub asset_id: Option<AssetId>,
+	/// Version of the commitment format the leaves of the tree are encoded in
+	pub commitment_version: u16,
+	/// Can the leaves of the tree only be appended, or also be updated by the
+	/// manager
+	pub append_only: bool,
+	/// Number of leaves the tree must have before the withdrawals from it are
+	/// accepted
+	pub min_anonymity_set: u32,
+	/// Are the inputs hashed into the leaves of the tree on insert, rather
+	/// than stored as the leaves themselves
+	pub hash_leaf_on_insert: bool,
+	/// What happens to the leaves inserted into the tree once it's full
+	pub on_full: OnFull,
+	/// The tree the full tree rolled over into, which the leaves inserted into
+	/// the tree go to from then on
+	pub successor: Option<TreeId>,
+	/// Order the public inputs of the proofs of the tree are bound to them in,
+	/// none of them are bound if it's empty
+	pub public_input_layout: Vec<PublicInput>,
+	/// Were the leaves and nodes of the tree pruned
+	pub pruned: bool,
+}
+
+impl<Balance, AssetId, TreeId> Default for TreeConfig<Balance, AssetId, TreeId> {
+	fn default() -> Self {
+		TreeConfig {
+			denomination: None,
+			asset_id: None,
+			commitment_version: 0,
+			// the trees created before the leaves could be updated are
+			// append-only
+			append_only: true,
+			min_anonymity_set: 0,
+			hash_leaf_on_insert: false,
+			on_full: OnFull::default(),
+			successor: None,
+			public_input_layout: Vec::new(),
+			pruned: false,
+		}
+	}
+}
+
+/// Storage version of the pallet along with the progress of its migration,
+/// as exposed to the clients
 #[cfg_attr(feature = "std", derive(Debug))]
 #[derive(Clone, Encode, Decode, PartialEq)]
-pub enum HashFunction {
-	PoseidonDefault,
-	PoseidonExp3,
-	PoseidonExp5,
-	PoseidonExp17,
-	MiMC,
-	Blake2,
-	Sha256,
+pub struct StorageStatus {
+	/// Current storage version
+	pub version: Releases,
+	/// Are some of the trees still to be migrated, their nodes can't be
+	/// relied on until they are
+	pub migration_pending: bool,
+}
+
+/// Leaf inserted into or replaced in a tree by a block, as read from the
+/// events of the block
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq)]
+pub struct LeafChange<TreeId> {
+	/// Tree the leaf belongs to
+	pub tree_id: TreeId,
+	/// Index of the leaf in the tree
+	pub leaf_index: u32,
+	/// The leaf as stored in the tree
+	pub leaf: ScalarData,
+	/// Did the leaf replace the one at its index, rather than being inserted
+	pub replaced: bool,
 }
 
 /// Essential data about the tree
@@ -572,45 +1398,225 @@ pub struct MerkleTree {
 	pub should_store_leaves: bool,
 }
 
+/// Hashes the nodes with the hash function of the tree, and the inputs of the
+/// trees hashing their leaves on insert along with the empty node, so a leaf is
+/// never one of the inputs. The zero-knowledge proofs are made against the
+/// commitments stored as the leaves themselves.
+pub struct DefaultHasher;
+
+impl MerkleHasher for DefaultHasher {
+	fn hash_two(hasher: &HashFunction, left: ScalarData, right: ScalarData, hash_params: &Poseidon) -> ScalarData {
+		ScalarData(merkle_primitives::hash(hasher, left.0, right.0, hash_params))
+	}
+
+	fn hash_leaf(hasher: &HashFunction, data: ScalarData, hash_params: &Poseidon) -> ScalarData {
+		Self::hash_two(hasher, data, ScalarData::zero(), hash_params)
+	}
+
+	fn zero_tree(hasher: &HashFunction, hash_params: &Poseidon) -> Vec<[u8; 32]> {
+		match hasher {
+			HashFunction::Blake2 | HashFunction::Sha256 => {
+				let mut zero_tree = vec![Scalar::zero().to_bytes()];
+				for i in 0..32 {
+					let node = ScalarData::from(zero_tree[i]);
+					zero_tree.push(Self::hash_two(hasher, node, node, hash_params).to_bytes());
+				}
+				zero_tree
+			}
+			_ => gen_zero_tree(hash_params.width, &hash_params.sbox),
+		}
+	}
+}
+
 impl MerkleTree {
-	pub fn new<T: Config>(depth: u8) -> Self {
+	pub fn new<T: Config>(depth: u8, hasher: HashFunction) -> Self {
 		Self {
 			initialized: false,
 			leaf_count: 0,
 			depth,
-			max_leaves: u32::MAX >> (T::MaxTreeDepth::get() - depth),
+			max_leaves: Self::capacity(depth),
 			root_hash: None,
 			edge_nodes: None,
-			hasher: HashFunction::PoseidonDefault,
+			hasher,
 			should_store_leaves: true, // the default for now.
 		}
 	}
+
+	/// Number of leaves a tree of the given depth holds. It only depends on
+	/// the depth of this tree, not on the depth of the deepest tree allowed.
+	pub fn capacity(depth: u8) -> u32 {
+		(1u64 << depth).min(u32::MAX as u64) as u32
+	}
+
+	/// Checks whether the given number of leaves still fit into the tree
+	pub fn has_room_for(&self, count: u32) -> bool {
+		self.leaf_count
+			.checked_add(count)
+			.map_or(false, |c| c <= self.max_leaves)
+	}
+}
+
+/// Configuration of a new tree, as passed to `create_tree`
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq, Eq)]
+pub struct TreeParams<Balance, AssetId> {
+	/// Is the manager required for the guarded calls
+	pub manager_required: bool,
+	/// Depth of the tree, `MaxTreeDepth` if not provided
+	pub depth: Option<u8>,
+	/// Hash function of the tree, Poseidon if not provided
+	pub hasher: Option<HashFunction>,
+	/// Amount every leaf locks as a deposit, if any
+	pub denomination: Option<Balance>,
+	/// Version of the commitment format of the leaves
+	pub commitment_version: u16,
+	/// Can the leaves only be appended, not updated
+	pub append_only: bool,
+	/// Unique name of the tree, if any
+	pub name: Option<Vec<u8>>,
+	/// Number of leaves required before the withdrawals are accepted
+	pub min_anonymity_set: u32,
+	/// Are the inputs hashed into the leaves on insert
+	pub hash_leaf_on_insert: bool,
+	/// Asset the deposits are made in, if not the native currency
+	pub asset_id: Option<AssetId>,
+}
+
+/// An append-only tree of the maximum depth hashed with Poseidon, with no
+/// deposits and no name, which doesn't require the manager
+impl<Balance, AssetId> Default for TreeParams<Balance, AssetId> {
+	fn default() -> Self {
+		Self {
+			manager_required: false,
+			depth: None,
+			hasher: None,
+			denomination: None,
+			commitment_version: 0,
+			append_only: true,
+			name: None,
+			min_anonymity_set: 0,
+			hash_leaf_on_insert: false,
+			asset_id: None,
+		}
+	}
+}
+
+/// Stored configuration and state of the tree, as exposed to the clients
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq)]
+pub struct TreeMetadata {
+	/// Depth of the tree
+	pub depth: u8,
+	/// Current number of leaves in the tree
+	pub leaf_count: u32,
+	/// Is the tree stopped
+	pub is_stopped: bool,
+	/// Hash function for the merkle tree
+	pub hasher: HashFunction,
+	/// Number of the most recent roots retained in the root history
+	pub root_history_size: u32,
+	/// Are the leaves and nodes of the tree pruned
+	pub is_pruned: bool,
+	/// Amount every leaf locks as a deposit, if any
+	pub denomination: Option<u128>,
+	/// Version of the commitment format of the leaves
+	pub commitment_version: u16,
+	/// Can the leaves only be appended, not updated
+	pub is_append_only: bool,
+	/// Number of leaves which can still be added before the tree is full
+	pub leaves_remaining: u32,
+	/// Number of leaves required before the withdrawals are accepted
+	pub min_anonymity_set: u32,
+	/// Are the inputs hashed into the leaves on insert
+	pub hash_leaf_on_insert: bool,
+	/// Asset the deposits are made in, if not the native currency
+	pub asset_id: Option<u64>,
+	/// What happens to the leaves inserted once the tree is full
+	pub on_full: OnFull,
+	/// Tree the tree rolled over into, if any
+	pub successor_tree_id: Option<u32>,
+	/// Are the nodes of the tree migrated to the storage version
+	pub is_migrated: bool,
+}
+
+/// Outcome of the dry run of a withdrawal, as exposed to the clients
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Copy, Encode, Decode, PartialEq, Eq)]
+pub enum WithdrawCheck {
+	/// The withdrawal would be accepted
+	Valid,
+	/// The proof is made against a root which isn't cached or retained in
+	/// the root history
+	UnknownRoot,
+	/// The nullifier of the proof was already used
+	NullifierUsed,
+	/// The proof doesn't decode or doesn't verify against the tree
+	ProofInvalid,
+	/// The tree has fewer leaves than its minimum anonymity set
+	AnonymitySetTooSmall,
+	/// The relayer fee is larger than the denomination of the tree
+	FeeExceedsDeposit,
 }
 
 impl<T: Config> Tree<T> for Pallet<T> {
-	fn create_tree(
-		sender: T::AccountId,
-		is_manager_required: bool,
-		depth: u8,
-	) -> Result<T::TreeId, dispatch::DispatchError> {
+	fn create_tree(sender: T::AccountId, params: TreeParamsOf<T>) -> Result<T::TreeId, dispatch::DispatchError> {
+		let TreeParams {
+			manager_required: is_manager_required,
+			depth,
+			hasher,
+			denomination,
+			commitment_version,
+			append_only,
+			name,
+			min_anonymity_set,
+			hash_leaf_on_insert,
+			asset_id,
+		} = params;
+		let depth = depth.unwrap_or_else(T::MaxTreeDepth::get);
+		let hasher = hasher.unwrap_or(HashFunction::PoseidonDefault);
 		ensure!(
-			depth <= T::MaxTreeDepth::get() && depth > 0,
+			depth <= T::MaxTreeDepth::get() && depth <= 32 && depth > 0,
 			Error::<T>::InvalidTreeDepth
 		);
+		ensure!(hasher.is_supported(), Error::<T>::UnsupportedHashFunction);
+		ensure!(
+			asset_id.is_none() || denomination.is_some(),
+			Error::<T>::AssetWithoutDenomination
+		);
 
 		// Setting the next tree id
 		let tree_id = Self::next_tree_id();
+		ensure!(tree_id < T::MaxTrees::get().into(), Error::<T>::TooManyTrees);
+		if let Some(name) = name {
+			ensure!(
+				name.len() as u32 <= T::MaxTreeNameLength::get(),
+				Error::<T>::TreeNameTooLong
+			);
+			let name_hash = T::Hashing::hash(&name);
+			ensure!(Self::tree_names(name_hash).is_none(), Error::<T>::TreeNameTaken);
+			TreeNames::<T>::insert(name_hash, Some(tree_id));
+		}
 		NextTreeId::<T>::mutate(|id| *id += One::one());
 
 		// Setting up the tree
-		let mtree = MerkleTree::new::<T>(depth);
+		let mtree = MerkleTree::new::<T>(depth, hasher.clone());
 		Trees::<T>::insert(tree_id, Some(mtree));
+		TreeConfigs::<T>::insert(tree_id, TreeConfig {
+			denomination,
+			asset_id,
+			commitment_version,
+			append_only,
+			min_anonymity_set,
+			hash_leaf_on_insert,
+			..Default::default()
+		});
 
 		// Setting up the manager
 		let manager = Manager::<T>::new(sender.clone(), is_manager_required);
 		Managers::<T>::insert(tree_id, Some(manager));
 
-		Self::deposit_event(Event::NewTree(tree_id, sender, is_manager_required));
+		Self::deposit_event(Event::NewTree(tree_id, sender.clone(), is_manager_required));
+		Self::deposit_event(Event::TreeCreated(tree_id, depth, hasher, sender));
 		Ok(tree_id)
 	}
 
@@ -628,6 +1634,8 @@ impl<T: Config> Tree<T> for Pallet<T> {
 		tree.edge_nodes = Some(init_edges);
 		tree.initialized = true;
 		Trees::<T>::insert(tree_id, Some(tree));
+		RootHistoryLength::<T>::put(T::RootHistorySize::get());
+		Self::add_root_to_history(tree_id, init_root);
 		<Self as Tree<_>>::set_verifying_key_for_tree(key_id, tree_id)?;
 		Ok(())
 	}
@@ -660,16 +1668,116 @@ impl<T: Config> Tree<T> for Pallet<T> {
 	fn set_stopped(sender: T::AccountId, id: T::TreeId, stopped: bool) -> Result<(), dispatch::DispatchError> {
 		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist).unwrap();
 		ensure!(sender == manager_data.account_id, Error::<T>::ManagerIsRequired);
+		ensure!(stopped || !Self::pruned(id), Error::<T>::TreeIsPruned);
 		Stopped::<T>::insert(id, stopped);
+		if stopped {
+			Self::deposit_event(Event::TreeStopped(id));
+		} else {
+			Self::deposit_event(Event::TreeResumed(id));
+		}
 		Ok(())
 	}
 
-	fn set_manager_required(
-		sender: T::AccountId,
-		id: T::TreeId,
-		manager_required: bool,
-	) -> Result<(), dispatch::DispatchError> {
-		let mut manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist).unwrap();
+	fn prune_tree(sender: T::AccountId, id: T::TreeId) -> Result<(), dispatch::DispatchError> {
+		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist)?;
+		ensure!(sender == manager_data.account_id, Error::<T>::ManagerIsRequired);
+		ensure!(Trees::<T>::contains_key(id), Error::<T>::TreeDoesntExist);
+		ensure!(Self::stopped(id), Error::<T>::TreeIsNotStopped);
+		ensure!(!Self::pruned(id), Error::<T>::TreeIsPruned);
+		// the tree itself keeps the root and the edge nodes
+		Leaves::<T>::remove_prefix(id);
+		LeafIndex::<T>::remove_prefix(id);
+		Nodes::<T>::remove_prefix(id);
+		TreeConfigs::<T>::mutate(id, |config| config.pruned = true);
+		Self::mark_changed(id, 0, Trees::<T>::get(id).and_then(|tree| tree.root_hash));
+		Self::deposit_event(Event::TreePruned(id));
+		Ok(())
+	}
+
+	fn set_on_full(sender: T::AccountId, id: T::TreeId, on_full: OnFull) -> Result<(), dispatch::DispatchError> {
+		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist)?;
+		ensure!(sender == manager_data.account_id, Error::<T>::ManagerIsRequired);
+		ensure!(Trees::<T>::contains_key(id), Error::<T>::TreeDoesntExist);
+		TreeConfigs::<T>::mutate(id, |config| config.on_full = on_full);
+		Ok(())
+	}
+
+	fn set_public_input_layout(
+		sender: T::AccountId,
+		id: T::TreeId,
+		layout: Vec<PublicInput>,
+	) -> Result<(), dispatch::DispatchError> {
+		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist)?;
+		ensure!(sender == manager_data.account_id, Error::<T>::ManagerIsRequired);
+		ensure!(Trees::<T>::contains_key(id), Error::<T>::TreeDoesntExist);
+		ensure!(layout.len() <= MAX_PUBLIC_INPUTS, Error::<T>::InvalidPublicInputLayout);
+		ensure!(
+			layout.iter().enumerate().all(|(i, input)| !layout[..i].contains(input)),
+			Error::<T>::InvalidPublicInputLayout
+		);
+		TreeConfigs::<T>::mutate(id, |config| config.public_input_layout = layout);
+		Ok(())
+	}
+
+	fn update_leaf(
+		sender: T::AccountId,
+		id: T::TreeId,
+		index: u32,
+		leaf: ScalarData,
+	) -> Result<(), dispatch::DispatchError> {
+		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist)?;
+		ensure!(sender == manager_data.account_id, Error::<T>::ManagerIsRequired);
+		let mut tree = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist)?;
+		ensure!(!Self::append_only(id), Error::<T>::TreeIsAppendOnly);
+		ensure!(!Self::stopped(id), Error::<T>::TreeIsStopped);
+		// the siblings on the path are the same for the new leaf
+		let path = Self::get_membership_proof(id, index)?;
+		let hash_params = Self::get_poseidon_hasher_for_tree(id)?;
+		let leaf = Self::input_leaves(id, tree.hasher.clone(), vec![leaf], &hash_params)[0];
+
+		Self::mark_changed(id, index, tree.root_hash);
+		let old_leaf = Leaves::<T>::get(id, index);
+		if Self::leaf_index(id, old_leaf) == Some(index) {
+			LeafIndex::<T>::remove(id, old_leaf);
+		}
+		Leaves::<T>::insert(id, index, leaf);
+		LeafIndex::<T>::insert(id, leaf, Some(index));
+		let mut edge_nodes = tree.edge_nodes.clone().unwrap();
+		let mut node_index = index;
+		let mut hash = leaf.0;
+		for (i, (is_left, sibling)) in path.into_iter().enumerate() {
+			if i > 0 {
+				Nodes::<T>::insert(id, (i as u8, node_index), Some(ScalarData(hash)));
+			}
+			// the edge node of the level is its last left child
+			if node_index == ((tree.leaf_count - 1) >> i) & !1 {
+				edge_nodes[i] = ScalarData(hash);
+			}
+			hash = match is_left {
+				true => Self::hash(tree.hasher.clone(), hash, sibling.0, &hash_params),
+				false => Self::hash(tree.hasher.clone(), sibling.0, hash, &hash_params),
+			};
+			node_index /= 2;
+		}
+
+		let root = ScalarData(hash);
+		tree.root_hash = Some(root);
+		tree.edge_nodes = Some(edge_nodes);
+		Trees::<T>::insert(id, Some(tree));
+		let block_number: T::BlockNumber = <frame_system::Pallet<T>>::block_number();
+		CachedRoots::<T>::append(block_number, id, root);
+		Self::add_root_to_history(id, root);
+
+		Self::deposit_event(Event::LeafUpdated(id, index, leaf));
+		Ok(())
+	}
+
+	fn set_manager_required(
+		sender: T::AccountId,
+		id: T::TreeId,
+		manager_required: bool,
+	) -> Result<(), dispatch::DispatchError> {
+		let mut manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist).unwrap();
 		// Changing manager required should always require an extrinsic from the
 		// manager even if the tree doesn't explicitly require managers for
 		// other calls.
@@ -696,8 +1804,15 @@ impl<T: Config> Tree<T> for Pallet<T> {
 		id: T::TreeId,
 		members: Vec<ScalarData>,
 	) -> Result<(), dispatch::DispatchError> {
+		ensure!(
+			members.len() as u32 <= T::MaxLeavesPerCall::get(),
+			Error::<T>::TooManyLeaves
+		);
+		// the leaves of a rolled over tree go to the last tree of its chain
+		let mut id = Self::chain_tail(id);
 		let mut tree = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist)?;
 		ensure!(tree.initialized, Error::<T>::NotInitialized);
+		ensure!(!Self::stopped(id), Error::<T>::TreeIsStopped);
 		let hasher = Self::get_poseidon_hasher_for_tree(id)?;
 		// Check if the tree requires extrinsics to be called from a manager
 		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist).unwrap();
@@ -705,12 +1820,25 @@ impl<T: Config> Tree<T> for Pallet<T> {
 			Self::is_manager_required(sender.clone(), &manager_data),
 			Error::<T>::ManagerIsRequired
 		);
-		let leaf_count_before = tree.leaf_count;
 		let num_members = members.len() as u32;
-		ensure!(
-			leaf_count_before + num_members <= tree.max_leaves,
-			Error::<T>::ExceedsMaxLeaves
-		);
+		if Self::on_full(id) == OnFull::Rollover {
+			if tree.leaf_count == tree.max_leaves {
+				id = Self::roll_over(id, &tree)?;
+				tree = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist)?;
+			}
+			// the tree is filled up, and the members which don't fit go to the
+			// successor it rolls over into
+			if !tree.has_room_for(num_members) {
+				let mut members = members;
+				let rest = members.split_off((tree.max_leaves - tree.leaf_count) as usize);
+				<Self as Tree<_>>::add_members(sender.clone(), id, members)?;
+				return <Self as Tree<_>>::add_members(sender, id, rest);
+			}
+		}
+		let leaf_count_before = tree.leaf_count;
+		ensure!(tree.has_room_for(num_members), Error::<T>::ExceedsMaxLeaves);
+		let members = Self::input_leaves(id, tree.hasher.clone(), members, &hasher);
+		Self::mark_changed(id, leaf_count_before, tree.root_hash);
 
 		let zero_tree = Self::generate_zero_tree(tree.hasher.clone(), &hasher);
 		for data in &members {
@@ -719,13 +1847,15 @@ impl<T: Config> Tree<T> for Pallet<T> {
 				// the index where the leaf should be saved is the count
 				// of leaves we have in the tree.
 				Leaves::<T>::insert(id, tree.leaf_count, *data);
+				LeafIndex::<T>::insert(id, *data, Some(tree.leaf_count));
 			}
 			// then we add it to the tree itself.
 			// note that, this method internally increments the leaves count.
-			Self::add_leaf(&mut tree, *data, &zero_tree, &hasher);
+			Self::insert_leaf(id, &mut tree, *data, &zero_tree, &hasher);
 		}
 		let block_number: T::BlockNumber = <frame_system::Pallet<T>>::block_number();
 		CachedRoots::<T>::append(block_number, id, tree.root_hash.unwrap());
+		Self::add_root_to_history(id, tree.root_hash.unwrap());
 		Trees::<T>::insert(id, Some(tree));
 
 		// Raising the New Member event for the client to build a tree locally
@@ -733,6 +1863,78 @@ impl<T: Config> Tree<T> for Pallet<T> {
 		Ok(())
 	}
 
+	fn add_leaf(sender: T::AccountId, id: T::TreeId, leaf: ScalarData) -> Result<u32, dispatch::DispatchError> {
+		<Self as Tree<_>>::add_members(sender, id, vec![leaf])?;
+		// the leaf is the last one of the last tree of the chain, which the
+		// tree may have just rolled over into
+		let id = Self::chain_tail(id);
+		let leaf_index = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist)?.leaf_count - 1;
+		// the event carries the leaf as it was stored
+		let leaf = match Self::hash_leaf_on_insert(id) {
+			true => Leaves::<T>::get(id, leaf_index),
+			false => leaf,
+		};
+		Self::deposit_event(Event::LeafAdded(id, leaf_index, leaf));
+		Ok(leaf_index)
+	}
+
+	fn add_leaves(sender: T::AccountId, id: T::TreeId, leaves: Vec<ScalarData>) -> Result<(), dispatch::DispatchError> {
+		ensure!(
+			leaves.len() as u32 <= T::MaxLeavesPerCall::get(),
+			Error::<T>::TooManyLeaves
+		);
+		// the leaves of a rolled over tree go to the last tree of its chain
+		let mut id = Self::chain_tail(id);
+		let mut tree = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist)?;
+		ensure!(tree.initialized, Error::<T>::NotInitialized);
+		ensure!(!Self::stopped(id), Error::<T>::TreeIsStopped);
+		let hasher = Self::get_poseidon_hasher_for_tree(id)?;
+		// Check if the tree requires extrinsics to be called from a manager
+		let manager_data = Managers::<T>::get(id).ok_or(Error::<T>::ManagerDoesntExist).unwrap();
+		ensure!(
+			Self::is_manager_required(sender.clone(), &manager_data),
+			Error::<T>::ManagerIsRequired
+		);
+		let count = leaves.len() as u32;
+		if Self::on_full(id) == OnFull::Rollover {
+			if tree.leaf_count == tree.max_leaves {
+				id = Self::roll_over(id, &tree)?;
+				tree = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist)?;
+			}
+			// the tree is filled up, and the leaves which don't fit go to the
+			// successor it rolls over into
+			if !tree.has_room_for(count) {
+				let mut leaves = leaves;
+				let rest = leaves.split_off((tree.max_leaves - tree.leaf_count) as usize);
+				<Self as Tree<_>>::add_leaves(sender.clone(), id, leaves)?;
+				return <Self as Tree<_>>::add_leaves(sender, id, rest);
+			}
+		}
+		let from_index = tree.leaf_count;
+		ensure!(tree.has_room_for(count), Error::<T>::ExceedsMaxLeaves);
+		if leaves.is_empty() {
+			return Ok(());
+		}
+		let leaves = Self::input_leaves(id, tree.hasher.clone(), leaves, &hasher);
+		Self::mark_changed(id, from_index, tree.root_hash);
+
+		if tree.should_store_leaves {
+			for (i, data) in leaves.iter().enumerate() {
+				Leaves::<T>::insert(id, from_index + i as u32, *data);
+				LeafIndex::<T>::insert(id, *data, Some(from_index + i as u32));
+			}
+		}
+		let zero_tree = Self::generate_zero_tree(tree.hasher.clone(), &hasher);
+		Self::add_leaves_batch(id, &mut tree, &leaves, &zero_tree, &hasher);
+		let block_number: T::BlockNumber = <frame_system::Pallet<T>>::block_number();
+		CachedRoots::<T>::append(block_number, id, tree.root_hash.unwrap());
+		Self::add_root_to_history(id, tree.root_hash.unwrap());
+		Trees::<T>::insert(id, Some(tree));
+
+		Self::deposit_event(Event::LeavesAdded(id, from_index, count));
+		Ok(())
+	}
+
 	fn add_nullifier(
 		sender: T::AccountId,
 		id: T::TreeId,
@@ -751,6 +1953,8 @@ impl<T: Config> Tree<T> for Pallet<T> {
 	fn has_used_nullifier(id: T::TreeId, nullifier: ScalarData) -> Result<(), dispatch::DispatchError> {
 		let _ = Trees::<T>::get(id).ok_or(Error::<T>::TreeDoesntExist).unwrap();
 
+		// the nullifier is looked up by the hash of its storage key, it isn't
+		// compared with the used nullifiers
 		ensure!(
 			!UsedNullifiers::<T>::contains_key((id, nullifier)),
 			Error::<T>::AlreadyUsedNullifier
@@ -766,15 +1970,19 @@ impl<T: Config> Tree<T> for Pallet<T> {
 			Error::<T>::InvalidPathLength
 		);
 		let hash_params = Self::get_poseidon_hasher_for_tree(id)?;
+		let leaf = Self::input_leaves(id, tree.hasher.clone(), vec![leaf], &hash_params)[0];
 		let mut hash = leaf.0;
 		for (is_right, node) in path {
 			hash = match is_right {
-				true => Poseidon_hash_2(hash, node.0, &hash_params),
-				false => Poseidon_hash_2(node.0, hash, &hash_params),
+				true => Self::hash(tree.hasher.clone(), hash, node.0, &hash_params),
+				false => Self::hash(tree.hasher.clone(), node.0, hash, &hash_params),
 			}
 		}
 
-		ensure!(hash == tree.root_hash.unwrap().0, Error::<T>::InvalidMembershipProof);
+		ensure!(
+			ScalarData(hash).ct_eq(&tree.root_hash.unwrap()) || Self::is_known_root(id, ScalarData(hash)),
+			Error::<T>::InvalidMembershipProof
+		);
 		Ok(())
 	}
 
@@ -790,25 +1998,15 @@ impl<T: Config> Tree<T> for Pallet<T> {
 		recipient: ScalarData,
 		relayer: ScalarData,
 	) -> Result<(), dispatch::DispatchError> {
-		let tree = Trees::<T>::get(tree_id).ok_or(Error::<T>::TreeDoesntExist).unwrap();
+		let tree = Trees::<T>::get(tree_id).ok_or(Error::<T>::TreeDoesntExist)?;
 		ensure!(tree.initialized, Error::<T>::NotInitialized);
-		ensure!(
-			tree.edge_nodes.unwrap().len() == proof_commitments.len(),
-			Error::<T>::InvalidPathLength
-		);
 		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id)?;
-		// Ensure that root being checked against is in the cache
-		let old_roots = Self::cached_roots(cached_block, tree_id);
-		ensure!(
-			old_roots.iter().any(|r| *r == cached_root),
-			Error::<T>::InvalidMerkleRoot
-		);
-		// TODO: Initialise these generators with the pallet
-		let pc_gens = PedersenGens::default();
-		<Self as Tree<_>>::verify_zk(
-			pc_gens,
+		Self::verify_zk_against_tree(
+			tree_id,
+			&tree,
+			&hash_params,
+			cached_block,
 			cached_root,
-			tree.depth,
 			comms,
 			nullifier_hash,
 			proof_bytes,
@@ -816,7 +2014,6 @@ impl<T: Config> Tree<T> for Pallet<T> {
 			proof_commitments,
 			recipient,
 			relayer,
-			&hash_params,
 		)
 	}
 
@@ -831,10 +2028,22 @@ impl<T: Config> Tree<T> for Pallet<T> {
 		proof_commitments: Vec<Commitment>,
 		recipient: ScalarData,
 		relayer: ScalarData,
+		public_input_layout: &[PublicInput],
 		hash_params: &Poseidon,
 	) -> Result<(), dispatch::DispatchError> {
 		let label = b"zk_membership_proof";
 		let mut verifier_transcript = Transcript::new(label);
+		// the public inputs are bound in the order of the circuit, so a proof
+		// made with the inputs arranged differently doesn't verify
+		for input in public_input_layout {
+			let value = match input {
+				PublicInput::Root => m_root,
+				PublicInput::NullifierHash => nullifier_hash,
+				PublicInput::Recipient => recipient,
+				PublicInput::Relayer => relayer,
+			};
+			verifier_transcript.append_message(b"public_input", &value.to_bytes());
+		}
 		let mut verifier = Verifier::new(&mut verifier_transcript);
 
 		ensure!(comms.len() == 3, Error::<T>::InvalidPrivateInputs);
@@ -908,6 +2117,249 @@ impl<T: Config> Tree<T> for Pallet<T> {
 }
 
 impl<T: Config> Pallet<T> {
+	/// Indicates whether the leaves and nodes of the tree were pruned
+	pub fn pruned(tree_id: T::TreeId) -> bool {
+		Self::tree_config(tree_id).pruned
+	}
+
+	/// The amount every leaf of the tree locks as a deposit, if any
+	pub fn denomination(tree_id: T::TreeId) -> Option<BalanceOf<T>> {
+		Self::tree_config(tree_id).denomination
+	}
+
+	/// The asset the deposits into the tree are made in, `None` for the
+	/// `DepositCurrency`
+	pub fn asset_id(tree_id: T::TreeId) -> Option<T::AssetId> {
+		Self::tree_config(tree_id).asset_id
+	}
+
+	/// Version of the commitment format the leaves of the tree are encoded in
+	pub fn commitment_version(tree_id: T::TreeId) -> u16 {
+		Self::tree_config(tree_id).commitment_version
+	}
+
+	/// Indicates whether the leaves of the tree can only be appended, or can
+	/// also be updated by the manager
+	pub fn append_only(tree_id: T::TreeId) -> bool {
+		Self::tree_config(tree_id).append_only
+	}
+
+	/// Number of leaves the tree must have before the withdrawals from it are
+	/// accepted
+	pub fn min_anonymity_set(tree_id: T::TreeId) -> u32 {
+		Self::tree_config(tree_id).min_anonymity_set
+	}
+
+	/// Indicates whether the inputs are hashed into the leaves of the tree on
+	/// insert, or stored as the leaves themselves
+	pub fn hash_leaf_on_insert(tree_id: T::TreeId) -> bool {
+		Self::tree_config(tree_id).hash_leaf_on_insert
+	}
+
+	/// What happens to the leaves inserted into the tree once it's full
+	pub fn on_full(tree_id: T::TreeId) -> OnFull {
+		Self::tree_config(tree_id).on_full
+	}
+
+	/// The tree the full tree rolled over into, if any
+	pub fn successor(tree_id: T::TreeId) -> Option<T::TreeId> {
+		Self::tree_config(tree_id).successor
+	}
+
+	/// Order the public inputs of the proofs of the tree are bound to them in
+	pub fn public_input_layout(tree_id: T::TreeId) -> Vec<PublicInput> {
+		Self::tree_config(tree_id).public_input_layout
+	}
+
+	/// Account holding the deposits of the trees with a denomination
+	pub fn account_id() -> T::AccountId {
+		T::DepositPalletId::get().into_account()
+	}
+
+	/// Public input of the withdrawal proof binding the recipient, so the
+	/// deposit can't be released to anyone else.
+	pub fn recipient_input(recipient: &T::AccountId) -> ScalarData {
+		ScalarData::from_slice(&recipient.encode())
+	}
+
+	/// Public input of the withdrawal proof binding the relayer and the fee,
+	/// so neither can be tampered with after the proof is made.
+	pub fn relayer_input(relayer: &T::AccountId, fee: BalanceOf<T>) -> ScalarData {
+		ScalarData::from(BlakeTwo256::hash_of(&(relayer, fee)).to_fixed_bytes())
+	}
+
+	/// Locks the denomination of the tree from the sender for each of the
+	/// `count` leaves, if the tree has one.
+	pub fn take_deposits(sender: &T::AccountId, tree_id: T::TreeId, count: u32) -> dispatch::DispatchResult {
+		if let Some(denomination) = Self::denomination(tree_id) {
+			let amount = denomination.saturating_mul(count.into());
+			if !amount.is_zero() {
+				Self::transfer_deposit(tree_id, sender, &Self::account_id(), amount)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Transfers the amount in the asset of the tree, or in the
+	/// `DepositCurrency` if it has none
+	fn transfer_deposit(
+		tree_id: T::TreeId,
+		from: &T::AccountId,
+		to: &T::AccountId,
+		amount: BalanceOf<T>,
+	) -> dispatch::DispatchResult {
+		match Self::asset_id(tree_id) {
+			Some(asset_id) => T::Assets::transfer(asset_id, from, to, amount),
+			None => T::DepositCurrency::transfer(from, to, amount, ExistenceRequirement::AllowDeath),
+		}
+	}
+
+	/// Gets the ids of the trees whose deposits are made in the asset, in
+	/// ascending order
+	pub fn get_trees_by_asset(asset_id: T::AssetId) -> Vec<T::TreeId> {
+		let mut tree_ids: Vec<T::TreeId> = TreeConfigs::<T>::iter()
+			.filter(|(_, config)| config.asset_id == Some(asset_id))
+			.map(|(tree_id, _)| tree_id)
+			.collect();
+		tree_ids.sort();
+		tree_ids
+	}
+
+	/// Creates the successor of the full tree, with the same configuration,
+	/// manager and verifying key, and links the tree to it. The successor
+	/// has no name and rolls over too once it's full.
+	fn roll_over(tree_id: T::TreeId, tree: &MerkleTree) -> Result<T::TreeId, dispatch::DispatchError> {
+		let manager = Managers::<T>::get(tree_id).ok_or(Error::<T>::ManagerDoesntExist)?;
+		let config = Self::tree_config(tree_id);
+		let params = TreeParams {
+			manager_required: manager.required,
+			depth: Some(tree.depth),
+			hasher: Some(tree.hasher.clone()),
+			denomination: config.denomination,
+			commitment_version: config.commitment_version,
+			append_only: config.append_only,
+			name: None,
+			min_anonymity_set: config.min_anonymity_set,
+			hash_leaf_on_insert: config.hash_leaf_on_insert,
+			asset_id: config.asset_id,
+		};
+		let successor = <Self as Tree<_>>::create_tree(manager.account_id, params)?;
+		<Self as Tree<_>>::initialize_tree(successor, Self::verifying_key_for_tree(tree_id))?;
+		TreeConfigs::<T>::mutate(successor, |successor_config| {
+			successor_config.on_full = OnFull::Rollover;
+			successor_config.public_input_layout = config.public_input_layout;
+		});
+		TreeConfigs::<T>::mutate(tree_id, |config| config.successor = Some(successor));
+		Self::deposit_event(Event::TreeRolledOver(tree_id, successor));
+		Ok(successor)
+	}
+
+	/// Follows the successors of the tree up to the last tree of its chain,
+	/// which is the tree itself if it never rolled over
+	pub fn chain_tail(tree_id: T::TreeId) -> T::TreeId {
+		let mut tree_id = tree_id;
+		while let Some(successor) = Self::successor(tree_id) {
+			tree_id = successor;
+		}
+		tree_id
+	}
+
+	/// Gets the chain of trees the tree rolled over into, starting with the
+	/// tree itself and followed by each successor in order. Empty if the tree
+	/// doesn't exist.
+	pub fn get_tree_chain(tree_id: T::TreeId) -> Vec<T::TreeId> {
+		if !Trees::<T>::contains_key(tree_id) {
+			return Vec::new();
+		}
+		let mut chain = vec![tree_id];
+		while let Some(successor) = Self::successor(chain[chain.len() - 1]) {
+			chain.push(successor);
+		}
+		chain
+	}
+
+	/// Gets the storage version of the pallet and whether the trees are still
+	/// being migrated to it. The trees are migrated lazily, a bounded number
+	/// of nodes per block, so the version is only the one the trees were
+	/// migrated to once no migration is pending.
+	pub fn get_storage_status() -> StorageStatus {
+		let version = Self::storage_version();
+		StorageStatus {
+			version,
+			migration_pending: version != Releases::V2_0_0,
+		}
+	}
+
+	/// Gets the number of the most recent roots retained in the root history
+	/// of every tree
+	pub fn get_root_history_size() -> u32 {
+		T::RootHistorySize::get()
+	}
+
+	/// Checks whether the nodes of the tree are migrated to the storage
+	/// version. The trees are migrated in id order, so the ones before the
+	/// tree of the migration cursor are, while the others aren't read until
+	/// the migration reaches them.
+	pub fn is_migrated(tree_id: T::TreeId) -> bool {
+		Self::storage_version() == Releases::V2_0_0 || tree_id < Self::migration_cursor().0
+	}
+
+	/// Gets the leaves inserted into or replaced in the trees by the current
+	/// block, in the order of its events, with a single read of the events.
+	/// The leaves of the batches of `LeavesAdded` are read from the storage,
+	/// so the ones of the trees which don't store their leaves are skipped.
+	pub fn get_block_leaves() -> Vec<LeafChange<T::TreeId>>
+	where
+		<T as frame_system::Config>::Event: TryInto<Event<T>>,
+	{
+		let inserted = |tree_id, leaf_index, leaf| LeafChange {
+			tree_id,
+			leaf_index,
+			leaf,
+			replaced: false,
+		};
+		let mut leaves = Vec::new();
+		for record in <frame_system::Pallet<T>>::events() {
+			match record.event.try_into() {
+				// `LeafAdded` follows the `NewMembers` of the same leaf
+				Ok(Event::NewMembers(tree_id, _, from, members)) => {
+					leaves.extend(
+						(from..)
+							.zip(members)
+							.map(|(index, leaf)| inserted(tree_id, index, leaf)),
+					);
+				}
+				Ok(Event::LeavesAdded(tree_id, from, count)) => {
+					leaves.extend((from..from + count).filter_map(|index| {
+						Leaves::<T>::try_get(tree_id, index)
+							.ok()
+							.map(|leaf| inserted(tree_id, index, leaf))
+					}));
+				}
+				Ok(Event::LeafUpdated(tree_id, leaf_index, leaf)) => leaves.push(LeafChange {
+					tree_id,
+					leaf_index,
+					leaf,
+					replaced: true,
+				}),
+				_ => {}
+			}
+		}
+		leaves
+	}
+
+	/// Records the leaves of the tree from `from` on as changed in the current
+	/// block, along with the root the tree had before the block, for the
+	/// off-chain worker to cache the nodes above them
+	pub fn mark_changed(tree_id: T::TreeId, from: u32, root: Option<ScalarData>) {
+		ChangedLeaves::<T>::mutate(tree_id, |changed| {
+			*changed = Some(match changed.take() {
+				Some((lowest, root)) => (lowest.min(from), root),
+				None => (from, root),
+			})
+		});
+	}
+
 	pub fn get_cache(tree_id: T::TreeId, block_number: T::BlockNumber) -> Vec<ScalarData> {
 		Self::cached_roots(block_number, tree_id)
 	}
@@ -924,6 +2376,183 @@ impl<T: Config> Pallet<T> {
 		Ok(())
 	}
 
+	/// Pushes the root to the root history of the tree, overwriting the
+	/// oldest root once the history is full
+	pub fn add_root_to_history(tree_id: T::TreeId, root: ScalarData) {
+		let root_count = RootCount::<T>::get(tree_id);
+		RootHistory::<T>::insert(tree_id, root_count % T::RootHistorySize::get(), root);
+		RootCount::<T>::insert(tree_id, root_count + 1);
+	}
+
+	/// Number of roots of the tree currently retained in the root history
+	pub fn get_cached_root_count(tree_id: T::TreeId) -> u32 {
+		RootCount::<T>::get(tree_id).min(T::RootHistorySize::get())
+	}
+
+	/// Gets the root at `root_index` in the root history of the tree, where
+	/// `0` is the current root, `1` the one before it and so on. Returns
+	/// `None` if the root is no longer in the history.
+	pub fn get_root_at_index(tree_id: T::TreeId, root_index: u32) -> Option<ScalarData> {
+		let root_count = RootCount::<T>::get(tree_id);
+		let root_history_size = T::RootHistorySize::get();
+		if root_index >= root_count.min(root_history_size) {
+			return None;
+		}
+		let position = (root_count - 1 - root_index) % root_history_size;
+		Some(RootHistory::<T>::get(tree_id, position))
+	}
+
+	/// Checks whether the root is currently retained in the root history of
+	/// the tree
+	pub fn is_known_root(tree_id: T::TreeId, root: ScalarData) -> bool {
+		(0..Self::get_cached_root_count(tree_id))
+			.any(|i| Self::get_root_at_index(tree_id, i).map_or(false, |known| known.ct_eq(&root)))
+	}
+
+	/// Verifies a batch of SCALE-encoded `ZkProofOf` proofs of the tree,
+	/// returning whether each of them is valid. The tree and its hash
+	/// parameters are loaded once for the whole batch, a proof is valid if it
+	/// decodes and passes every check of `verify_zk_membership`.
+	pub fn verify_zk_membership_proofs(tree_id: T::TreeId, proofs: Vec<Vec<u8>>) -> Vec<bool> {
+		let (tree, hash_params) = match Self::get_verifiable_tree(tree_id) {
+			Some(verifiable) => verifiable,
+			None => return vec![false; proofs.len()],
+		};
+		proofs
+			.iter()
+			.map(|bytes| Self::check_zk_membership_proof(tree_id, &tree, &hash_params, bytes) == WithdrawCheck::Valid)
+			.collect()
+	}
+
+	/// Runs the checks of `verify_zk_membership` on the SCALE-encoded
+	/// `ZkProofOf` proof of the tree, without using its nullifier or
+	/// releasing the deposit, and returns the first one which failed
+	pub fn dry_run_withdraw(tree_id: T::TreeId, proof: Vec<u8>) -> WithdrawCheck {
+		match Self::get_verifiable_tree(tree_id) {
+			Some((tree, hash_params)) => Self::check_zk_membership_proof(tree_id, &tree, &hash_params, &proof),
+			None => WithdrawCheck::ProofInvalid,
+		}
+	}
+
+	/// Loads the tree and its hash parameters to verify the proofs against,
+	/// `None` if no proof of the tree can be verified
+	fn get_verifiable_tree(tree_id: T::TreeId) -> Option<(MerkleTree, Poseidon)> {
+		let tree =
+			Trees::<T>::get(tree_id).filter(|tree| tree.initialized && tree.hasher == HashFunction::PoseidonDefault)?;
+		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id).ok()?;
+		Some((tree, hash_params))
+	}
+
+	/// Checks the SCALE-encoded proof with `check_withdrawal` and reports
+	/// the first check which failed
+	fn check_zk_membership_proof(
+		tree_id: T::TreeId,
+		tree: &MerkleTree,
+		hash_params: &Poseidon,
+		bytes: &[u8],
+	) -> WithdrawCheck {
+		let proof = match ZkProofOf::<T>::decode(&mut &bytes[..]) {
+			Ok(proof) => proof,
+			Err(_) => return WithdrawCheck::ProofInvalid,
+		};
+		match Self::check_withdrawal(tree_id, tree, hash_params, proof) {
+			Ok(()) => WithdrawCheck::Valid,
+			Err(e) if e == Error::<T>::InvalidMerkleRoot.into() => WithdrawCheck::UnknownRoot,
+			Err(e) if e == Error::<T>::AlreadyUsedNullifier.into() => WithdrawCheck::NullifierUsed,
+			Err(e) if e == Error::<T>::AnonymitySetTooSmall.into() => WithdrawCheck::AnonymitySetTooSmall,
+			Err(e) if e == Error::<T>::FeeExceedsDeposit.into() => WithdrawCheck::FeeExceedsDeposit,
+			Err(_) => WithdrawCheck::ProofInvalid,
+		}
+	}
+
+	/// Checks every precondition of the withdrawal of the proof from the tree,
+	/// without using its nullifier or releasing the deposit. The withdrawals
+	/// and their dry runs both go through it, so a proof the dry run reports
+	/// as valid is accepted by `verify_zk_membership` at the same block.
+	fn check_withdrawal(
+		tree_id: T::TreeId,
+		tree: &MerkleTree,
+		hash_params: &Poseidon,
+		proof: ZkProofOf<T>,
+	) -> Result<(), dispatch::DispatchError> {
+		ensure!(proof.version == ZK_PROOF_VERSION, Error::<T>::UnsupportedProofVersion);
+		// the fee is paid out of the deposit, a tree without a denomination
+		// has nothing to pay it from
+		ensure!(
+			proof.fee <= Self::denomination(tree_id).unwrap_or_else(Zero::zero),
+			Error::<T>::FeeExceedsDeposit
+		);
+		<Self as Tree<_>>::has_used_nullifier(tree_id, proof.nullifier_hash)?;
+		Self::verify_zk_against_tree(
+			tree_id,
+			tree,
+			hash_params,
+			proof.cached_block,
+			proof.cached_root,
+			proof.comms,
+			proof.nullifier_hash,
+			proof.proof_bytes,
+			proof.leaf_index_commitments,
+			proof.proof_commitments,
+			Self::recipient_input(&proof.recipient),
+			Self::relayer_input(&proof.relayer, proof.fee),
+		)
+	}
+
+	/// Verifies the zero-knowledge membership proof against the loaded tree
+	/// and its hash parameters: the minimum anonymity set, the length of the
+	/// path, the cached roots and the root history, then the proof itself
+	fn verify_zk_against_tree(
+		tree_id: T::TreeId,
+		tree: &MerkleTree,
+		hash_params: &Poseidon,
+		cached_block: T::BlockNumber,
+		cached_root: ScalarData,
+		comms: Vec<Commitment>,
+		nullifier_hash: ScalarData,
+		proof_bytes: Vec<u8>,
+		leaf_index_commitments: Vec<Commitment>,
+		proof_commitments: Vec<Commitment>,
+		recipient: ScalarData,
+		relayer: ScalarData,
+	) -> Result<(), dispatch::DispatchError> {
+		ensure!(tree.initialized, Error::<T>::NotInitialized);
+		ensure!(
+			tree.leaf_count >= Self::min_anonymity_set(tree_id),
+			Error::<T>::AnonymitySetTooSmall
+		);
+		// The circuit hashes the path with Poseidon
+		ensure!(
+			tree.hasher == HashFunction::PoseidonDefault,
+			Error::<T>::UnsupportedHashFunction
+		);
+		let depth = tree.edge_nodes.as_ref().map_or(0, |edge_nodes| edge_nodes.len());
+		ensure!(depth == proof_commitments.len(), Error::<T>::InvalidPathLength);
+		// Ensure that root being checked against is in the cache or the root
+		// history
+		let old_roots = Self::cached_roots(cached_block, tree_id);
+		ensure!(
+			old_roots.iter().any(|r| r.ct_eq(&cached_root)) || Self::is_known_root(tree_id, cached_root),
+			Error::<T>::InvalidMerkleRoot
+		);
+		// TODO: Initialise these generators with the pallet
+		let pc_gens = PedersenGens::default();
+		<Self as Tree<_>>::verify_zk(
+			pc_gens,
+			cached_root,
+			tree.depth,
+			comms,
+			nullifier_hash,
+			proof_bytes,
+			leaf_index_commitments,
+			proof_commitments,
+			recipient,
+			relayer,
+			&Self::public_input_layout(tree_id),
+			hash_params,
+		)
+	}
+
 	pub fn get_tree(tree_id: T::TreeId) -> Result<MerkleTree, dispatch::DispatchError> {
 		let tree = Trees::<T>::get(tree_id).ok_or(Error::<T>::TreeDoesntExist).unwrap();
 		Ok(tree)
@@ -937,12 +2566,22 @@ impl<T: Config> Pallet<T> {
 		}
 	}
 
-	pub fn add_leaf(tree: &mut MerkleTree, data: ScalarData, zero_tree: &Vec<[u8; 32]>, hash_params: &Poseidon) {
+	pub fn insert_leaf(
+		id: T::TreeId,
+		tree: &mut MerkleTree,
+		data: ScalarData,
+		zero_tree: &Vec<[u8; 32]>,
+		hash_params: &Poseidon,
+	) {
 		let mut edge_index = tree.leaf_count;
 		let mut hash = data.0;
 		let mut edge_nodes = tree.edge_nodes.clone().unwrap();
 		// Update the tree
 		for i in 0..edge_nodes.len() {
+			// keeping the nodes on the path of the leaf, for the membership proofs
+			if i > 0 && tree.should_store_leaves {
+				Nodes::<T>::insert(id, (i as u8, edge_index), Some(ScalarData(hash)));
+			}
 			hash = if edge_index % 2 == 0 {
 				edge_nodes[i] = ScalarData(hash);
 				let zero_h = Scalar::from_bytes_mod_order(zero_tree[i]);
@@ -959,18 +2598,275 @@ impl<T: Config> Pallet<T> {
 		tree.edge_nodes = Some(edge_nodes);
 	}
 
+	/// Gets the number of leaves in the tree, reading only the tree itself and
+	/// none of its leaves. `None` if the tree doesn't exist.
+	pub fn get_leaf_count(tree_id: T::TreeId) -> Option<u32> {
+		Trees::<T>::get(tree_id).map(|tree| tree.leaf_count)
+	}
+
+	pub fn get_tree_metadata(tree_id: T::TreeId) -> Option<TreeMetadata> {
+		let tree = Trees::<T>::get(tree_id)?;
+		let config = Self::tree_config(tree_id);
+		Some(TreeMetadata {
+			depth: tree.depth,
+			leaf_count: tree.leaf_count,
+			is_stopped: Self::stopped(tree_id),
+			hasher: tree.hasher,
+			root_history_size: T::RootHistorySize::get(),
+			is_pruned: config.pruned,
+			denomination: config.denomination.map(|amount| amount.saturated_into()),
+			commitment_version: config.commitment_version,
+			is_append_only: config.append_only,
+			leaves_remaining: tree.max_leaves - tree.leaf_count,
+			min_anonymity_set: config.min_anonymity_set,
+			hash_leaf_on_insert: config.hash_leaf_on_insert,
+			asset_id: config.asset_id.map(|asset_id| asset_id.saturated_into()),
+			on_full: config.on_full,
+			successor_tree_id: config.successor.map(|successor| successor.saturated_into()),
+			is_migrated: Self::is_migrated(tree_id),
+		})
+	}
+
+	/// Gets the roots of the empty subtrees of each height, from the empty
+	/// leaf up to the root of the empty tree, which stand in for the unfilled
+	/// positions of the tree. `None` if the tree isn't initialized.
+	pub fn get_default_nodes(tree_id: T::TreeId) -> Option<Vec<ScalarData>> {
+		let tree = Trees::<T>::get(tree_id).filter(|tree| tree.initialized)?;
+		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id).ok()?;
+		let zero_tree = Self::generate_zero_tree(tree.hasher, &hash_params);
+		Some(
+			zero_tree[..=tree.depth as usize]
+				.iter()
+				.map(|node| ScalarData::from(*node))
+				.collect(),
+		)
+	}
+
+	/// Gets the nodes at the level of the tree in the range `from..to`,
+	/// clamped to the positions of the level which have at least one leaf
+	/// under them. The level `0` holds the leaves and the level `depth` the
+	/// root. The nodes which aren't stored, as in the trees which don't keep
+	/// their leaves, are `None`, the rest of the level is made of the default
+	/// nodes. `None` if the tree isn't initialized or
+	/// the level is deeper than the tree.
+	pub fn get_tree_nodes(tree_id: T::TreeId, level: u8, from: u32, to: u32) -> Option<Vec<Option<ScalarData>>> {
+		let tree = Trees::<T>::get(tree_id).filter(|tree| tree.initialized && level <= tree.depth)?;
+		if level == 0 {
+			return Some(Self::get_leaves_range(tree_id, from, to));
+		}
+		// every node of the level covers `2^level` leaves
+		let filled = ((tree.leaf_count as u64 + (1u64 << level) - 1) >> level) as u32;
+		Some(
+			(from..to.min(filled))
+				.map(|index| match level == tree.depth {
+					// the root isn't kept with the other nodes
+					true => tree.root_hash,
+					false => Nodes::<T>::get(tree_id, (level, index)),
+				})
+				.collect(),
+		)
+	}
+
+	/// Gets the verifying key the proofs of the tree are checked against,
+	/// which also holds the parameters of its Poseidon hasher. `None` if the
+	/// tree isn't initialized.
+	pub fn get_verifying_key(tree_id: T::TreeId) -> Option<Vec<u8>> {
+		Trees::<T>::get(tree_id).filter(|tree| tree.initialized)?;
+		Self::verifying_keys(Self::verifying_key_for_tree(tree_id))
+	}
+
+	/// Gets the id of the tree with the given name, if any
+	pub fn get_tree_id_by_name(name: Vec<u8>) -> Option<T::TreeId> {
+		Self::tree_names(T::Hashing::hash(&name))
+	}
+
+	/// Gets the leaves of the tree in the range `from..to`, clamped to the
+	/// number of leaves in the tree. The leaves which aren't stored are
+	/// `None`, so every entry stays at the position of its index.
+	pub fn get_leaves_range(tree_id: T::TreeId, from: u32, to: u32) -> Vec<Option<ScalarData>> {
+		let leaf_count = Trees::<T>::get(tree_id).map_or(0, |tree| tree.leaf_count);
+		(from..to.min(leaf_count))
+			.map(|index| Leaves::<T>::try_get(tree_id, index).ok())
+			.collect()
+	}
+
+	/// Gets the checksum of the stored leaves of the tree in the range
+	/// `from..to`, clamped to the number of leaves in the tree, so a copy of
+	/// the leaves can be checked without fetching them again. The leaves are
+	/// folded into the zero scalar in order, each fold hashing the checksum so
+	/// far with the leaf using the hash function of the tree. `None` if the
+	/// tree isn't initialized.
+	pub fn get_leaves_checksum(tree_id: T::TreeId, from: u32, to: u32) -> Option<ScalarData> {
+		let tree = Trees::<T>::get(tree_id).filter(|tree| tree.initialized)?;
+		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id).ok()?;
+		let checksum = Self::get_leaves_range(tree_id, from, to)
+			.into_iter()
+			.flatten()
+			.fold(Scalar::zero(), |checksum, leaf| {
+				Self::hash(tree.hasher.clone(), checksum, leaf.0, &hash_params)
+			});
+		Some(ScalarData(checksum))
+	}
+
+	/// Inserts the batch of leaves into the tree, level by level. The new
+	/// nodes of each level are hashed in pairs to get the new nodes of the
+	/// level above, so every node is calculated only once, with the edge
+	/// nodes and empty subtrees standing in for the missing siblings.
+	pub fn add_leaves_batch(
+		id: T::TreeId,
+		tree: &mut MerkleTree,
+		leaves: &[ScalarData],
+		zero_tree: &Vec<[u8; 32]>,
+		hash_params: &Poseidon,
+	) {
+		let mut index = tree.leaf_count;
+		let mut layer: Vec<Scalar> = leaves.iter().map(|leaf| leaf.0).collect();
+		let mut edge_nodes = tree.edge_nodes.clone().unwrap();
+		for i in 0..edge_nodes.len() {
+			// the first new node is a right child, so its left sibling is the
+			// edge node of this level
+			if index % 2 == 1 {
+				layer.insert(0, edge_nodes[i].0);
+				index -= 1;
+			}
+			// the last left child becomes the edge node for the next inserts
+			let last_left = (layer.len() - 1) & !1;
+			edge_nodes[i] = ScalarData(layer[last_left]);
+			if i > 0 && tree.should_store_leaves {
+				for (j, node) in layer.iter().enumerate() {
+					Nodes::<T>::insert(id, (i as u8, index + j as u32), Some(ScalarData(*node)));
+				}
+			}
+
+			let zero_h = Scalar::from_bytes_mod_order(zero_tree[i]);
+			layer = Self::hash_layer(tree.hasher.clone(), &layer, zero_h, hash_params);
+			index /= 2;
+		}
+
+		tree.leaf_count += leaves.len() as u32;
+		tree.root_hash = Some(ScalarData(layer[0]));
+		tree.edge_nodes = Some(edge_nodes);
+	}
+
+	/// Hashes the nodes of a level in pairs to get the nodes of the level
+	/// above. The level starts at an even index, and the last node is paired
+	/// with the root of an empty subtree if it doesn't have a sibling.
+	pub fn hash_layer(hasher: HashFunction, layer: &[Scalar], zero_h: Scalar, hash_params: &Poseidon) -> Vec<Scalar> {
+		layer
+			.chunks(2)
+			.map(|pair| {
+				let right = pair.get(1).copied().unwrap_or(zero_h);
+				Self::hash(hasher.clone(), pair[0], right, hash_params)
+			})
+			.collect()
+	}
+
+	/// Rebuilds the membership proof for the leaf at `leaf_index` from the
+	/// stored leaves, without reading any of the stored nodes, along with the
+	/// root the leaves hash to. It hashes the whole tree, but doesn't rely on
+	/// the nodes, so the proofs built from them can be checked against it.
+	/// The path has the same format as the one of `get_membership_proof`.
+	/// `None` if the tree isn't initialized, doesn't store its leaves or
+	/// doesn't hold the leaf.
+	pub fn rebuild_path(tree_id: T::TreeId, leaf_index: u32) -> Option<(Vec<(bool, ScalarData)>, ScalarData)> {
+		let tree = Trees::<T>::get(tree_id).filter(|tree| tree.initialized && tree.should_store_leaves)?;
+		if Self::pruned(tree_id) || leaf_index >= tree.leaf_count {
+			return None;
+		}
+		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id).ok()?;
+		let zero_tree = Self::generate_zero_tree(tree.hasher.clone(), &hash_params);
+
+		let mut layer: Vec<Scalar> = (0..tree.leaf_count).map(|i| Leaves::<T>::get(tree_id, i).0).collect();
+		let mut index = leaf_index as usize;
+		let mut path = Vec::with_capacity(tree.depth as usize);
+		for i in 0..tree.depth as usize {
+			// missing siblings are the roots of empty subtrees
+			let zero_h = Scalar::from_bytes_mod_order(zero_tree[i]);
+			let sibling = layer.get(index ^ 1).copied().unwrap_or(zero_h);
+			path.push((index % 2 == 0, ScalarData(sibling)));
+			layer = Self::hash_layer(tree.hasher.clone(), &layer, zero_h, &hash_params);
+			index /= 2;
+		}
+		Some((path, ScalarData(layer[0])))
+	}
+
+	/// Builds the membership proof for the leaf at `leaf_index` from the
+	/// stored nodes, reading one sibling per level. The path goes from the leaf
+	/// to the root and has the same format as the one accepted by `verify`,
+	/// where `true` means that the node is on the right side.
+	pub fn get_membership_proof(
+		tree_id: T::TreeId,
+		leaf_index: u32,
+	) -> Result<Vec<(bool, ScalarData)>, dispatch::DispatchError> {
+		let tree = Trees::<T>::get(tree_id).ok_or(Error::<T>::TreeDoesntExist)?;
+		ensure!(tree.initialized, Error::<T>::NotInitialized);
+		// the nodes of a pruned tree are gone, the missing siblings would be
+		// taken for empty subtrees
+		ensure!(!Self::pruned(tree_id), Error::<T>::TreeIsPruned);
+		// the nodes of a tree still to be migrated aren't all built yet
+		ensure!(Self::is_migrated(tree_id), Error::<T>::MigrationPending);
+		ensure!(
+			tree.should_store_leaves && leaf_index < tree.leaf_count,
+			Error::<T>::LeafDoesntExist
+		);
+		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id)?;
+		let zero_tree = Self::generate_zero_tree(tree.hasher.clone(), &hash_params);
+
+		let mut index = leaf_index;
+		let mut path = Vec::with_capacity(tree.depth as usize);
+		for i in 0..tree.depth as usize {
+			let zero_h = ScalarData(Scalar::from_bytes_mod_order(zero_tree[i]));
+			// missing siblings are the roots of empty subtrees
+			let sibling = if i == 0 {
+				if (index ^ 1) < tree.leaf_count {
+					Leaves::<T>::get(tree_id, index ^ 1)
+				} else {
+					zero_h
+				}
+			} else {
+				Nodes::<T>::get(tree_id, (i as u8, index ^ 1)).unwrap_or(zero_h)
+			};
+			path.push((index % 2 == 0, sibling));
+			index /= 2;
+		}
+		Ok(path)
+	}
+
+	/// Hashes two nodes of the tree with the `Hasher` of the pallet and the
+	/// hash function of the tree. The Poseidon parameters are ignored by the
+	/// other hash functions.
 	pub fn hash(hasher: HashFunction, left: Scalar, right: Scalar, hash_params: &Poseidon) -> Scalar {
-		match hasher {
-			HashFunction::PoseidonDefault => Poseidon_hash_2(left, right, hash_params),
-			_ => Poseidon_hash_2(left, right, hash_params),
+		T::Hasher::hash_two(&hasher, ScalarData(left), ScalarData(right), hash_params).0
+	}
+
+	/// Turns the inputs into the leaves of the tree, hashing each of them with
+	/// the `Hasher` of the pallet if the tree hashes the leaves on insert.
+	/// Otherwise the inputs are the leaves themselves.
+	pub fn input_leaves(
+		tree_id: T::TreeId,
+		hasher: HashFunction,
+		inputs: Vec<ScalarData>,
+		hash_params: &Poseidon,
+	) -> Vec<ScalarData> {
+		if !Self::hash_leaf_on_insert(tree_id) {
+			return inputs;
 		}
+		inputs
+			.into_iter()
+			.map(|input| ScalarData(Self::hash_leaf(hasher.clone(), input.0, hash_params)))
+			.collect()
 	}
 
+	/// Hashes the input into the leaf stored in the tree with the `Hasher` of
+	/// the pallet. The stored leaves are never hashed again.
+	pub fn hash_leaf(hasher: HashFunction, leaf: Scalar, hash_params: &Poseidon) -> Scalar {
+		T::Hasher::hash_leaf(&hasher, ScalarData(leaf), hash_params).0
+	}
+
+	/// Generates the roots of the empty subtrees of each depth, up to the
+	/// depth of 32, with the `Hasher` of the pallet.
 	pub fn generate_zero_tree(hasher: HashFunction, hash_params: &Poseidon) -> Vec<[u8; 32]> {
-		match hasher {
-			HashFunction::PoseidonDefault => gen_zero_tree(hash_params.width, &hash_params.sbox),
-			_ => gen_zero_tree(hash_params.width, &hash_params.sbox),
-		}
+		T::Hasher::zero_tree(&hasher, hash_params)
 	}
 
 	pub fn get_poseidon_hasher_for_tree(id: T::TreeId) -> Result<Poseidon, dispatch::DispatchError> {
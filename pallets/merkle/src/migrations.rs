@@ -0,0 +1,236 @@
+//! Storage migrations for the merkle pallet
+use super::*;
+
+/// Builds the intermediate nodes of the existing trees, which weren't kept
+/// in the storage before `V2_0_0`. The trees are migrated lazily in id
+/// order, level by level from the leaves up, and `MigrationCursor` keeps the
+/// next node to build in between the calls. A call stops once building the
+/// next node would go over `max_weight`, though it always builds at least one
+/// node so the migration goes on. The storage version is only bumped once
+/// every tree is migrated.
+pub fn migrate_to_v2<T: Config>(max_weight: Weight) -> Weight {
+	let db_weight = T::DbWeight::get();
+	if StorageVersion::<T>::get() != Releases::V1_0_0 {
+		return db_weight.reads(1);
+	}
+
+	// the parameters of the hash function are loaded once per tree, and every
+	// node reads its two children
+	let hasher_weight = T::WeightInfo::verify_path(0);
+	let node_weight = T::WeightInfo::verify_path(1)
+		.saturating_sub(hasher_weight)
+		.saturating_add(db_weight.reads_writes(2, 1));
+	let mut weight = db_weight.reads_writes(3, 1);
+	let mut built = 0;
+	let next_tree_id = NextTreeId::<T>::get();
+	let (mut tree_id, mut level, mut index) = MigrationCursor::<T>::get();
+	'trees: while tree_id < next_tree_id {
+		weight = weight.saturating_add(db_weight.reads(2));
+		let tree = Trees::<T>::get(tree_id)
+			.filter(|tree| tree.initialized && tree.should_store_leaves && !Pallet::<T>::pruned(tree_id));
+		if let Some(tree) = tree {
+			if built > 0 && weight.saturating_add(hasher_weight).saturating_add(node_weight) > max_weight {
+				break;
+			}
+			if let Ok(hash_params) = Pallet::<T>::get_poseidon_hasher_for_tree(tree_id) {
+				weight = weight.saturating_add(hasher_weight);
+				let zero_tree = Pallet::<T>::generate_zero_tree(tree.hasher.clone(), &hash_params);
+				// the leaves are kept already, the nodes start at the level above
+				level = level.max(1);
+				while level < tree.depth {
+					let zero_h = Scalar::from_bytes_mod_order(zero_tree[level as usize - 1]);
+					let child = |child_index: u32| {
+						if level > 1 {
+							Nodes::<T>::get(tree_id, (level - 1, child_index)).map_or(zero_h, |node| node.0)
+						} else if child_index < tree.leaf_count {
+							Leaves::<T>::get(tree_id, child_index).0
+						} else {
+							zero_h
+						}
+					};
+					// the nodes above the leaves, the others are the roots of
+					// empty subtrees
+					let width = ((tree.leaf_count as u64 + (1u64 << level) - 1) >> level) as u32;
+					while index < width {
+						if built > 0 && weight.saturating_add(node_weight) > max_weight {
+							break 'trees;
+						}
+						let node = Pallet::<T>::hash(
+							tree.hasher.clone(),
+							child(2 * index),
+							child(2 * index + 1),
+							&hash_params,
+						);
+						Nodes::<T>::insert(tree_id, (level, index), Some(ScalarData(node)));
+						weight = weight.saturating_add(node_weight);
+						built += 1;
+						index += 1;
+					}
+					level += 1;
+					index = 0;
+				}
+				// the nodes of the tree can be cached once it's migrated
+				Pallet::<T>::mark_changed(tree_id, 0, tree.root_hash);
+				weight = weight.saturating_add(db_weight.reads_writes(1, 1));
+			}
+		}
+		tree_id += One::one();
+		level = 0;
+		index = 0;
+	}
+	if tree_id < next_tree_id {
+		MigrationCursor::<T>::put((tree_id, level, index));
+	} else {
+		MigrationCursor::<T>::kill();
+		StorageVersion::<T>::put(Releases::V2_0_0);
+		weight = weight.saturating_add(db_weight.writes(1));
+	}
+
+	weight
+}
+
+/// Sets the capacity of the existing trees from their depth again. The trees
+/// created before the capacity only depended on their own depth were given
+/// `u32::MAX >> (MaxTreeDepth - depth)` leaves, one leaf short of what they
+/// hold when the deepest depth allowed is 32, and more leaves than they hold
+/// when it's less. Only the trees whose capacity is off are written.
+pub fn backfill_max_leaves<T: Config>() -> Weight {
+	let mut reads: Weight = 1;
+	let mut writes: Weight = 0;
+	let next_tree_id = NextTreeId::<T>::get();
+	let mut tree_id: T::TreeId = Zero::zero();
+	while tree_id < next_tree_id {
+		reads += 1;
+		if let Some(mut tree) = Trees::<T>::get(tree_id) {
+			let max_leaves = MerkleTree::capacity(tree.depth);
+			if tree.max_leaves != max_leaves {
+				tree.max_leaves = max_leaves;
+				Trees::<T>::insert(tree_id, Some(tree));
+				writes += 1;
+			}
+		}
+		tree_id += One::one();
+	}
+
+	T::DbWeight::get().reads_writes(reads, writes)
+}
+
+/// Lays out the root history of every tree again when `RootHistorySize`
+/// changes, keeping the most recent roots which fit into the new size. The
+/// oldest roots are dropped when the size shrinks.
+pub fn resize_root_history<T: Config>() -> Weight {
+	let old_size = RootHistoryLength::<T>::get();
+	let new_size = T::RootHistorySize::get();
+	if old_size == new_size {
+		return T::DbWeight::get().reads(1);
+	}
+
+	let mut reads: Weight = 1;
+	let mut writes: Weight = 1;
+	let root_counts: Vec<(T::TreeId, u32)> = RootCount::<T>::iter().collect();
+	for (tree_id, root_count) in root_counts {
+		let kept = root_count.min(old_size).min(new_size);
+		// the most recent root goes last, so it's still the current one in the
+		// new layout
+		let roots: Vec<ScalarData> = (0..kept)
+			.rev()
+			.map(|i| RootHistory::<T>::get(tree_id, (root_count - 1 - i) % old_size))
+			.collect();
+		RootHistory::<T>::remove_prefix(tree_id);
+		for (position, root) in roots.into_iter().enumerate() {
+			RootHistory::<T>::insert(tree_id, position as u32, root);
+		}
+		RootCount::<T>::insert(tree_id, kept);
+		reads += 1 + kept as Weight;
+		writes += 1 + (root_count.min(old_size) + kept) as Weight;
+	}
+	RootHistoryLength::<T>::put(new_size);
+
+	T::DbWeight::get().reads_writes(reads, writes)
+}
+
+/// Storage items the configuration of each tree was kept in before it was
+/// folded into `TreeConfigs`
+const OLD_CONFIG_ITEMS: [&[u8]; 10] = [
+	b"Denominations",
+	b"TreeAssets",
+	b"CommitmentVersions",
+	b"AppendOnly",
+	b"MinAnonymitySets",
+	b"HashLeafOnInsert",
+	b"OnFullBehaviors",
+	b"Successors",
+	b"PublicInputLayouts",
+	b"Pruned",
+];
+
+/// Moves the configuration of the existing trees from the maps it was kept in,
+/// one per setting, into `TreeConfigs`. The settings a tree has no item for
+/// keep their defaults, so the old trees stay append-only. The old items are
+/// removed as they're moved, so the migration has nothing left to do on the
+/// next upgrades.
+pub fn migrate_tree_configs<T: Config>() -> Weight {
+	use frame_support::{
+		storage::migration::{storage_iter, take_storage_item},
+		traits::PalletInfo,
+		Blake2_128Concat,
+	};
+
+	let db_weight = T::DbWeight::get();
+	let module = <T as frame_system::Config>::PalletInfo::name::<Pallet<T>>()
+		.unwrap_or("MerkleTrees")
+		.as_bytes();
+	if OLD_CONFIG_ITEMS
+		.iter()
+		.all(|item| storage_iter::<()>(module, item).next().is_none())
+	{
+		return db_weight.reads(OLD_CONFIG_ITEMS.len() as Weight);
+	}
+
+	let mut reads = OLD_CONFIG_ITEMS.len() as Weight + 1;
+	let mut writes = 0;
+	let next_tree_id = NextTreeId::<T>::get();
+	let mut tree_id: T::TreeId = Zero::zero();
+	while tree_id < next_tree_id {
+		let defaults = TreeConfigOf::<T>::default();
+		let config = TreeConfig {
+			denomination: take_storage_item::<_, Option<BalanceOf<T>>, Blake2_128Concat>(
+				module,
+				b"Denominations",
+				tree_id,
+			)
+			.unwrap_or(defaults.denomination),
+			asset_id: take_storage_item::<_, Option<T::AssetId>, Blake2_128Concat>(module, b"TreeAssets", tree_id)
+				.unwrap_or(defaults.asset_id),
+			commitment_version: take_storage_item::<_, u16, Blake2_128Concat>(module, b"CommitmentVersions", tree_id)
+				.unwrap_or(defaults.commitment_version),
+			append_only: take_storage_item::<_, bool, Blake2_128Concat>(module, b"AppendOnly", tree_id)
+				.unwrap_or(defaults.append_only),
+			min_anonymity_set: take_storage_item::<_, u32, Blake2_128Concat>(module, b"MinAnonymitySets", tree_id)
+				.unwrap_or(defaults.min_anonymity_set),
+			hash_leaf_on_insert: take_storage_item::<_, bool, Blake2_128Concat>(module, b"HashLeafOnInsert", tree_id)
+				.unwrap_or(defaults.hash_leaf_on_insert),
+			on_full: take_storage_item::<_, OnFull, Blake2_128Concat>(module, b"OnFullBehaviors", tree_id)
+				.unwrap_or(defaults.on_full),
+			successor: take_storage_item::<_, Option<T::TreeId>, Blake2_128Concat>(module, b"Successors", tree_id)
+				.unwrap_or(defaults.successor),
+			public_input_layout: take_storage_item::<_, Vec<PublicInput>, Blake2_128Concat>(
+				module,
+				b"PublicInputLayouts",
+				tree_id,
+			)
+			.unwrap_or(defaults.public_input_layout),
+			pruned: take_storage_item::<_, bool, Blake2_128Concat>(module, b"Pruned", tree_id)
+				.unwrap_or(defaults.pruned),
+		};
+		if Trees::<T>::contains_key(tree_id) {
+			TreeConfigs::<T>::insert(tree_id, config);
+			writes += 1;
+		}
+		reads += OLD_CONFIG_ITEMS.len() as Weight + 1;
+		writes += OLD_CONFIG_ITEMS.len() as Weight;
+		tree_id += One::one();
+	}
+
+	db_weight.reads_writes(reads, writes)
+}
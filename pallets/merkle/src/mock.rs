@@ -1,6 +1,6 @@
 use super::*;
 use crate as pallet_merkle;
-use frame_support::{construct_runtime, parameter_types, weights::Weight};
+use frame_support::{construct_runtime, parameter_types, traits::GenesisBuild, weights::Weight, PalletId};
 use frame_system as system;
 use pallet_merkle::weights::Weights;
 use sp_core::H256;
@@ -10,7 +10,10 @@ use sp_runtime::{
 	Perbill,
 };
 use system::mocking::{MockBlock, MockUncheckedExtrinsic};
+use webb_currencies::BasicCurrencyAdapter;
 pub(crate) type Balance = u64;
+pub type Amount = i128;
+pub type CurrencyId = u64;
 
 // Configure a mock runtime to test the pallet.
 type UncheckedExtrinsic = MockUncheckedExtrinsic<Test>;
@@ -25,7 +28,9 @@ construct_runtime!(
 		System: system::{Pallet, Call, Config, Storage, Event<T>},
 		Balances: balances::{Pallet, Call, Storage, Config<T>, Event<T>},
 		Randomness: pallet_randomness_collective_flip::{Pallet, Call, Storage},
-		MerkleTrees: pallet_merkle::{Pallet, Call, Storage, Event<T>},
+		MerkleTrees: pallet_merkle::{Pallet, Call, Storage, Event<T>, Config<T>},
+		Currencies: webb_currencies::{Pallet, Storage, Event<T>},
+		Tokens: webb_tokens::{Pallet, Storage, Event<T>},
 	}
 );
 
@@ -68,6 +73,11 @@ parameter_types! {
 	pub const MaxLocks: u32 = 50;
 	pub const MaxTreeDepth: u8 = 32;
 	pub const CacheBlockLength: u64 = 5;
+	pub const MaxTrees: u32 = 8;
+	pub const MaxTreeNameLength: u32 = 32;
+	pub const MaxLeavesPerCall: u32 = 32;
+	pub const RootHistorySize: u32 = 30;
+	pub const MerklePalletId: PalletId = PalletId(*b"py/merkl");
 	pub const MinimumDepositLength: u64 = 10;
 }
 
@@ -81,12 +91,58 @@ impl balances::Config for Test {
 	type WeightInfo = ();
 }
 
+parameter_types! {
+	pub const NativeCurrencyId: CurrencyId = 0;
+	pub const TokensPalletId: PalletId = PalletId(*b"py/token");
+	pub const CurrencyDeposit: u64 = 0;
+	pub const ApprovalDeposit: u64 = 1;
+	pub const StringLimit: u32 = 50;
+	pub const MetadataDepositBase: u64 = 1;
+	pub const MetadataDepositPerByte: u64 = 1;
+	pub DustAccount: u64 = PalletId(*b"webb/dst").into_account();
+}
+
+impl webb_tokens::Config for Test {
+	type Amount = Amount;
+	type ApprovalDeposit = ApprovalDeposit;
+	type Balance = Balance;
+	type CurrencyDeposit = CurrencyDeposit;
+	type CurrencyId = CurrencyId;
+	type DustAccount = DustAccount;
+	type Event = Event;
+	type Extra = ();
+	type ForceOrigin = frame_system::EnsureRoot<u64>;
+	type MetadataDepositBase = MetadataDepositBase;
+	type MetadataDepositPerByte = MetadataDepositPerByte;
+	type NativeCurrency = BasicCurrencyAdapter<Test, Balances, Amount, u64>;
+	type PalletId = TokensPalletId;
+	type StringLimit = StringLimit;
+	type WeightInfo = ();
+}
+
+impl webb_currencies::Config for Test {
+	type Event = Event;
+	type GetNativeCurrencyId = NativeCurrencyId;
+	type MultiCurrency = Tokens;
+	type NativeCurrency = BasicCurrencyAdapter<Test, Balances, Amount, u64>;
+	type WeightInfo = ();
+}
+
 impl Config for Test {
+	type AssetId = CurrencyId;
+	type Assets = Currencies;
 	type CacheBlockLength = CacheBlockLength;
+	type DepositCurrency = Balances;
+	type DepositPalletId = MerklePalletId;
 	type Event = Event;
+	type Hasher = DefaultHasher;
 	type KeyId = u32;
+	type MaxLeavesPerCall = MaxLeavesPerCall;
 	type MaxTreeDepth = MaxTreeDepth;
+	type MaxTreeNameLength = MaxTreeNameLength;
+	type MaxTrees = MaxTrees;
 	type Randomness = Randomness;
+	type RootHistorySize = RootHistorySize;
 	type TreeId = u32;
 	type WeightInfo = Weights<Self>;
 }
@@ -95,5 +151,10 @@ pub type MerkleCall = pallet_merkle::Call<Test>;
 
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
-	system::GenesisConfig::default().build_storage::<Test>().unwrap().into()
+	let mut t = system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	// the trees of the tests are created at the current storage version
+	pallet_merkle::GenesisConfig::<Test>::default()
+		.assimilate_storage(&mut t)
+		.unwrap();
+	t.into()
 }
@@ -0,0 +1,143 @@
+//! Off-chain cache of the nodes of the merkle trees
+use super::*;
+use sp_runtime::offchain::storage::StorageValueRef;
+
+/// Prefix of the keys of the state of each tree cached off-chain
+pub const TREE_KEY_PREFIX: &[u8] = b"merkle::offchain::tree";
+/// Prefix of the keys of the nodes cached off-chain
+pub const NODE_KEY_PREFIX: &[u8] = b"merkle::offchain::node";
+
+/// Key of the state of the tree with cached nodes
+pub fn tree_key<TreeId: Encode>(tree_id: &TreeId) -> Vec<u8> {
+	let mut key = TREE_KEY_PREFIX.to_vec();
+	tree_id.using_encoded(|bytes| key.extend_from_slice(bytes));
+	key
+}
+
+/// Key of the node of the tree at the level and index, the leaves are the
+/// nodes of level 0
+pub fn node_key<TreeId: Encode>(tree_id: &TreeId, level: u8, index: u32) -> Vec<u8> {
+	let mut key = NODE_KEY_PREFIX.to_vec();
+	(tree_id, level, index).using_encoded(|bytes| key.extend_from_slice(bytes));
+	key
+}
+
+/// State of a tree as cached off-chain, along with its nodes stored under
+/// `node_key`, from which the membership proofs are built without calling
+/// into the runtime
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq)]
+pub struct CachedTree {
+	/// Root the cached nodes hash to
+	pub root: ScalarData,
+	/// Number of leaves of the tree when the nodes were cached
+	pub leaf_count: u32,
+	/// Roots of the empty subtrees of each level below the root, which stand
+	/// in for the unfilled positions
+	pub default_nodes: Vec<ScalarData>,
+}
+
+impl CachedTree {
+	/// Number of the filled nodes of the level, every node of which covers
+	/// `2^level` leaves
+	pub fn filled(&self, level: u8) -> u32 {
+		((self.leaf_count as u64 + (1u64 << level) - 1) >> level) as u32
+	}
+
+	/// Builds the membership proof for the leaf at `leaf_index`, in the same
+	/// format as `get_membership_proof`, reading the filled siblings with
+	/// `read_node`. `None` if the leaf wasn't in the tree when the nodes were
+	/// cached, or one of its siblings can't be read.
+	pub fn membership_proof(
+		&self,
+		leaf_index: u32,
+		read_node: impl Fn(u8, u32) -> Option<ScalarData>,
+	) -> Option<Vec<(bool, ScalarData)>> {
+		if leaf_index >= self.leaf_count {
+			return None;
+		}
+		let mut index = leaf_index;
+		let mut path = Vec::with_capacity(self.default_nodes.len());
+		for (level, default_node) in self.default_nodes.iter().enumerate() {
+			let level = level as u8;
+			let sibling = match index ^ 1 < self.filled(level) {
+				true => read_node(level, index ^ 1)?,
+				false => *default_node,
+			};
+			path.push((index % 2 == 0, sibling));
+			index /= 2;
+		}
+		Some(path)
+	}
+}
+
+impl<T: Config> Pallet<T> {
+	/// Gets the state of the tree to cache, `None` if the tree isn't
+	/// initialized, its leaves aren't stored or its nodes aren't migrated yet
+	pub fn get_cached_tree(tree_id: T::TreeId) -> Option<CachedTree> {
+		let tree = Trees::<T>::get(tree_id).filter(|tree| tree.initialized && tree.should_store_leaves)?;
+		if Self::pruned(tree_id) || !Self::is_migrated(tree_id) {
+			return None;
+		}
+		let root = tree.root_hash?;
+		let hash_params = Self::get_poseidon_hasher_for_tree(tree_id).ok()?;
+		let zero_tree = Self::generate_zero_tree(tree.hasher.clone(), &hash_params);
+		let default_nodes = zero_tree[..tree.depth as usize]
+			.iter()
+			.map(|node| ScalarData(Scalar::from_bytes_mod_order(*node)))
+			.collect();
+		Some(CachedTree {
+			root,
+			leaf_count: tree.leaf_count,
+			default_nodes,
+		})
+	}
+}
+
+/// Caches the nodes of the trees whose leaves changed in the current block.
+/// Only the nodes above the changed leaves are written, as long as the cached
+/// nodes are the ones of the root the tree had before the block; otherwise,
+/// the worker didn't run for some block or ran on another fork, so every node
+/// of the tree is written again. A tree is first cached in the first block
+/// its leaves change in while the worker runs.
+pub fn cache_trees<T: Config>() {
+	for (tree_id, (from, root_before)) in ChangedLeaves::<T>::iter() {
+		cache_tree::<T>(tree_id, from, root_before);
+	}
+}
+
+fn cache_tree<T: Config>(tree_id: T::TreeId, from: u32, root_before: Option<ScalarData>) {
+	let mut cached_tree = StorageValueRef::persistent(&tree_key(&tree_id));
+	let previous = cached_tree.get::<CachedTree>().flatten();
+	// the state is cleared before the nodes are written, so the proofs aren't
+	// built from the nodes of two roots
+	cached_tree.clear();
+	let tree = match Pallet::<T>::get_cached_tree(tree_id) {
+		Some(tree) => tree,
+		None => {
+			if let Some(previous) = previous {
+				for level in 0..previous.default_nodes.len() as u8 {
+					for index in 0..previous.filled(level) {
+						StorageValueRef::persistent(&node_key(&tree_id, level, index)).clear();
+					}
+				}
+			}
+			return;
+		}
+	};
+	let from = match previous {
+		Some(previous) if Some(previous.root) == root_before => from.min(previous.leaf_count),
+		_ => 0,
+	};
+	for level in 0..tree.default_nodes.len() as u8 {
+		let default_node = tree.default_nodes[level as usize];
+		for index in (from >> level)..tree.filled(level) {
+			let node = match level {
+				0 => Leaves::<T>::get(tree_id, index),
+				_ => Nodes::<T>::get(tree_id, (level, index)).unwrap_or(default_node),
+			};
+			StorageValueRef::persistent(&node_key(&tree_id, level, index)).set(&node);
+		}
+	}
+	cached_tree.set(&tree);
+}
@@ -1,6 +1,6 @@
 use super::*;
 use crate::{
-	mock::*,
+	mock::{Event, *},
 	utils::keys::{from_bytes_to_bp_gens, get_bp_gen_bytes, Commitment, ScalarData},
 };
 use bulletproofs::{r1cs::Prover, BulletproofGens, PedersenGens};
@@ -13,11 +13,18 @@ use bulletproofs_gadgets::{
 	smt::gen_zero_tree,
 };
 use curve25519_dalek::{ristretto::RistrettoPoint, scalar::Scalar};
-use frame_support::{assert_err, assert_ok, traits::UnfilteredDispatchable};
+use frame_support::{
+	assert_err, assert_ok,
+	storage::migration::{have_storage_value, put_storage_value},
+	traits::{GenesisBuild, Hooks, UnfilteredDispatchable},
+	Blake2_128Concat, StorageHasher,
+};
 use frame_system::RawOrigin;
 use merlin::Transcript;
 use rand_chacha::ChaChaRng;
-use sp_runtime::traits::BadOrigin;
+use sp_core::offchain::{testing::TestOffchainExt, OffchainExt};
+use sp_runtime::{offchain::storage::StorageValueRef, traits::BadOrigin};
+use webb_tokens::ExtendedTokenSystem;
 
 fn key_bytes(x: u8) -> [u8; 32] {
 	[
@@ -36,14 +43,42 @@ fn default_hasher(num_gens: usize) -> Poseidon {
 #[test]
 fn can_create_tree() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+	});
+}
+
+#[test]
+fn should_emit_tree_created_event() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			hasher: Some(HashFunction::Blake2),
+			..Default::default()
+		}));
+
+		let metadata = MerkleTrees::get_tree_metadata(0).unwrap();
+		let manager = MerkleTrees::get_manager(0).unwrap();
+		let created_event = Event::pallet_merkle(crate::Event::TreeCreated(
+			0,
+			metadata.depth,
+			metadata.hasher,
+			manager.account_id,
+		));
+		assert!(System::events().iter().any(|record| record.event == created_event));
 	});
 }
 
 #[test]
 fn can_update_manager_when_required() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), true, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			manager_required: true,
+			depth: Some(3),
+			..Default::default()
+		}));
 
 		assert_ok!(MerkleTrees::set_manager(Origin::signed(1), 0, 2,));
 
@@ -55,7 +90,10 @@ fn can_update_manager_when_required() {
 #[test]
 fn can_update_manager_when_not_required() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 
 		assert_ok!(MerkleTrees::set_manager(Origin::signed(1), 0, 2,));
 
@@ -67,7 +105,10 @@ fn can_update_manager_when_not_required() {
 #[test]
 fn cannot_update_manager_as_not_manager() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 
 		assert_err!(MerkleTrees::set_manager(Origin::signed(2), 0, 2,), BadOrigin);
 	});
@@ -76,7 +117,10 @@ fn cannot_update_manager_as_not_manager() {
 #[test]
 fn can_update_manager_required_manager() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 
 		assert_ok!(MerkleTrees::set_manager_required(Origin::signed(1), 0, true,));
 
@@ -88,7 +132,10 @@ fn can_update_manager_required_manager() {
 #[test]
 fn cannot_update_manager_required_as_not_manager() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 
 		assert_err!(
 			MerkleTrees::set_manager_required(Origin::signed(2), 0, true,),
@@ -101,7 +148,10 @@ fn cannot_update_manager_required_as_not_manager() {
 fn can_add_member() {
 	new_test_ext().execute_with(|| {
 		let key = ScalarData::from(key_bytes(1));
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
@@ -116,7 +166,11 @@ fn can_add_member_as_manager() {
 	new_test_ext().execute_with(|| {
 		let key = ScalarData::from(key_bytes(1));
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), true, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			manager_required: true,
+			depth: Some(3),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
@@ -131,7 +185,11 @@ fn cannot_add_member_as_not_manager() {
 	new_test_ext().execute_with(|| {
 		let key = ScalarData::from(key_bytes(1));
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), true, Some(3)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			manager_required: true,
+			depth: Some(3),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
@@ -147,7 +205,11 @@ fn cannot_add_member_as_not_manager() {
 #[test]
 fn should_be_able_to_set_stopped_merkle() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), true, Some(1)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			manager_required: true,
+			depth: Some(1),
+			..Default::default()
+		}));
 		assert_ok!(MerkleTrees::set_stopped(Origin::signed(1), 0, true));
 
 		// stopping merkle, stopped == true
@@ -163,212 +225,1091 @@ fn should_be_able_to_set_stopped_merkle() {
 }
 
 #[test]
-fn should_be_able_to_change_manager_with_root() {
+fn should_not_add_leaves_to_stopped_tree() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), true, Some(3)));
-		let call = Box::new(MerkleCall::set_manager(0, 2));
-		let res = call.dispatch_bypass_filter(RawOrigin::Root.into());
-		assert_ok!(res);
-		let mng = MerkleTrees::get_manager(0).unwrap();
-		assert_eq!(mng.account_id, 2);
+		System::set_block_number(1);
+		let key = ScalarData::from(key_bytes(1));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
 
-		let call = Box::new(MerkleCall::set_manager(0, 3));
-		let res = call.dispatch_bypass_filter(RawOrigin::Signed(0).into());
-		assert_err!(res, BadOrigin);
-	})
+		assert_err!(MerkleTrees::stop_tree(Origin::signed(2), 0), BadOrigin);
+		assert_ok!(MerkleTrees::stop_tree(Origin::signed(1), 0));
+		assert!(MerkleTrees::stopped(0));
+		let stopped_event = Event::pallet_merkle(crate::Event::TreeStopped(0));
+		assert!(System::events().iter().any(|record| record.event == stopped_event));
+		assert_err!(
+			MerkleTrees::add_members(Origin::signed(1), 0, vec![key]),
+			Error::<Test>::TreeIsStopped
+		);
+		assert_err!(
+			MerkleTrees::add_leaves(Origin::signed(1), 0, vec![key]),
+			Error::<Test>::TreeIsStopped
+		);
+
+		assert_ok!(MerkleTrees::resume_tree(Origin::signed(1), 0));
+		assert!(!MerkleTrees::stopped(0));
+		let resumed_event = Event::pallet_merkle(crate::Event::TreeResumed(0));
+		assert!(System::events().iter().any(|record| record.event == resumed_event));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key]));
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 0, vec![key]));
+	});
 }
 
 #[test]
-fn should_not_have_0_depth() {
+fn should_prune_stopped_tree() {
 	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let key0 = ScalarData::from(key_bytes(1));
+		let key1 = ScalarData::from(key_bytes(2));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key0, key1]));
+		let root = MerkleTrees::trees(0).unwrap().root_hash;
+		let path = MerkleTrees::get_membership_proof(0, 0).unwrap();
+
 		assert_err!(
-			MerkleTrees::create_tree(Origin::signed(1), false, Some(0)),
-			Error::<Test>::InvalidTreeDepth,
+			MerkleTrees::prune_tree(Origin::signed(1), 0),
+			Error::<Test>::TreeIsNotStopped
+		);
+		assert_ok!(MerkleTrees::stop_tree(Origin::signed(1), 0));
+		assert_err!(MerkleTrees::prune_tree(Origin::signed(2), 0), BadOrigin);
+		assert_ok!(MerkleTrees::prune_tree(Origin::signed(1), 0));
+		assert!(MerkleTrees::pruned(0));
+		let pruned_event = Event::pallet_merkle(crate::Event::TreePruned(0));
+		assert!(System::events().iter().any(|record| record.event == pruned_event));
+
+		// the leaves and the nodes are gone, the root and the metadata are kept
+		assert_eq!(MerkleTrees::get_leaves_range(0, 0, 2), vec![None, None]);
+		assert_eq!(Nodes::<Test>::iter_prefix(0).count(), 0);
+		assert_eq!(MerkleTrees::trees(0).unwrap().root_hash, root);
+		assert!(MerkleTrees::get_tree_metadata(0).unwrap().is_pruned);
+		assert_ok!(MerkleTrees::verify(Origin::signed(2), 0, key0, path));
+		assert_err!(MerkleTrees::get_membership_proof(0, 0), Error::<Test>::TreeIsPruned);
+
+		assert_err!(
+			MerkleTrees::prune_tree(Origin::signed(1), 0),
+			Error::<Test>::TreeIsPruned
+		);
+		assert_err!(
+			MerkleTrees::resume_tree(Origin::signed(1), 0),
+			Error::<Test>::TreeIsPruned
 		);
 	});
 }
 
 #[test]
-fn should_have_min_depth() {
+fn should_emit_index_of_added_leaf() {
 	new_test_ext().execute_with(|| {
-		let key = ScalarData::from(key_bytes(1));
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(1)));
-		let tree_id = 0;
+		System::set_block_number(1);
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key.clone()]));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+
+		let key0 = ScalarData::from(key_bytes(1));
+		let key1 = ScalarData::from(key_bytes(2));
+		assert_ok!(MerkleTrees::add_leaf(Origin::signed(2), 0, key0.to_bytes(), 0));
+		assert_ok!(MerkleTrees::add_leaf(Origin::signed(2), 0, key1.to_bytes(), 0));
+
+		let event0 = Event::pallet_merkle(crate::Event::LeafAdded(0, 0, key0));
+		let event1 = Event::pallet_merkle(crate::Event::LeafAdded(0, 1, key1));
+		assert!(System::events().iter().any(|record| record.event == event0));
+		assert!(System::events().iter().any(|record| record.event == event1));
+		assert_eq!(MerkleTrees::leaves(0, 1), key1);
+	});
+}
+
+#[test]
+fn should_not_add_non_canonical_leaf() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+
+		// Above the group order, so it isn't a canonical scalar
 		assert_err!(
-			MerkleTrees::add_members(Origin::signed(1), 0, vec![key.clone()]),
-			Error::<Test>::ExceedsMaxLeaves,
+			MerkleTrees::add_leaf(Origin::signed(1), 0, [0xff; 32], 0),
+			Error::<Test>::InvalidLeaf
 		);
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaf_count, 0);
 	});
 }
 
 #[test]
-fn should_have_max_depth() {
+fn should_get_leaves_range() {
 	new_test_ext().execute_with(|| {
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(32)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let keys = vec![ScalarData::from(key_bytes(1)), ScalarData::from(key_bytes(2))];
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+
+		assert_eq!(MerkleTrees::get_leaf_count(0), Some(2));
+		assert_eq!(MerkleTrees::get_leaf_count(1), None);
+
+		// The range is clamped to the number of leaves
+		assert_eq!(MerkleTrees::get_leaves_range(0, 0, 5), vec![
+			Some(keys[0]),
+			Some(keys[1])
+		]);
+		assert_eq!(MerkleTrees::get_leaves_range(0, 1, 2), vec![Some(keys[1])]);
+		assert_eq!(MerkleTrees::get_leaves_range(0, 2, 5), vec![]);
+		assert_eq!(MerkleTrees::get_leaves_range(1, 0, 5), vec![]);
 	});
 }
 
 #[test]
-fn should_not_have_more_than_max_depth() {
+fn should_get_leaves_checksum() {
 	new_test_ext().execute_with(|| {
-		assert_err!(
-			MerkleTrees::create_tree(Origin::signed(1), false, Some(33)),
-			Error::<Test>::InvalidTreeDepth,
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		assert_eq!(MerkleTrees::get_leaves_checksum(0, 0, 5), None);
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let keys = vec![ScalarData::from(key_bytes(1)), ScalarData::from(key_bytes(2))];
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+
+		let tree = MerkleTrees::get_tree(0).unwrap();
+		let hash_params = MerkleTrees::get_poseidon_hasher_for_tree(0).unwrap();
+		let first = MerkleTrees::hash(tree.hasher.clone(), Scalar::zero(), keys[0].0, &hash_params);
+		let both = MerkleTrees::hash(tree.hasher.clone(), first, keys[1].0, &hash_params);
+
+		// The range is clamped to the number of leaves
+		assert_eq!(MerkleTrees::get_leaves_checksum(0, 0, 5), Some(ScalarData(both)));
+		assert_eq!(MerkleTrees::get_leaves_checksum(0, 0, 1), Some(ScalarData(first)));
+		assert_eq!(MerkleTrees::get_leaves_checksum(0, 2, 5), Some(ScalarData::zero()));
+		assert_ne!(
+			MerkleTrees::get_leaves_checksum(0, 1, 2),
+			MerkleTrees::get_leaves_checksum(0, 0, 1)
 		);
+		assert_eq!(MerkleTrees::get_leaves_checksum(1, 0, 5), None);
 	});
 }
 
 #[test]
-fn should_have_correct_root_hash_after_insertion() {
+fn should_reject_leaf_of_other_commitment_version() {
 	new_test_ext().execute_with(|| {
-		let h = default_hasher(4096);
-		let zero_tree = gen_zero_tree(h.width, &h.sbox);
-		let key0 = ScalarData::from(key_bytes(0));
-		let key1 = ScalarData::from(key_bytes(1));
-		let key2 = ScalarData::from(key_bytes(2));
-		let zero_h0 = ScalarData::from(zero_tree[0]);
-		let zero_h1 = ScalarData::from(zero_tree[1]);
-
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(2)));
-		let tree_id = 0;
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			commitment_version: 2,
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key0.clone()]));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().commitment_version, 2);
 
-		let keyh1 = Poseidon_hash_2(key0.0, zero_h0.0, &h);
-		let keyh2 = Poseidon_hash_2(keyh1, zero_h1.0, &h);
+		let leaf = ScalarData::from(key_bytes(1));
+		assert_err!(
+			MerkleTrees::add_leaf(Origin::signed(1), 0, leaf.to_bytes(), 1),
+			Error::<Test>::CommitmentVersionMismatch
+		);
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaf_count, 0);
+		assert_ok!(MerkleTrees::add_leaf(Origin::signed(1), 0, leaf.to_bytes(), 2));
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaf_count, 1);
+	});
+}
 
-		let tree = MerkleTrees::trees(0).unwrap();
+#[test]
+fn should_update_leaf() {
+	new_test_ext().execute_with(|| {
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		for _ in 0..2 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				append_only: false,
+				..Default::default()
+			}));
+		}
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 1, 0));
+		let keys = vec![
+			ScalarData::from(key_bytes(1)),
+			ScalarData::from(key_bytes(2)),
+			ScalarData::from(key_bytes(3)),
+		];
+		let new_leaf = ScalarData::from(key_bytes(4));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 1, vec![
+			keys[0], new_leaf, keys[2]
+		]));
 
-		assert_eq!(tree.root_hash.unwrap().0, keyh2, "Invalid root hash");
+		assert_err!(
+			MerkleTrees::update_leaf(Origin::signed(2), 0, 1, new_leaf.to_bytes()),
+			BadOrigin
+		);
+		assert_err!(
+			MerkleTrees::update_leaf(Origin::signed(1), 0, 3, new_leaf.to_bytes()),
+			Error::<Test>::LeafDoesntExist
+		);
+		assert_ok!(MerkleTrees::update_leaf(Origin::signed(1), 0, 1, new_leaf.to_bytes()));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+		assert_eq!(root, MerkleTrees::get_merkle_root(1).unwrap());
+		assert!(MerkleTrees::is_known_root(0, root));
+		assert_eq!(MerkleTrees::get_leaves_range(0, 0, 3)[1], Some(new_leaf));
+		assert_eq!(MerkleTrees::leaf_index(0, new_leaf), Some(1));
+		assert_eq!(MerkleTrees::leaf_index(0, keys[1]), None);
+		assert_eq!(
+			MerkleTrees::get_membership_proof(0, 2).unwrap(),
+			MerkleTrees::get_membership_proof(1, 2).unwrap()
+		);
 
-		assert_ok!(MerkleTrees::add_members(Origin::signed(2), 0, vec![key1.clone()]));
+		// the leaves added after the update are placed on the updated nodes
+		let key = ScalarData::from(key_bytes(5));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key]));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 1, vec![key]));
+		assert_eq!(
+			MerkleTrees::get_merkle_root(0).unwrap(),
+			MerkleTrees::get_merkle_root(1).unwrap()
+		);
+	});
+}
 
-		let keyh1 = Poseidon_hash_2(key0.0, key1.0, &h);
-		let keyh2 = Poseidon_hash_2(keyh1, zero_h1.0, &h);
+#[test]
+fn should_get_leaves_of_block_from_its_events() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			append_only: false,
+			..Default::default()
+		}));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let keys: Vec<ScalarData> = (1..=4).map(|i| ScalarData::from(key_bytes(i))).collect();
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[..2].to_vec()));
+		assert_ok!(MerkleTrees::add_leaf(Origin::signed(1), 0, keys[2].to_bytes(), 0));
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 0, vec![keys[3]]));
+		assert_ok!(MerkleTrees::update_leaf(Origin::signed(1), 0, 0, keys[3].to_bytes()));
+
+		let change = |leaf_index: u32, leaf, replaced| LeafChange {
+			tree_id: 0,
+			leaf_index,
+			leaf,
+			replaced,
+		};
+		// the single leaf is reported once, though it's in two events
+		assert_eq!(MerkleTrees::get_block_leaves(), vec![
+			change(0, keys[0], false),
+			change(1, keys[1], false),
+			change(2, keys[2], false),
+			change(3, keys[3], false),
+			change(0, keys[3], true),
+		]);
+	});
+}
 
-		let tree = MerkleTrees::trees(0).unwrap();
+#[test]
+fn should_not_update_leaf_of_append_only_tree() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let leaf = ScalarData::from(key_bytes(1));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![leaf]));
 
-		assert_eq!(tree.root_hash.unwrap().0, keyh2, "Invalid root hash");
+		assert_err!(
+			MerkleTrees::update_leaf(Origin::signed(1), 0, 0, key_bytes(2)),
+			Error::<Test>::TreeIsAppendOnly
+		);
+		assert_eq!(MerkleTrees::get_leaves_range(0, 0, 1), vec![Some(leaf)]);
+	});
+}
 
-		assert_ok!(MerkleTrees::add_members(Origin::signed(3), 0, vec![key2.clone()]));
+#[test]
+fn should_get_tree_nodes() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			manager_required: true,
+			depth: Some(3),
+			..Default::default()
+		}));
+		assert_eq!(MerkleTrees::get_tree_nodes(0, 0, 0, 5), None);
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let keys = vec![
+			ScalarData::from(key_bytes(1)),
+			ScalarData::from(key_bytes(2)),
+			ScalarData::from(key_bytes(3)),
+		];
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
 
-		let keyh1 = Poseidon_hash_2(key0.0, key1.0, &h);
-		let keyh2 = Poseidon_hash_2(key2.0, zero_h0.0, &h);
-		let keyh3 = Poseidon_hash_2(keyh1, keyh2, &h);
+		// The level 0 holds the leaves
+		assert_eq!(
+			MerkleTrees::get_tree_nodes(0, 0, 0, 5),
+			Some(keys.iter().cloned().map(Some).collect())
+		);
+		// The range is clamped to the nodes with leaves under them
+		let level_one = MerkleTrees::get_tree_nodes(0, 1, 0, 5).unwrap();
+		assert_eq!(level_one.len(), 2);
+		assert!(level_one.iter().all(Option::is_some));
+		assert_eq!(MerkleTrees::get_tree_nodes(0, 1, 1, 2), Some(vec![level_one[1]]));
+		assert_eq!(MerkleTrees::get_tree_nodes(0, 2, 1, 5), Some(vec![]));
+		// The top level holds the root
+		assert_eq!(
+			MerkleTrees::get_tree_nodes(0, 3, 0, 5),
+			Some(vec![Some(MerkleTrees::get_merkle_root(0).unwrap())])
+		);
+		assert_eq!(MerkleTrees::get_tree_nodes(0, 4, 0, 5), None);
+		assert_eq!(MerkleTrees::get_tree_nodes(1, 0, 0, 5), None);
+	});
+}
 
-		let tree = MerkleTrees::trees(0).unwrap();
+#[test]
+fn should_hash_nodes_with_default_hasher() {
+	let h = default_hasher(4096);
+	let leaf = ScalarData::from(key_bytes(1));
+	// the inputs are hashed into the leaves along with the empty node
+	let hashed = DefaultHasher::hash_leaf(&HashFunction::PoseidonDefault, leaf, &h);
+	assert_ne!(hashed, leaf);
+	assert_eq!(
+		hashed,
+		DefaultHasher::hash_two(&HashFunction::PoseidonDefault, leaf, ScalarData::zero(), &h)
+	);
+	// the Poseidon zero tree is the precomputed one
+	assert_eq!(
+		DefaultHasher::zero_tree(&HashFunction::PoseidonDefault, &h),
+		gen_zero_tree(h.width, &h.sbox)
+	);
+	// the other hash functions hash the empty nodes level by level
+	let zero_tree = DefaultHasher::zero_tree(&HashFunction::Blake2, &h);
+	assert_eq!(zero_tree.len(), 33);
+	let zero = ScalarData::from(zero_tree[0]);
+	assert_eq!(
+		DefaultHasher::hash_two(&HashFunction::Blake2, zero, zero, &h).to_bytes(),
+		zero_tree[1]
+	);
+}
 
-		assert_eq!(tree.root_hash.unwrap().0, keyh3, "Invalid root hash");
-	});
+#[test]
+fn should_hash_like_primitives_default_poseidon() {
+	// the primitives get by with a single generator, the pallet builds the
+	// parameters with the generators of the verifying key
+	let primitives = merkle_primitives::default_poseidon();
+	let pallet = crate::default_hasher(BulletproofGens::new(16400, 1));
+	let left = ScalarData::from(key_bytes(1));
+	let right = ScalarData::from(key_bytes(2));
+	assert_eq!(
+		merkle_primitives::hash(&HashFunction::PoseidonDefault, left.0, right.0, &primitives),
+		DefaultHasher::hash_two(&HashFunction::PoseidonDefault, left, right, &pallet).0
+	);
 }
 
 #[test]
-fn should_have_correct_root_hash() {
+fn should_verify_membership_proof_with_primitives() {
 	new_test_ext().execute_with(|| {
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			hasher: Some(HashFunction::Blake2),
+			..Default::default()
+		}));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let keys: Vec<ScalarData> = (1..4).map(|i| ScalarData::from(key_bytes(i))).collect();
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+
+		// the leaf hash isn't the identity, but only `hash_two` is applied to
+		// the nodes
 		let h = default_hasher(4096);
-		let zero_tree = gen_zero_tree(h.width, &h.sbox);
-		let mut keys = Vec::new();
-		for i in 0..15 {
-			keys.push(Scalar::from_bytes_mod_order(key_bytes(i as u8)))
+		assert_ne!(DefaultHasher::hash_leaf(&HashFunction::Blake2, keys[0], &h), keys[0]);
+		let root = MerkleTrees::get_merkle_root(0).unwrap().to_bytes();
+		for (i, key) in keys.iter().enumerate() {
+			let path = MerkleTrees::get_membership_proof(0, i as u32).unwrap();
+			let directions: Vec<bool> = path.iter().map(|(is_right, _)| *is_right).collect();
+			let siblings: Vec<[u8; 32]> = path.iter().map(|(_, node)| node.to_bytes()).collect();
+			assert!(merkle_primitives::verify_path(
+				key.to_bytes(),
+				&siblings,
+				&directions,
+				root,
+				HashFunction::Blake2
+			));
 		}
-		let zero_h0 = ScalarData::from(zero_tree[0]);
+	});
+}
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(4)));
-		let tree_id = 0;
+#[test]
+fn should_hash_leaves_on_insert() {
+	new_test_ext().execute_with(|| {
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+		for hash_leaf_on_insert in vec![false, true] {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				hash_leaf_on_insert,
+				..Default::default()
+			}));
+		}
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 1, 0));
+		assert!(!MerkleTrees::get_tree_metadata(0).unwrap().hash_leaf_on_insert);
+		assert!(MerkleTrees::get_tree_metadata(1).unwrap().hash_leaf_on_insert);
 
-		let keys_data: Vec<ScalarData> = keys.iter().map(|x| ScalarData(*x)).collect();
-		assert_ok!(MerkleTrees::add_members(Origin::signed(0), 0, keys_data.clone()));
+		let h = default_hasher(4096);
+		let inputs = vec![ScalarData::from(key_bytes(1)), ScalarData::from(key_bytes(2))];
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, inputs.clone()));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 1, inputs.clone()));
+		for (i, input) in inputs.iter().enumerate() {
+			// the inputs are stored as they are, or hashed once by the hasher
+			// of the pallet, which isn't the identity
+			let leaf = DefaultHasher::hash_leaf(&HashFunction::PoseidonDefault, *input, &h);
+			assert_ne!(leaf, *input);
+			assert_eq!(MerkleTrees::leaves(0, i as u32), *input);
+			assert_eq!(MerkleTrees::leaves(1, i as u32), leaf);
+			for tree_id in 0..2 {
+				let path = MerkleTrees::get_membership_proof(tree_id, i as u32).unwrap();
+				assert_ok!(MerkleTrees::verify(Origin::signed(2), tree_id, *input, path));
+			}
+		}
+		// the hashed leaves are hashed into the nodes with `hash_two` only
+		let expected = MerkleTrees::hash(
+			HashFunction::PoseidonDefault,
+			MerkleTrees::leaves(1, 0).0,
+			MerkleTrees::leaves(1, 1).0,
+			&h,
+		);
+		assert_ne!(
+			MerkleTrees::get_merkle_root(0).unwrap(),
+			MerkleTrees::get_merkle_root(1).unwrap()
+		);
+		let path = MerkleTrees::get_membership_proof(1, 0).unwrap();
+		assert_eq!(path[0].1, MerkleTrees::leaves(1, 1));
+		assert_eq!(
+			MerkleTrees::get_tree_nodes(1, 1, 0, 1),
+			Some(vec![Some(ScalarData(expected))])
+		);
+	});
+}
 
-		let key1_1 = Poseidon_hash_2(keys[0], keys[1], &h);
-		let key1_2 = Poseidon_hash_2(keys[2], keys[3], &h);
-		let key1_3 = Poseidon_hash_2(keys[4], keys[5], &h);
-		let key1_4 = Poseidon_hash_2(keys[6], keys[7], &h);
-		let key1_5 = Poseidon_hash_2(keys[8], keys[9], &h);
-		let key1_6 = Poseidon_hash_2(keys[10], keys[11], &h);
-		let key1_7 = Poseidon_hash_2(keys[12], keys[13], &h);
-		let key1_8 = Poseidon_hash_2(keys[14], zero_h0.0, &h);
+#[test]
+fn should_get_default_nodes() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		assert_eq!(MerkleTrees::get_default_nodes(0), None);
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
 
-		let key2_1 = Poseidon_hash_2(key1_1, key1_2, &h);
-		let key2_2 = Poseidon_hash_2(key1_3, key1_4, &h);
-		let key2_3 = Poseidon_hash_2(key1_5, key1_6, &h);
-		let key2_4 = Poseidon_hash_2(key1_7, key1_8, &h);
+		let h = default_hasher(4096);
+		let zero_tree = gen_zero_tree(h.width, &h.sbox);
+		let nodes = MerkleTrees::get_default_nodes(0).unwrap();
+		assert_eq!(nodes.len(), 4);
+		for (i, node) in nodes.iter().enumerate() {
+			assert_eq!(node.to_bytes(), zero_tree[i]);
+		}
+		// The root of the empty tree is the last default node
+		assert_eq!(MerkleTrees::get_merkle_root(0).unwrap(), nodes[3]);
 
-		let key3_1 = Poseidon_hash_2(key2_1, key2_2, &h);
-		let key3_2 = Poseidon_hash_2(key2_3, key2_4, &h);
+		// The empty subtrees fill in for the missing leaves
+		let key = ScalarData::from(key_bytes(1));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key]));
+		let mut root = key.0;
+		for node in &nodes[..3] {
+			root = Poseidon_hash_2(root, node.0, &h);
+		}
+		assert_eq!(MerkleTrees::get_merkle_root(0).unwrap(), ScalarData(root));
+	});
+}
 
-		let root_hash = Poseidon_hash_2(key3_1, key3_2, &h);
+#[test]
+fn should_build_genesis_trees() {
+	let mut t = frame_system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	crate::GenesisConfig::<Test> {
+		verifying_key: Some(get_bp_gen_bytes(&BulletproofGens::new(16400, 1))),
+		trees: vec![
+			(1, 3, HashFunction::PoseidonDefault, None, vec![
+				key_bytes(1),
+				key_bytes(2),
+			]),
+			(2, 5, HashFunction::Blake2, Some(100), vec![]),
+		],
+	}
+	.assimilate_storage(&mut t)
+	.unwrap();
+
+	sp_io::TestExternalities::from(t).execute_with(|| {
+		assert_eq!(MerkleTrees::next_tree_id(), 2);
+		let metadata = MerkleTrees::get_tree_metadata(0).unwrap();
+		assert_eq!(metadata.depth, 3);
+		assert_eq!(metadata.hasher, HashFunction::PoseidonDefault);
+		assert_eq!(metadata.denomination, None);
+		assert_eq!(metadata.leaf_count, 2);
+		assert_eq!(MerkleTrees::leaves(0, 1), ScalarData::from(key_bytes(2)));
+		assert_eq!(MerkleTrees::get_manager(0).unwrap().account_id, 1);
+
+		let metadata = MerkleTrees::get_tree_metadata(1).unwrap();
+		assert_eq!(metadata.depth, 5);
+		assert_eq!(metadata.hasher, HashFunction::Blake2);
+		assert_eq!(metadata.denomination, Some(100));
+		assert_eq!(metadata.leaf_count, 0);
+		assert!(MerkleTrees::trees(1).unwrap().initialized);
+	});
+}
 
-		let tree = MerkleTrees::trees(0).unwrap();
+#[test]
+#[should_panic(expected = "genesis leaves can't be inserted into a denominated tree")]
+fn should_reject_genesis_leaves_of_denominated_tree() {
+	let mut t = frame_system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	crate::GenesisConfig::<Test> {
+		verifying_key: Some(get_bp_gen_bytes(&BulletproofGens::new(16400, 1))),
+		trees: vec![(1, 3, HashFunction::PoseidonDefault, Some(100), vec![key_bytes(1)])],
+	}
+	.assimilate_storage(&mut t)
+	.unwrap();
+}
 
-		assert_eq!(tree.root_hash.unwrap().0, root_hash, "Invalid root hash");
-	});
+#[test]
+#[should_panic(expected = "genesis leaf is canonical")]
+fn should_reject_non_canonical_genesis_leaf() {
+	let mut t = frame_system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	crate::GenesisConfig::<Test> {
+		verifying_key: Some(get_bp_gen_bytes(&BulletproofGens::new(16400, 1))),
+		trees: vec![(1, 3, HashFunction::PoseidonDefault, None, vec![[0xff; 32]])],
+	}
+	.assimilate_storage(&mut t)
+	.unwrap();
 }
 
 #[test]
-fn should_be_unable_to_pass_proof_path_with_invalid_length() {
+fn should_get_tree_metadata() {
 	new_test_ext().execute_with(|| {
-		let key0 = ScalarData::from(key_bytes(0));
-		let key1 = ScalarData::from(key_bytes(1));
-		let key2 = ScalarData::from(key_bytes(2));
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(2)));
-		let tree_id = 0;
+		assert_eq!(MerkleTrees::get_tree_metadata(0), None);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let keys = vec![ScalarData::from(key_bytes(1)), ScalarData::from(key_bytes(2))];
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys));
+		assert_ok!(MerkleTrees::set_stopped(Origin::signed(1), 0, true));
 
-		assert_ok!(MerkleTrees::add_members(Origin::signed(0), 0, vec![
-			key0.clone(),
-			key1.clone(),
-			key2.clone()
-		]));
+		let metadata = MerkleTrees::get_tree_metadata(0).unwrap();
+		assert_eq!(metadata, TreeMetadata {
+			depth: 3,
+			leaf_count: 2,
+			is_stopped: true,
+			hasher: HashFunction::PoseidonDefault,
+			root_history_size: RootHistorySize::get(),
+			is_pruned: false,
+			denomination: None,
+			commitment_version: 0,
+			is_append_only: true,
+			leaves_remaining: 6,
+			min_anonymity_set: 0,
+			hash_leaf_on_insert: false,
+			asset_id: None,
+			on_full: OnFull::Reject,
+			successor_tree_id: None,
+			is_migrated: true,
+		});
+	});
+}
 
-		let path = vec![(true, key0)];
-		assert_err!(
-			MerkleTrees::verify(Origin::signed(2), 0, key0, path),
-			Error::<Test>::InvalidPathLength,
-		);
+#[test]
+fn should_be_able_to_change_manager_with_root() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			manager_required: true,
+			depth: Some(3),
+			..Default::default()
+		}));
+		let call = Box::new(MerkleCall::set_manager(0, 2));
+		let res = call.dispatch_bypass_filter(RawOrigin::Root.into());
+		assert_ok!(res);
+		let mng = MerkleTrees::get_manager(0).unwrap();
+		assert_eq!(mng.account_id, 2);
 
-		let path = vec![(true, key0), (false, key1), (true, key2)];
+		let call = Box::new(MerkleCall::set_manager(0, 3));
+		let res = call.dispatch_bypass_filter(RawOrigin::Signed(0).into());
+		assert_err!(res, BadOrigin);
+	})
+}
+
+#[test]
+fn should_not_have_0_depth() {
+	new_test_ext().execute_with(|| {
 		assert_err!(
-			MerkleTrees::verify(Origin::signed(2), 0, key0, path),
-			Error::<Test>::InvalidPathLength,
+			MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(0),
+				..Default::default()
+			}),
+			Error::<Test>::InvalidTreeDepth,
 		);
 	});
 }
 
 #[test]
-fn should_not_verify_invalid_proof() {
+fn should_have_min_depth() {
 	new_test_ext().execute_with(|| {
-		let h = default_hasher(4096);
-		let zero_tree = gen_zero_tree(h.width, &h.sbox);
-		let key0 = ScalarData::from(key_bytes(9));
-		let key1 = ScalarData::from(key_bytes(3));
-		let key2 = ScalarData::from(key_bytes(5));
-		let zero_h0 = ScalarData::from(zero_tree[0]);
-
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(2)));
+		let key = ScalarData::from(key_bytes(1));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
 		let key_id = 0;
 		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
-
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![
-			key0.clone(),
-			key1.clone(),
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key.clone(); 2]));
+		assert_err!(
+			MerkleTrees::add_members(Origin::signed(1), 0, vec![key.clone()]),
+			Error::<Test>::ExceedsMaxLeaves,
+		);
+	});
+}
+
+#[test]
+fn should_not_add_leaf_to_full_tree() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(2),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaves_remaining, 4);
+
+		// a tree of depth 2 holds 2^2 leaves
+		for i in 1..5 {
+			assert_ok!(MerkleTrees::add_leaf(Origin::signed(1), 0, key_bytes(i), 0));
+		}
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaves_remaining, 0);
+		assert_err!(
+			MerkleTrees::add_leaf(Origin::signed(1), 0, key_bytes(5), 0),
+			Error::<Test>::ExceedsMaxLeaves
+		);
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaf_count, 4);
+	});
+}
+
+#[test]
+fn should_get_verifying_key_of_tree() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data.clone()));
+		assert_eq!(MerkleTrees::get_verifying_key(0), None);
+
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_eq!(MerkleTrees::get_verifying_key(0), Some(key_data));
+
+		let new_key_data = get_bp_gen_bytes(&BulletproofGens::new(8200, 1));
+		assert_ok!(MerkleTrees::set_verifying_key(Origin::root(), 0, new_key_data.clone()));
+		assert_eq!(MerkleTrees::get_verifying_key(0), Some(new_key_data));
+		assert_eq!(MerkleTrees::get_verifying_key(1), None);
+	});
+}
+
+#[test]
+fn should_find_tree_by_name() {
+	new_test_ext().execute_with(|| {
+		let name = b"usdc-100".to_vec();
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			name: Some(name.clone()),
+			..Default::default()
+		}));
+		assert_eq!(MerkleTrees::get_tree_id_by_name(name.clone()), Some(1));
+		assert_eq!(MerkleTrees::get_tree_id_by_name(b"usdc-10".to_vec()), None);
+
+		assert_err!(
+			MerkleTrees::create_tree(Origin::signed(2), TreeParams {
+				depth: Some(3),
+				name: Some(name),
+				..Default::default()
+			}),
+			Error::<Test>::TreeNameTaken
+		);
+		let long_name = vec![b'a'; MaxTreeNameLength::get() as usize + 1];
+		assert_err!(
+			MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				name: Some(long_name),
+				..Default::default()
+			}),
+			Error::<Test>::TreeNameTooLong
+		);
+		assert_eq!(MerkleTrees::next_tree_id(), 2);
+	});
+}
+
+#[test]
+fn should_not_create_more_than_max_trees() {
+	new_test_ext().execute_with(|| {
+		for _ in 0..MaxTrees::get() {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				..Default::default()
+			}));
+		}
+		assert_err!(
+			MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				..Default::default()
+			}),
+			Error::<Test>::TooManyTrees
+		);
+		assert_eq!(MerkleTrees::next_tree_id(), MaxTrees::get());
+	});
+}
+
+#[test]
+fn should_store_chosen_depth() {
+	new_test_ext().execute_with(|| {
+		let key = ScalarData::from(key_bytes(1));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams::default()));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+
+		let tree = MerkleTrees::get_tree(0).unwrap();
+		assert_eq!(tree.depth, 3);
+		assert_eq!(tree.max_leaves, 8);
+		assert_eq!(tree.edge_nodes.unwrap().len(), 3);
+		assert_eq!(MerkleTrees::get_tree(1).unwrap().depth, MaxTreeDepth::get());
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key; 8]));
+		assert_err!(
+			MerkleTrees::add_members(Origin::signed(1), 0, vec![key]),
+			Error::<Test>::ExceedsMaxLeaves,
+		);
+		assert_eq!(MerkleTrees::get_membership_proof(0, 7).unwrap().len(), 3);
+	});
+}
+
+#[test]
+fn should_roll_over_full_tree() {
+	new_test_ext().execute_with(|| {
+		let keys: Vec<ScalarData> = (1..=6).map(|i| ScalarData::from(key_bytes(i))).collect();
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(2),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[..4].to_vec()));
+		// The full tree rejects the leaves by default
+		assert_err!(
+			MerkleTrees::add_members(Origin::signed(1), 0, vec![keys[4]]),
+			Error::<Test>::ExceedsMaxLeaves,
+		);
+
+		assert_err!(
+			MerkleTrees::set_on_full(Origin::signed(2), 0, OnFull::Rollover),
+			BadOrigin
+		);
+		assert_ok!(MerkleTrees::set_on_full(Origin::signed(1), 0, OnFull::Rollover));
+		assert_ok!(MerkleTrees::add_leaf(Origin::signed(1), 0, keys[4].to_bytes(), 0));
+
+		// The leaf is inserted into the successor, which rolls over too
+		let rolled_event = Event::pallet_merkle(crate::Event::TreeRolledOver(0, 1));
+		assert!(System::events().iter().any(|record| record.event == rolled_event));
+		let added_event = Event::pallet_merkle(crate::Event::LeafAdded(1, 0, keys[4]));
+		assert!(System::events().iter().any(|record| record.event == added_event));
+		assert_eq!(MerkleTrees::get_leaf_count(0), Some(4));
+		assert_eq!(MerkleTrees::get_leaves_range(1, 0, 4), vec![Some(keys[4])]);
+		let metadata = MerkleTrees::get_tree_metadata(0).unwrap();
+		assert_eq!(metadata.on_full, OnFull::Rollover);
+		assert_eq!(metadata.successor_tree_id, Some(1));
+		let metadata = MerkleTrees::get_tree_metadata(1).unwrap();
+		assert_eq!(metadata.depth, 2);
+		assert_eq!(metadata.on_full, OnFull::Rollover);
+		assert_eq!(metadata.successor_tree_id, None);
+		assert_eq!(MerkleTrees::get_manager(1).unwrap().account_id, 1);
+		assert_eq!(MerkleTrees::verifying_key_for_tree(1), 0);
+
+		// The leaves inserted through the rolled over tree follow the chain
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![keys[5]]));
+		assert_eq!(MerkleTrees::get_leaf_count(0), Some(4));
+		assert_eq!(MerkleTrees::get_leaves_range(1, 0, 4), vec![
+			Some(keys[4]),
+			Some(keys[5])
+		]);
+
+		assert_eq!(MerkleTrees::get_tree_chain(0), vec![0, 1]);
+		assert_eq!(MerkleTrees::get_tree_chain(1), vec![1]);
+		assert_eq!(MerkleTrees::get_tree_chain(2), vec![]);
+	});
+}
+
+#[test]
+fn should_fill_partly_full_tree_before_rolling_over() {
+	new_test_ext().execute_with(|| {
+		let keys: Vec<ScalarData> = (1..=8).map(|i| ScalarData::from(key_bytes(i))).collect();
+		for _ in 0..2 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(2),
+				..Default::default()
+			}));
+		}
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		for tree_id in 0..2 {
+			assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, 0));
+		}
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[..3].to_vec()));
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 1, keys[..3].to_vec()));
+		// The partly full tree rejects the leaves which don't fit by default
+		assert_err!(
+			MerkleTrees::add_members(Origin::signed(1), 0, keys[3..5].to_vec()),
+			Error::<Test>::ExceedsMaxLeaves,
+		);
+		assert_err!(
+			MerkleTrees::add_leaves(Origin::signed(1), 1, keys[3..5].to_vec()),
+			Error::<Test>::ExceedsMaxLeaves,
+		);
+
+		// Otherwise the tree is filled up and the rest goes to the successor
+		for tree_id in 0..2 {
+			assert_ok!(MerkleTrees::set_on_full(Origin::signed(1), tree_id, OnFull::Rollover));
+		}
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[3..8].to_vec()));
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 1, keys[3..8].to_vec()));
+		// The full successor only rolls over once more leaves come
+		assert_eq!(MerkleTrees::get_tree_chain(0), vec![0, 2]);
+		assert_eq!(MerkleTrees::get_tree_chain(1), vec![1, 3]);
+		let filled: Vec<Option<ScalarData>> = keys[..4].iter().copied().map(Some).collect();
+		let rest: Vec<Option<ScalarData>> = keys[4..].iter().copied().map(Some).collect();
+		for (tree_id, successor) in vec![(0, 2), (1, 3)] {
+			assert_eq!(MerkleTrees::get_leaves_range(tree_id, 0, 4), filled);
+			assert_eq!(MerkleTrees::get_leaves_range(successor, 0, 4), rest);
+		}
+	});
+}
+
+#[test]
+fn should_have_max_depth() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(32),
+			..Default::default()
+		}));
+	});
+}
+
+#[test]
+fn should_not_have_more_than_max_depth() {
+	new_test_ext().execute_with(|| {
+		assert_err!(
+			MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(33),
+				..Default::default()
+			}),
+			Error::<Test>::InvalidTreeDepth,
+		);
+	});
+}
+
+#[test]
+fn should_have_correct_root_hash_after_insertion() {
+	new_test_ext().execute_with(|| {
+		let h = default_hasher(4096);
+		let zero_tree = gen_zero_tree(h.width, &h.sbox);
+		let key0 = ScalarData::from(key_bytes(0));
+		let key1 = ScalarData::from(key_bytes(1));
+		let key2 = ScalarData::from(key_bytes(2));
+		let zero_h0 = ScalarData::from(zero_tree[0]);
+		let zero_h1 = ScalarData::from(zero_tree[1]);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(2),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![key0.clone()]));
+
+		let keyh1 = Poseidon_hash_2(key0.0, zero_h0.0, &h);
+		let keyh2 = Poseidon_hash_2(keyh1, zero_h1.0, &h);
+
+		let tree = MerkleTrees::trees(0).unwrap();
+
+		assert_eq!(tree.root_hash.unwrap().0, keyh2, "Invalid root hash");
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(2), 0, vec![key1.clone()]));
+
+		let keyh1 = Poseidon_hash_2(key0.0, key1.0, &h);
+		let keyh2 = Poseidon_hash_2(keyh1, zero_h1.0, &h);
+
+		let tree = MerkleTrees::trees(0).unwrap();
+
+		assert_eq!(tree.root_hash.unwrap().0, keyh2, "Invalid root hash");
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(3), 0, vec![key2.clone()]));
+
+		let keyh1 = Poseidon_hash_2(key0.0, key1.0, &h);
+		let keyh2 = Poseidon_hash_2(key2.0, zero_h0.0, &h);
+		let keyh3 = Poseidon_hash_2(keyh1, keyh2, &h);
+
+		let tree = MerkleTrees::trees(0).unwrap();
+
+		assert_eq!(tree.root_hash.unwrap().0, keyh3, "Invalid root hash");
+	});
+}
+
+#[test]
+fn should_have_correct_root_hash() {
+	new_test_ext().execute_with(|| {
+		let h = default_hasher(4096);
+		let zero_tree = gen_zero_tree(h.width, &h.sbox);
+		let mut keys = Vec::new();
+		for i in 0..15 {
+			keys.push(Scalar::from_bytes_mod_order(key_bytes(i as u8)))
+		}
+		let zero_h0 = ScalarData::from(zero_tree[0]);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(4),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		let keys_data: Vec<ScalarData> = keys.iter().map(|x| ScalarData(*x)).collect();
+		assert_ok!(MerkleTrees::add_members(Origin::signed(0), 0, keys_data.clone()));
+
+		let key1_1 = Poseidon_hash_2(keys[0], keys[1], &h);
+		let key1_2 = Poseidon_hash_2(keys[2], keys[3], &h);
+		let key1_3 = Poseidon_hash_2(keys[4], keys[5], &h);
+		let key1_4 = Poseidon_hash_2(keys[6], keys[7], &h);
+		let key1_5 = Poseidon_hash_2(keys[8], keys[9], &h);
+		let key1_6 = Poseidon_hash_2(keys[10], keys[11], &h);
+		let key1_7 = Poseidon_hash_2(keys[12], keys[13], &h);
+		let key1_8 = Poseidon_hash_2(keys[14], zero_h0.0, &h);
+
+		let key2_1 = Poseidon_hash_2(key1_1, key1_2, &h);
+		let key2_2 = Poseidon_hash_2(key1_3, key1_4, &h);
+		let key2_3 = Poseidon_hash_2(key1_5, key1_6, &h);
+		let key2_4 = Poseidon_hash_2(key1_7, key1_8, &h);
+
+		let key3_1 = Poseidon_hash_2(key2_1, key2_2, &h);
+		let key3_2 = Poseidon_hash_2(key2_3, key2_4, &h);
+
+		let root_hash = Poseidon_hash_2(key3_1, key3_2, &h);
+
+		let tree = MerkleTrees::trees(0).unwrap();
+
+		assert_eq!(tree.root_hash.unwrap().0, root_hash, "Invalid root hash");
+	});
+}
+
+#[test]
+fn should_be_unable_to_pass_proof_path_with_invalid_length() {
+	new_test_ext().execute_with(|| {
+		let key0 = ScalarData::from(key_bytes(0));
+		let key1 = ScalarData::from(key_bytes(1));
+		let key2 = ScalarData::from(key_bytes(2));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(2),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(0), 0, vec![
+			key0.clone(),
+			key1.clone(),
+			key2.clone()
+		]));
+
+		let path = vec![(true, key0)];
+		assert_err!(
+			MerkleTrees::verify(Origin::signed(2), 0, key0, path),
+			Error::<Test>::InvalidPathLength,
+		);
+
+		let path = vec![(true, key0), (false, key1), (true, key2)];
+		assert_err!(
+			MerkleTrees::verify(Origin::signed(2), 0, key0, path),
+			Error::<Test>::InvalidPathLength,
+		);
+	});
+}
+
+#[test]
+fn should_not_verify_invalid_proof() {
+	new_test_ext().execute_with(|| {
+		let h = default_hasher(4096);
+		let zero_tree = gen_zero_tree(h.width, &h.sbox);
+		let key0 = ScalarData::from(key_bytes(9));
+		let key1 = ScalarData::from(key_bytes(3));
+		let key2 = ScalarData::from(key_bytes(5));
+		let zero_h0 = ScalarData::from(zero_tree[0]);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(2),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![
+			key0.clone(),
+			key1.clone(),
 			key2.clone()
 		]));
 
@@ -410,7 +1351,10 @@ fn should_verify_proof_of_membership() {
 		}
 		let zero_h0 = ScalarData::from(zero_tree[0]);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(4)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(4),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
@@ -478,33 +1422,943 @@ fn should_verify_proof_of_membership() {
 }
 
 #[test]
-fn should_verify_simple_zk_proof_of_membership() {
+fn should_build_verifiable_membership_proof() {
 	new_test_ext().execute_with(|| {
-		let pc_gens = PedersenGens::default();
-
-		let label = b"zk_membership_proof";
-		let mut prover_transcript = Transcript::new(label);
-		let prover = Prover::new(&pc_gens, &mut prover_transcript);
-
 		let h = default_hasher(4096);
-		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+		let mut keys = Vec::new();
+		for i in 0..5 {
+			keys.push(ScalarData::from(key_bytes(i as u8)))
+		}
 
-		let leaf = ftree.generate_secrets();
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+
+		let key1_1 = Poseidon_hash_2(keys[0].0, keys[1].0, &h);
+		let path = MerkleTrees::get_membership_proof(0, 2).unwrap();
+		assert_eq!(path.len(), 3);
+		assert_eq!(path[0], (true, keys[3]));
+		assert_eq!(path[1], (false, ScalarData(key1_1)));
+
+		for i in 0..keys.len() {
+			let path = MerkleTrees::get_membership_proof(0, i as u32).unwrap();
+			assert_ok!(MerkleTrees::verify(Origin::signed(2), 0, keys[i], path));
+		}
+
+		assert_err!(
+			MerkleTrees::get_membership_proof(0, keys.len() as u32),
+			Error::<Test>::LeafDoesntExist
+		);
+	});
+}
+
+#[test]
+fn should_cache_nodes_offchain() {
+	let mut ext = new_test_ext();
+	let (offchain, _state) = TestOffchainExt::new();
+	ext.register_extension(OffchainExt::new(offchain));
+	ext.execute_with(|| {
+		let mut keys = Vec::new();
+		for i in 0..5 {
+			keys.push(ScalarData::from(key_bytes(i as u8)))
+		}
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let empty_root = MerkleTrees::get_merkle_root(0).unwrap();
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[..3].to_vec()));
+		assert_eq!(MerkleTrees::changed_leaves(0), Some((0, Some(empty_root))));
+
+		offchain::cache_trees::<Test>();
+		let read_node = |level, index| {
+			StorageValueRef::persistent(&offchain::node_key(&0u32, level, index))
+				.get::<ScalarData>()
+				.flatten()
+		};
+		let cached_tree = StorageValueRef::persistent(&offchain::tree_key(&0u32));
+		let tree = cached_tree.get::<offchain::CachedTree>().flatten().unwrap();
+		assert_eq!(tree.root, MerkleTrees::get_merkle_root(0).unwrap());
+		assert_eq!(tree.leaf_count, 3);
+
+		// the changed trees are cleared on the next block, and only the nodes
+		// above the new leaves are written
+		MerkleTrees::on_initialize(2);
+		assert_eq!(MerkleTrees::changed_leaves(0), None);
+		StorageValueRef::persistent(&offchain::node_key(&0u32, 0, 0)).set(&ScalarData::zero());
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[3..].to_vec()));
+		assert_eq!(MerkleTrees::changed_leaves(0), Some((3, Some(tree.root))));
+		offchain::cache_trees::<Test>();
+		assert_eq!(read_node(0, 0), Some(ScalarData::zero()));
+		StorageValueRef::persistent(&offchain::node_key(&0u32, 0, 0)).set(&keys[0]);
+
+		let tree = cached_tree.get::<offchain::CachedTree>().flatten().unwrap();
+		assert_eq!(tree.root, MerkleTrees::get_merkle_root(0).unwrap());
+		for i in 0..keys.len() as u32 {
+			assert_eq!(
+				tree.membership_proof(i, read_node),
+				Some(MerkleTrees::get_membership_proof(0, i).unwrap())
+			);
+		}
+		assert_eq!(tree.membership_proof(keys.len() as u32, read_node), None);
+
+		// the nodes of the pruned tree are cleared along with its state
+		MerkleTrees::on_initialize(3);
+		assert_ok!(MerkleTrees::stop_tree(Origin::signed(1), 0));
+		assert_ok!(MerkleTrees::prune_tree(Origin::signed(1), 0));
+		offchain::cache_trees::<Test>();
+		assert_eq!(cached_tree.get::<offchain::CachedTree>().flatten(), None);
+		assert_eq!(read_node(0, 0), None);
+	});
+}
+
+#[test]
+fn should_add_leaves_in_batch() {
+	new_test_ext().execute_with(|| {
+		let mut keys = Vec::new();
+		for i in 0..7 {
+			keys.push(ScalarData::from(key_bytes(i as u8)))
+		}
+
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		for tree_id in 0..2 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				..Default::default()
+			}));
+			assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, 0));
+		}
+
+		for key in &keys {
+			assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![*key]));
+		}
+		// splitting the batch on an odd index
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 1, keys[..3].to_vec()));
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 1, keys[3..].to_vec()));
+
+		let expected = MerkleTrees::get_tree(0).unwrap();
+		let tree = MerkleTrees::get_tree(1).unwrap();
+		assert_eq!(tree.leaf_count, 7);
+		assert_eq!(tree.root_hash, expected.root_hash);
+		assert_eq!(tree.edge_nodes, expected.edge_nodes);
+		for i in 0..keys.len() {
+			assert_eq!(MerkleTrees::leaves(1, i as u32), keys[i]);
+			let path = MerkleTrees::get_membership_proof(1, i as u32).unwrap();
+			assert_eq!(path, MerkleTrees::get_membership_proof(0, i as u32).unwrap());
+		}
+	});
+}
+
+#[test]
+fn should_find_leaf_index_by_value() {
+	new_test_ext().execute_with(|| {
+		let mut keys = Vec::new();
+		for i in 0..7 {
+			keys.push(ScalarData::from(key_bytes(i as u8)))
+		}
+
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		for tree_id in 0..2 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				..Default::default()
+			}));
+			assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, 0));
+		}
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys[..4].to_vec()));
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 0, keys[4..].to_vec()));
+		for i in 0..keys.len() {
+			assert_eq!(MerkleTrees::leaf_index(0, keys[i]), Some(i as u32));
+			// the leaves are indexed per tree
+			assert_eq!(MerkleTrees::leaf_index(1, keys[i]), None);
+		}
+		assert_eq!(MerkleTrees::leaf_index(0, ScalarData::from(key_bytes(7))), None);
+	});
+}
+
+#[test]
+fn should_not_add_leaves_over_the_limits() {
+	new_test_ext().execute_with(|| {
+		let key = ScalarData::from(key_bytes(1));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+
+		assert_err!(
+			MerkleTrees::add_leaves(Origin::signed(1), 0, vec![key; MaxLeavesPerCall::get() as usize + 1]),
+			Error::<Test>::TooManyLeaves
+		);
+		assert_err!(
+			MerkleTrees::add_members(Origin::signed(1), 0, vec![key; MaxLeavesPerCall::get() as usize + 1]),
+			Error::<Test>::TooManyLeaves
+		);
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 0, vec![key; 5]));
+		assert_err!(
+			MerkleTrees::add_leaves(Origin::signed(1), 0, vec![key; 4]),
+			Error::<Test>::ExceedsMaxLeaves
+		);
+		assert_eq!(MerkleTrees::get_tree(0).unwrap().leaf_count, 5);
+	});
+}
+
+#[test]
+fn should_build_tree_with_chosen_hasher() {
+	new_test_ext().execute_with(|| {
+		let blake2 = |left: Scalar, right: Scalar| {
+			let input = [left.to_bytes(), right.to_bytes()].concat();
+			Scalar::from_bytes_mod_order(sp_io::hashing::blake2_256(&input))
+		};
+		let keys = vec![ScalarData::from(key_bytes(1)), ScalarData::from(key_bytes(2))];
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(2),
+			hasher: Some(HashFunction::Blake2),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+
+		let zero = Scalar::zero();
+		let expected = blake2(blake2(keys[0].0, keys[1].0), blake2(zero, zero));
+		assert_eq!(MerkleTrees::get_merkle_root(0).unwrap(), ScalarData(expected));
+		for i in 0..keys.len() {
+			let path = MerkleTrees::get_membership_proof(0, i as u32).unwrap();
+			assert_ok!(MerkleTrees::verify(Origin::signed(2), 0, keys[i], path));
+		}
+
+		assert_err!(
+			MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(2),
+				hasher: Some(HashFunction::MiMC),
+				..Default::default()
+			}),
+			Error::<Test>::UnsupportedHashFunction
+		);
+	});
+}
+
+#[test]
+fn should_rebuild_nodes_on_migration() {
+	new_test_ext().execute_with(|| {
+		let mut keys = Vec::new();
+		for i in 0..5 {
+			keys.push(ScalarData::from(key_bytes(i as u8)))
+		}
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+		let paths: Vec<_> = (0..keys.len())
+			.map(|i| MerkleTrees::get_membership_proof(0, i as u32).unwrap())
+			.collect();
+
+		// the nodes weren't kept before the migration
+		Nodes::<Test>::remove_prefix(0);
+		StorageVersion::<Test>::put(Releases::V1_0_0);
+		assert!(!MerkleTrees::get_tree_metadata(0).unwrap().is_migrated);
+		assert_err!(MerkleTrees::get_membership_proof(0, 0), Error::<Test>::MigrationPending);
+		migrations::migrate_to_v2::<Test>(Weight::max_value());
+		assert_eq!(MerkleTrees::storage_version(), Releases::V2_0_0);
+
+		for i in 0..keys.len() {
+			let path = MerkleTrees::get_membership_proof(0, i as u32).unwrap();
+			assert_eq!(path, paths[i]);
+			assert_ok!(MerkleTrees::verify(Origin::signed(2), 0, keys[i], path));
+		}
+	});
+}
+
+#[test]
+fn should_rebuild_path_from_leaves() {
+	new_test_ext().execute_with(|| {
+		let keys: Vec<ScalarData> = (0..5).map(|i| ScalarData::from(key_bytes(i))).collect();
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
+		assert_eq!(MerkleTrees::rebuild_path(0, 0), None);
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys.clone()));
+
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+		for i in 0..keys.len() {
+			let (path, rebuilt_root) = MerkleTrees::rebuild_path(0, i as u32).unwrap();
+			assert_eq!(rebuilt_root, root);
+			assert_eq!(path, MerkleTrees::get_membership_proof(0, i as u32).unwrap());
+			assert_ok!(MerkleTrees::verify(Origin::signed(2), 0, keys[i], path));
+		}
+		assert_eq!(MerkleTrees::rebuild_path(0, 5), None);
+
+		// The stored nodes aren't read, so a diverged node doesn't change the path
+		Nodes::<Test>::insert(0, (1, 0), Some(ScalarData::zero()));
+		let (path, rebuilt_root) = MerkleTrees::rebuild_path(0, 2).unwrap();
+		assert_eq!(rebuilt_root, root);
+		assert_ne!(path, MerkleTrees::get_membership_proof(0, 2).unwrap());
+		assert_ok!(MerkleTrees::verify(Origin::signed(2), 0, keys[2], path));
+	});
+}
+
+#[test]
+fn should_migrate_trees_lazily() {
+	new_test_ext().execute_with(|| {
+		let hasher_weight = <Test as Config>::WeightInfo::verify_path(0);
+		let node_weight = <Test as Config>::WeightInfo::verify_path(1) - hasher_weight;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		for tree_id in 0..3 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				..Default::default()
+			}));
+			assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, 0));
+			assert_ok!(MerkleTrees::add_members(Origin::signed(1), tree_id, vec![
+				ScalarData::from(key_bytes(1)),
+				ScalarData::from(key_bytes(2)),
+				ScalarData::from(key_bytes(3))
+			]));
+		}
+		let paths: Vec<_> = (0..3)
+			.map(|i| MerkleTrees::get_membership_proof(i, 0).unwrap())
+			.collect();
+		for tree_id in 0..3 {
+			Nodes::<Test>::remove_prefix(tree_id);
+		}
+		StorageVersion::<Test>::put(Releases::V1_0_0);
+		assert_eq!(MerkleTrees::get_storage_status(), StorageStatus {
+			version: Releases::V1_0_0,
+			migration_pending: true,
+		});
+
+		// A node is built even if the weight is used up
+		assert_eq!(migrations::migrate_to_v2::<Test>(0), hasher_weight + node_weight);
+		assert_eq!(MerkleTrees::migration_cursor(), (0, 1, 1));
+
+		// The nodes are built level by level until the weight is used up
+		let max_weight = hasher_weight * 2 + node_weight * 3;
+		assert_eq!(migrations::migrate_to_v2::<Test>(max_weight), max_weight);
+		assert_eq!(MerkleTrees::migration_cursor(), (1, 1, 1));
+		assert!(MerkleTrees::get_storage_status().migration_pending);
+		assert_eq!(MerkleTrees::get_membership_proof(0, 0).unwrap(), paths[0]);
+		// the proofs of the partly migrated tree are refused
+		assert_err!(MerkleTrees::get_membership_proof(1, 0), Error::<Test>::MigrationPending);
+		assert_eq!(MerkleTrees::get_cached_tree(1), None);
+
+		migrations::migrate_to_v2::<Test>(Weight::max_value());
+		assert_eq!(MerkleTrees::migration_cursor(), (0, 0, 0));
+		assert_eq!(MerkleTrees::get_storage_status(), StorageStatus {
+			version: Releases::V2_0_0,
+			migration_pending: false,
+		});
+		for tree_id in 1..3 {
+			assert_eq!(
+				MerkleTrees::get_membership_proof(tree_id, 0).unwrap(),
+				paths[tree_id as usize]
+			);
+		}
+	});
+}
+
+#[test]
+fn should_keep_root_history() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(6),
+			..Default::default()
+		}));
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_eq!(MerkleTrees::get_cached_root_count(0), 0);
+		assert_eq!(MerkleTrees::get_root_at_index(0, 0), None);
+
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		let mut roots = vec![MerkleTrees::get_merkle_root(0).unwrap()];
+		for i in 0..RootHistorySize::get() {
+			assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData::from(
+				key_bytes(i as u8)
+			)]));
+			roots.push(MerkleTrees::get_merkle_root(0).unwrap());
+		}
+
+		// the initial root is the only one which aged out
+		assert_eq!(MerkleTrees::get_cached_root_count(0), RootHistorySize::get());
+		for i in 0..RootHistorySize::get() {
+			let root = roots[roots.len() - 1 - i as usize];
+			assert_eq!(MerkleTrees::get_root_at_index(0, i), Some(root));
+		}
+		assert_eq!(MerkleTrees::get_root_at_index(0, RootHistorySize::get()), None);
+	});
+}
+
+#[test]
+fn should_resize_root_history() {
+	new_test_ext().execute_with(|| {
+		let roots: Vec<ScalarData> = (0..50).map(|i| ScalarData::from(key_bytes(i))).collect();
+		let lay_out = |tree_id: u32, size: u32, count: u32| {
+			for n in 0..count {
+				RootHistory::<Test>::insert(tree_id, n % size, roots[n as usize]);
+			}
+			RootCount::<Test>::insert(tree_id, count);
+		};
+		// Tree 0 was laid out for a longer history, tree 1 for a shorter one
+		lay_out(0, 40, 50);
+		RootHistoryLength::<Test>::put(40);
+		migrations::resize_root_history::<Test>();
+		assert_eq!(MerkleTrees::root_history_length(), RootHistorySize::get());
+
+		// The oldest roots are dropped when shrinking
+		assert_eq!(MerkleTrees::get_cached_root_count(0), RootHistorySize::get());
+		for i in 0..RootHistorySize::get() {
+			assert_eq!(MerkleTrees::get_root_at_index(0, i), Some(roots[49 - i as usize]));
+		}
+		assert_eq!(MerkleTrees::get_root_at_index(0, RootHistorySize::get()), None);
+		assert_eq!(
+			RootHistory::<Test>::iter_prefix(0).count() as u32,
+			RootHistorySize::get()
+		);
+		assert!(MerkleTrees::is_known_root(0, roots[49 - 29]));
+		assert!(!MerkleTrees::is_known_root(0, roots[49 - 30]));
+
+		// The history can't grow past the roots which were retained
+		lay_out(1, 4, 10);
+		RootHistoryLength::<Test>::put(4);
+		migrations::resize_root_history::<Test>();
+		assert_eq!(MerkleTrees::get_cached_root_count(1), 4);
+		for i in 0..4 {
+			assert_eq!(MerkleTrees::get_root_at_index(1, i), Some(roots[9 - i as usize]));
+		}
+		assert_eq!(MerkleTrees::get_root_at_index(1, 4), None);
+
+		// New roots keep going into the new layout
+		MerkleTrees::add_root_to_history(1, roots[10]);
+		assert_eq!(MerkleTrees::get_cached_root_count(1), 5);
+		assert_eq!(MerkleTrees::get_root_at_index(1, 0), Some(roots[10]));
+		assert_eq!(MerkleTrees::get_root_at_index(1, 4), Some(roots[6]));
+	});
+}
+
+#[test]
+fn should_backfill_max_leaves() {
+	new_test_ext().execute_with(|| {
+		for depth in 2..4 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(depth),
+				..Default::default()
+			}));
+			// the capacity the trees were created with before it only depended
+			// on their own depth
+			let mut tree = MerkleTrees::trees(depth as u32 - 2).unwrap();
+			tree.max_leaves = u32::MAX >> (MaxTreeDepth::get() - depth);
+			Trees::<Test>::insert(depth as u32 - 2, Some(tree));
+		}
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaves_remaining, 3);
+
+		migrations::backfill_max_leaves::<Test>();
+		assert_eq!(MerkleTrees::trees(0).unwrap().max_leaves, 4);
+		assert_eq!(MerkleTrees::trees(1).unwrap().max_leaves, 8);
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().leaves_remaining, 4);
+	});
+}
+
+#[test]
+fn should_migrate_tree_configs() {
+	new_test_ext().execute_with(|| {
+		for _ in 0..2 {
+			assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				..Default::default()
+			}));
+		}
+		// Both trees were created before their configuration was folded into
+		// `TreeConfigs`, and tree 1 is from before the leaves could be updated
+		TreeConfigs::<Test>::remove(0);
+		TreeConfigs::<Test>::remove(1);
+		let key = |tree_id: u32| Blake2_128Concat::hash(&tree_id.encode());
+		put_storage_value(b"MerkleTrees", b"Denominations", &key(0), Some(5u64));
+		put_storage_value(b"MerkleTrees", b"CommitmentVersions", &key(0), 2u16);
+		put_storage_value(b"MerkleTrees", b"AppendOnly", &key(0), false);
+		put_storage_value(b"MerkleTrees", b"MinAnonymitySets", &key(0), 3u32);
+		put_storage_value(b"MerkleTrees", b"OnFullBehaviors", &key(0), OnFull::Rollover);
+		put_storage_value(b"MerkleTrees", b"Successors", &key(0), Some(1u32));
+		put_storage_value(b"MerkleTrees", b"PublicInputLayouts", &key(0), vec![PublicInput::Root]);
+		put_storage_value(b"MerkleTrees", b"Pruned", &key(1), true);
+
+		migrations::migrate_tree_configs::<Test>();
+		assert_eq!(MerkleTrees::tree_config(0), TreeConfig {
+			denomination: Some(5),
+			commitment_version: 2,
+			append_only: false,
+			min_anonymity_set: 3,
+			on_full: OnFull::Rollover,
+			successor: Some(1),
+			public_input_layout: vec![PublicInput::Root],
+			..Default::default()
+		});
+		assert_eq!(MerkleTrees::tree_config(1), TreeConfig {
+			pruned: true,
+			..Default::default()
+		});
+		assert!(MerkleTrees::append_only(1));
+		assert!(!have_storage_value(b"MerkleTrees", b"Denominations", &key(0)));
+		assert!(!have_storage_value(b"MerkleTrees", b"Pruned", &key(1)));
+
+		// Once the old items are gone the configurations are left alone
+		TreeConfigs::<Test>::mutate(1, |config| config.pruned = false);
+		migrations::migrate_tree_configs::<Test>();
+		assert!(!MerkleTrees::pruned(1));
+	});
+}
+
+#[test]
+fn should_verify_simple_zk_proof_of_membership() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let label = b"zk_membership_proof";
+		let mut prover_transcript = Transcript::new(label);
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::zero(),
+			Scalar::zero(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+
+		assert_ok!(MerkleTrees::verify_zk_membership_proof(
+			0,
+			0,
+			root,
+			comms,
+			ScalarData(nullifier_hash),
+			proof.to_bytes(),
+			leaf_index_comms,
+			proof_comms,
+			ScalarData::zero(),
+			ScalarData::zero(),
+		));
+	});
+}
+
+#[test]
+fn should_not_verify_below_min_anonymity_set() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let label = b"zk_membership_proof";
+		let mut prover_transcript = Transcript::new(label);
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+
+		let leaf = ftree.generate_secrets();
 		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(1)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			min_anonymity_set: 2,
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().min_anonymity_set, 2);
+
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::zero(),
+			Scalar::zero(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+
+		assert_err!(
+			MerkleTrees::verify_zk_membership_proof(
+				0,
+				0,
+				root,
+				comms.clone(),
+				ScalarData(nullifier_hash),
+				proof.to_bytes(),
+				leaf_index_comms.clone(),
+				proof_comms.clone(),
+				ScalarData::zero(),
+				ScalarData::zero(),
+			),
+			Error::<Test>::AnonymitySetTooSmall
+		);
+
+		// the proof against the previous root is accepted once the tree has
+		// enough leaves
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData::zero()]));
+		assert_ok!(MerkleTrees::verify_zk_membership_proof(
+			0,
+			0,
+			root,
+			comms,
+			ScalarData(nullifier_hash),
+			proof.to_bytes(),
+			leaf_index_comms,
+			proof_comms,
+			ScalarData::zero(),
+			ScalarData::zero(),
+		));
+	});
+}
+
+#[test]
+fn should_not_verify_invalid_commitments_for_leaf_creation() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let label = b"zk_membership_proof";
+		let mut prover_transcript = Transcript::new(label);
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::zero(),
+			Scalar::zero(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let mut comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let mut rng = ChaChaRng::from_seed([1u8; 32]);
+		comms[0] = Commitment(RistrettoPoint::random(&mut rng).compress());
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+
+		assert_err!(
+			MerkleTrees::verify_zk_membership_proof(
+				0,
+				0,
+				root,
+				comms,
+				ScalarData(nullifier_hash),
+				proof.to_bytes(),
+				leaf_index_comms,
+				proof_comms,
+				ScalarData::zero(),
+				ScalarData::zero(),
+			),
+			Error::<Test>::ZkVericationFailed
+		);
+	});
+}
+
+#[test]
+fn should_not_verify_invalid_private_inputs() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let label = b"zk_membership_proof";
+		let mut prover_transcript = Transcript::new(label);
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::zero(),
+			Scalar::zero(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let mut comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+
+		let mut rng = ChaChaRng::from_seed([1u8; 32]);
+		comms.push(Commitment(RistrettoPoint::random(&mut rng).compress()));
+
+		assert_err!(
+			MerkleTrees::verify_zk_membership_proof(
+				0,
+				0,
+				root,
+				comms,
+				ScalarData(nullifier_hash),
+				proof.to_bytes(),
+				leaf_index_comms,
+				proof_comms,
+				ScalarData::zero(),
+				ScalarData::zero(),
+			),
+			Error::<Test>::InvalidPrivateInputs
+		);
+	});
+}
+
+#[test]
+fn should_not_verify_invalid_path_commitments_for_membership() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let label = b"zk_membership_proof";
+		let mut prover_transcript = Transcript::new(label);
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::zero(),
+			Scalar::zero(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let mut leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let mut proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let mut rng = ChaChaRng::from_seed([1u8; 32]);
+		leaf_index_comms[0] = Commitment(RistrettoPoint::random(&mut rng).compress());
+		proof_comms[0] = Commitment(RistrettoPoint::random(&mut rng).compress());
+
+		assert_err!(
+			MerkleTrees::verify_zk_membership_proof(
+				0,
+				0,
+				root,
+				comms,
+				ScalarData(nullifier_hash),
+				proof.to_bytes(),
+				leaf_index_comms,
+				proof_comms,
+				ScalarData::zero(),
+				ScalarData::zero(),
+			),
+			Error::<Test>::ZkVericationFailed
+		);
+	});
+}
+
+#[test]
+fn should_not_verify_invalid_transcript() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let label = b"zk_membership_proof_invalid";
+		let mut prover_transcript = Transcript::new(label);
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(1),
+			..Default::default()
+		}));
+		let tree_id = 0;
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		let key_id = 0;
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
+
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::zero(),
+			Scalar::zero(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+
+		assert_err!(
+			MerkleTrees::verify_zk_membership_proof(
+				0,
+				0,
+				root,
+				comms,
+				ScalarData(nullifier_hash),
+				proof.to_bytes(),
+				leaf_index_comms,
+				proof_comms,
+				ScalarData::zero(),
+				ScalarData::zero(),
+			),
+			Error::<Test>::ZkVericationFailed
+		);
+	});
+}
+
+#[test]
+fn should_verify_zk_proof_of_membership() {
+	new_test_ext().execute_with(|| {
+		let pc_gens = PedersenGens::default();
+
+		let mut prover_transcript = Transcript::new(b"zk_membership_proof");
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+		let h = default_hasher(4096);
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(3).build();
+
+		let leaf0 = ftree.generate_secrets();
+		let leaf1 = ftree.generate_secrets();
+		let leaf2 = ftree.generate_secrets();
+		let leaf3 = ftree.generate_secrets();
+		let leaf4 = ftree.generate_secrets();
+		let leaf5 = ftree.generate_secrets();
+		let leaf6 = ftree.generate_secrets();
+		let keys = vec![
+			leaf0.to_bytes(),
+			leaf1.to_bytes(),
+			leaf2.to_bytes(),
+			leaf3.to_bytes(),
+			leaf4.to_bytes(),
+			leaf5.to_bytes(),
+			leaf6.to_bytes(),
+		];
+		ftree.tree.add_leaves(keys.clone(), None);
+
+		let keys_data: Vec<ScalarData> = keys
+			.iter()
+			.map(|x| ScalarData(Scalar::from_bytes_mod_order(*x)))
+			.collect();
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
 		let key_id = 0;
 		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
 
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
-		let root = MerkleTrees::get_merkle_root(0).unwrap();
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys_data));
 
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
 		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
 			root.0,
-			leaf,
+			leaf5,
 			Scalar::zero(),
 			Scalar::zero(),
 			&ftree.hash_params.bp_gens,
@@ -531,166 +2385,268 @@ fn should_verify_simple_zk_proof_of_membership() {
 }
 
 #[test]
-fn should_not_verify_invalid_commitments_for_leaf_creation() {
+fn should_verify_zk_proof_with_matching_public_input_layout() {
 	new_test_ext().execute_with(|| {
 		let pc_gens = PedersenGens::default();
-
-		let label = b"zk_membership_proof";
-		let mut prover_transcript = Transcript::new(label);
-		let prover = Prover::new(&pc_gens, &mut prover_transcript);
-
 		let h = default_hasher(4096);
-		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(3).build();
 
-		let leaf = ftree.generate_secrets();
-		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+		let leaf0 = ftree.generate_secrets();
+		let leaf1 = ftree.generate_secrets();
+		let keys = vec![leaf0.to_bytes(), leaf1.to_bytes()];
+		ftree.tree.add_leaves(keys.clone(), None);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(1)));
-		let tree_id = 0;
+		let keys_data: Vec<ScalarData> = keys
+			.iter()
+			.map(|x| ScalarData(Scalar::from_bytes_mod_order(*x)))
+			.collect();
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys_data));
+		assert_eq!(MerkleTrees::public_input_layout(0), vec![]);
 
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
+		// The circuit binds the recipient first, then the root
 		let root = MerkleTrees::get_merkle_root(0).unwrap();
-
+		let recipient = ScalarData::zero();
+		let mut prover_transcript = Transcript::new(b"zk_membership_proof");
+		prover_transcript.append_message(b"public_input", &recipient.to_bytes());
+		prover_transcript.append_message(b"public_input", &root.to_bytes());
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
 		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
 			root.0,
-			leaf,
-			Scalar::zero(),
+			leaf1,
+			recipient.0,
 			Scalar::zero(),
 			&ftree.hash_params.bp_gens,
 			prover,
 		);
-
-		let mut comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
-		let mut rng = ChaChaRng::from_seed([1u8; 32]);
-		comms[0] = Commitment(RistrettoPoint::random(&mut rng).compress());
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
-
-		assert_err!(
+		let verify = || {
 			MerkleTrees::verify_zk_membership_proof(
 				0,
 				0,
 				root,
-				comms,
+				comms.clone(),
 				ScalarData(nullifier_hash),
 				proof.to_bytes(),
-				leaf_index_comms,
-				proof_comms,
-				ScalarData::zero(),
+				leaf_index_comms.clone(),
+				proof_comms.clone(),
+				recipient,
 				ScalarData::zero(),
-			),
-			Error::<Test>::ZkVericationFailed
+			)
+		};
+
+		assert_err!(verify(), Error::<Test>::ZkVericationFailed);
+		assert_ok!(MerkleTrees::set_public_input_layout(Origin::signed(1), 0, vec![
+			PublicInput::Root,
+			PublicInput::Recipient
+		]));
+		assert_err!(verify(), Error::<Test>::ZkVericationFailed);
+		assert_ok!(MerkleTrees::set_public_input_layout(Origin::signed(1), 0, vec![
+			PublicInput::Recipient,
+			PublicInput::Root
+		]));
+		assert_ok!(verify());
+
+		assert_err!(
+			MerkleTrees::set_public_input_layout(Origin::signed(1), 0, vec![PublicInput::Root, PublicInput::Root]),
+			Error::<Test>::InvalidPublicInputLayout
+		);
+		assert_err!(
+			MerkleTrees::set_public_input_layout(Origin::signed(1), 0, vec![PublicInput::Root; MAX_PUBLIC_INPUTS + 1]),
+			Error::<Test>::InvalidPublicInputLayout
+		);
+		assert_err!(
+			MerkleTrees::set_public_input_layout(Origin::signed(2), 0, vec![]),
+			BadOrigin
 		);
+		assert_eq!(MerkleTrees::public_input_layout(0), vec![
+			PublicInput::Recipient,
+			PublicInput::Root
+		]);
 	});
 }
 
 #[test]
-fn should_not_verify_invalid_private_inputs() {
+fn should_verify_zk_membership_and_use_nullifier() {
 	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
 		let pc_gens = PedersenGens::default();
 
-		let label = b"zk_membership_proof";
-		let mut prover_transcript = Transcript::new(label);
+		let mut prover_transcript = Transcript::new(b"zk_membership_proof");
 		let prover = Prover::new(&pc_gens, &mut prover_transcript);
-
 		let h = default_hasher(4096);
-		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(3).build();
 
 		let leaf = ftree.generate_secrets();
 		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(1)));
-		let tree_id = 0;
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
-
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
 		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
-		let root = MerkleTrees::get_merkle_root(0).unwrap();
 
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
 		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
 			root.0,
 			leaf,
 			Scalar::zero(),
-			Scalar::zero(),
+			MerkleTrees::relayer_input(&0, 0).to_scalar(),
 			&ftree.hash_params.bp_gens,
 			prover,
 		);
 
-		let mut comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
 
-		let mut rng = ChaChaRng::from_seed([1u8; 32]);
-		comms.push(Commitment(RistrettoPoint::random(&mut rng).compress()));
-
+		let mut unsupported = ZkMembershipProof::new(
+			0,
+			root,
+			comms.clone(),
+			ScalarData(nullifier_hash),
+			proof.to_bytes(),
+			leaf_index_comms.clone(),
+			proof_comms.clone(),
+			0,
+			0,
+			0,
+		);
+		unsupported.version = ZK_PROOF_VERSION + 1;
 		assert_err!(
-			MerkleTrees::verify_zk_membership_proof(
-				0,
+			MerkleTrees::verify_zk_membership(Origin::signed(2), 0, unsupported),
+			Error::<Test>::UnsupportedProofVersion
+		);
+		assert_ok!(MerkleTrees::verify_zk_membership(
+			Origin::signed(2),
+			0,
+			ZkMembershipProof::new(
 				0,
 				root,
-				comms,
+				comms.clone(),
 				ScalarData(nullifier_hash),
 				proof.to_bytes(),
-				leaf_index_comms,
-				proof_comms,
-				ScalarData::zero(),
-				ScalarData::zero(),
+				leaf_index_comms.clone(),
+				proof_comms.clone(),
+				0,
+				0,
+				0,
 			),
-			Error::<Test>::InvalidPrivateInputs
+		));
+		assert!(MerkleTrees::used_nullifiers((0, ScalarData(nullifier_hash))));
+		let event = Event::pallet_merkle(crate::Event::Withdrawn(0, 2, ScalarData(nullifier_hash), 0));
+		assert!(System::events().iter().any(|r| r.event == event));
+
+		// Same proof can't be used twice
+		assert_err!(
+			MerkleTrees::verify_zk_membership(
+				Origin::signed(2),
+				0,
+				ZkMembershipProof::new(
+					0,
+					root,
+					comms,
+					ScalarData(nullifier_hash),
+					proof.to_bytes(),
+					leaf_index_comms,
+					proof_comms,
+					0,
+					0,
+					0,
+				),
+			),
+			Error::<Test>::AlreadyUsedNullifier
 		);
 	});
 }
 
 #[test]
-fn should_not_verify_invalid_path_commitments_for_membership() {
+fn should_lock_and_release_denomination() {
 	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
 		let pc_gens = PedersenGens::default();
 
-		let label = b"zk_membership_proof";
-		let mut prover_transcript = Transcript::new(label);
+		let mut prover_transcript = Transcript::new(b"zk_membership_proof");
 		let prover = Prover::new(&pc_gens, &mut prover_transcript);
-
 		let h = default_hasher(4096);
-		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(3).build();
 
 		let leaf = ftree.generate_secrets();
 		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(1)));
-		let tree_id = 0;
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			denomination: Some(100),
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().denomination, Some(100));
+
+		Balances::make_free_balance_be(&1, 250);
+		assert_ok!(MerkleTrees::add_leaf(Origin::signed(1), 0, leaf.to_bytes(), 0));
+		assert_eq!(Balances::free_balance(1), 150);
+		assert_eq!(Balances::free_balance(MerkleTrees::account_id()), 100);
+		// not enough balance for both leaves, so none of them is added
+		let keys = vec![ScalarData::from(key_bytes(1)), ScalarData::from(key_bytes(2))];
+		assert_err!(
+			MerkleTrees::add_leaves(Origin::signed(1), 0, keys),
+			balances::Error::<Test>::InsufficientBalance
+		);
+		assert_eq!(MerkleTrees::trees(0).unwrap().leaf_count, 1);
 
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
 		let root = MerkleTrees::get_merkle_root(0).unwrap();
-
 		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
 			root.0,
 			leaf,
-			Scalar::zero(),
-			Scalar::zero(),
+			MerkleTrees::recipient_input(&2).to_scalar(),
+			MerkleTrees::relayer_input(&3, 10).to_scalar(),
 			&ftree.hash_params.bp_gens,
 			prover,
 		);
 
 		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
-		let mut leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
-		let mut proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
-		let mut rng = ChaChaRng::from_seed([1u8; 32]);
-		leaf_index_comms[0] = Commitment(RistrettoPoint::random(&mut rng).compress());
-		proof_comms[0] = Commitment(RistrettoPoint::random(&mut rng).compress());
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let recipient = 2;
 
+		// the fee is paid out of the deposit
 		assert_err!(
-			MerkleTrees::verify_zk_membership_proof(
+			MerkleTrees::verify_zk_membership(
+				Origin::signed(3),
 				0,
+				ZkMembershipProof::new(
+					0,
+					root,
+					comms.clone(),
+					ScalarData(nullifier_hash),
+					proof.to_bytes(),
+					leaf_index_comms.clone(),
+					proof_comms.clone(),
+					recipient,
+					3,
+					101,
+				),
+			),
+			Error::<Test>::FeeExceedsDeposit
+		);
+		// the relayer submits the withdrawal on behalf of the recipient
+		assert_ok!(MerkleTrees::verify_zk_membership(
+			Origin::signed(3),
+			0,
+			ZkMembershipProof::new(
 				0,
 				root,
 				comms,
@@ -698,44 +2654,96 @@ fn should_not_verify_invalid_path_commitments_for_membership() {
 				proof.to_bytes(),
 				leaf_index_comms,
 				proof_comms,
-				ScalarData::zero(),
-				ScalarData::zero(),
+				recipient,
+				3,
+				10,
 			),
-			Error::<Test>::ZkVericationFailed
+		));
+		// the relayer gets the fee and the recipient the rest of the deposit
+		assert_eq!(Balances::free_balance(2), 90);
+		assert_eq!(Balances::free_balance(3), 10);
+		assert_eq!(Balances::free_balance(MerkleTrees::account_id()), 0);
+	});
+}
+
+#[test]
+fn should_lock_deposits_in_asset() {
+	new_test_ext().execute_with(|| {
+		let asset_id: CurrencyId = 1;
+		assert_ok!(<Tokens as ExtendedTokenSystem<u64, CurrencyId, Balance>>::create(
+			asset_id, 1, 1, 1
+		));
+		assert_ok!(<Tokens as ExtendedTokenSystem<u64, CurrencyId, Balance>>::mint(
+			asset_id, 1, 250
+		));
+
+		// the asset is only used for the deposits of the denomination
+		assert_err!(
+			MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+				depth: Some(3),
+				asset_id: Some(asset_id),
+				..Default::default()
+			}),
+			Error::<Test>::AssetWithoutDenomination
 		);
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			denomination: Some(100),
+			asset_id: Some(asset_id),
+			..Default::default()
+		}));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			denomination: Some(100),
+			..Default::default()
+		}));
+		assert_eq!(MerkleTrees::get_tree_metadata(0).unwrap().asset_id, Some(asset_id));
+		assert_eq!(MerkleTrees::get_tree_metadata(1).unwrap().asset_id, None);
+		assert_eq!(MerkleTrees::get_trees_by_asset(asset_id), vec![0]);
+		assert_eq!(MerkleTrees::get_trees_by_asset(2), Vec::<u32>::new());
+
+		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
+		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+
+		// the deposit is locked in the asset, the native balance isn't touched
+		Balances::make_free_balance_be(&1, 50);
+		let keys = vec![ScalarData::from(key_bytes(1))];
+		assert_ok!(MerkleTrees::add_leaves(Origin::signed(1), 0, keys));
+		assert_eq!(Tokens::free_balance(asset_id, &1), 150);
+		assert_eq!(Tokens::free_balance(asset_id, &MerkleTrees::account_id()), 100);
+		assert_eq!(Balances::free_balance(1), 50);
 	});
 }
 
 #[test]
-fn should_not_verify_invalid_transcript() {
+fn should_verify_zk_membership_proofs_in_batch() {
 	new_test_ext().execute_with(|| {
 		let pc_gens = PedersenGens::default();
 
-		let label = b"zk_membership_proof_invalid";
-		let mut prover_transcript = Transcript::new(label);
+		let mut prover_transcript = Transcript::new(b"zk_membership_proof");
 		let prover = Prover::new(&pc_gens, &mut prover_transcript);
-
 		let h = default_hasher(4096);
-		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(1).build();
+		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(3).build();
 
 		let leaf = ftree.generate_secrets();
 		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(1)));
-		let tree_id = 0;
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
-
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
 		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
-		let root = MerkleTrees::get_merkle_root(0).unwrap();
 
+		let root = MerkleTrees::get_merkle_root(0).unwrap();
 		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
 			root.0,
 			leaf,
 			Scalar::zero(),
-			Scalar::zero(),
+			MerkleTrees::relayer_input(&0, 0).to_scalar(),
 			&ftree.hash_params.bp_gens,
 			prover,
 		);
@@ -744,26 +2752,67 @@ fn should_not_verify_invalid_transcript() {
 		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
 
-		assert_err!(
-			MerkleTrees::verify_zk_membership_proof(
-				0,
-				0,
-				root,
-				comms,
-				ScalarData(nullifier_hash),
-				proof.to_bytes(),
-				leaf_index_comms,
-				proof_comms,
-				ScalarData::zero(),
-				ScalarData::zero(),
-			),
-			Error::<Test>::ZkVericationFailed
+		let valid: ZkProofOf<Test> = ZkMembershipProof::new(
+			0,
+			root,
+			comms,
+			ScalarData(nullifier_hash),
+			proof.to_bytes(),
+			leaf_index_comms,
+			proof_comms,
+			0,
+			0,
+			0,
+		);
+		let mut invalid = valid.clone();
+		invalid.recipient = 1;
+		// the fee is bound to the proof along with the relayer
+		let mut tampered_fee = valid.clone();
+		tampered_fee.fee = 1;
+		let mut unsupported = valid.clone();
+		unsupported.version = ZK_PROOF_VERSION + 1;
+		let proofs = vec![valid.encode(), invalid.encode(), unsupported.encode(), vec![1, 2, 3]];
+		let res = MerkleTrees::verify_zk_membership_proofs(0, proofs.clone());
+		assert_eq!(res, vec![true, false, false, false]);
+		// Unknown trees have no valid proofs
+		let res = MerkleTrees::verify_zk_membership_proofs(1, proofs.clone());
+		assert_eq!(res, vec![false, false, false, false]);
+
+		// The dry run reports why the withdrawal would fail
+		let mut unknown_root = valid.clone();
+		unknown_root.cached_root = ScalarData(Scalar::one());
+		assert_eq!(MerkleTrees::dry_run_withdraw(0, valid.encode()), WithdrawCheck::Valid);
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, invalid.encode()),
+			WithdrawCheck::ProofInvalid
+		);
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, unsupported.encode()),
+			WithdrawCheck::ProofInvalid
+		);
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, tampered_fee.encode()),
+			WithdrawCheck::ProofInvalid
+		);
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, unknown_root.encode()),
+			WithdrawCheck::UnknownRoot
+		);
+		assert!(!MerkleTrees::used_nullifiers((0, ScalarData(nullifier_hash))));
+
+		// Proofs with used nullifiers are no longer valid
+		assert_ok!(MerkleTrees::add_nullifier(1, 0, ScalarData(nullifier_hash)));
+		let res = MerkleTrees::verify_zk_membership_proofs(0, proofs);
+		assert_eq!(res, vec![false, false, false, false]);
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, valid.encode()),
+			WithdrawCheck::NullifierUsed
 		);
 	});
 }
 
 #[test]
-fn should_verify_zk_proof_of_membership() {
+fn should_dry_run_every_check_of_the_withdrawal() {
 	new_test_ext().execute_with(|| {
 		let pc_gens = PedersenGens::default();
 
@@ -772,43 +2821,25 @@ fn should_verify_zk_proof_of_membership() {
 		let h = default_hasher(4096);
 		let mut ftree = FixedDepositTreeBuilder::new().hash_params(h).depth(3).build();
 
-		let leaf0 = ftree.generate_secrets();
-		let leaf1 = ftree.generate_secrets();
-		let leaf2 = ftree.generate_secrets();
-		let leaf3 = ftree.generate_secrets();
-		let leaf4 = ftree.generate_secrets();
-		let leaf5 = ftree.generate_secrets();
-		let leaf6 = ftree.generate_secrets();
-		let keys = vec![
-			leaf0.to_bytes(),
-			leaf1.to_bytes(),
-			leaf2.to_bytes(),
-			leaf3.to_bytes(),
-			leaf4.to_bytes(),
-			leaf5.to_bytes(),
-			leaf6.to_bytes(),
-		];
-		ftree.tree.add_leaves(keys.clone(), None);
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
 
-		let keys_data: Vec<ScalarData> = keys
-			.iter()
-			.map(|x| ScalarData(Scalar::from_bytes_mod_order(*x)))
-			.collect();
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(3)));
-		let tree_id = 0;
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(3),
+			min_anonymity_set: 2,
+			..Default::default()
+		}));
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
-		let key_id = 0;
-		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), tree_id, key_id));
-
-		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, keys_data));
+		assert_ok!(MerkleTrees::initialize_tree(Origin::signed(1), 0, 0));
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData(leaf)]));
 
 		let root = MerkleTrees::get_merkle_root(0).unwrap();
 		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
 			root.0,
-			leaf5,
-			Scalar::zero(),
-			Scalar::zero(),
+			leaf,
+			MerkleTrees::recipient_input(&0).to_scalar(),
+			MerkleTrees::relayer_input(&0, 0).to_scalar(),
 			&ftree.hash_params.bp_gens,
 			prover,
 		);
@@ -816,9 +2847,7 @@ fn should_verify_zk_proof_of_membership() {
 		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
 		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
-
-		assert_ok!(MerkleTrees::verify_zk_membership_proof(
-			0,
+		let valid: ZkProofOf<Test> = ZkMembershipProof::new(
 			0,
 			root,
 			comms,
@@ -826,9 +2855,47 @@ fn should_verify_zk_proof_of_membership() {
 			proof.to_bytes(),
 			leaf_index_comms,
 			proof_comms,
-			ScalarData::zero(),
-			ScalarData::zero(),
-		));
+			0,
+			0,
+			0,
+		);
+
+		// The tree is below its minimum anonymity set, the dry run agrees with
+		// the withdrawal
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, valid.encode()),
+			WithdrawCheck::AnonymitySetTooSmall
+		);
+		assert_eq!(MerkleTrees::verify_zk_membership_proofs(0, vec![valid.encode()]), vec![
+			false
+		]);
+		assert_err!(
+			MerkleTrees::verify_zk_membership(Origin::signed(2), 0, valid.clone()),
+			Error::<Test>::AnonymitySetTooSmall
+		);
+
+		// A tree without a denomination pays no fee
+		let mut with_fee = valid.clone();
+		with_fee.fee = 1;
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, with_fee.encode()),
+			WithdrawCheck::FeeExceedsDeposit
+		);
+		assert_err!(
+			MerkleTrees::verify_zk_membership(Origin::signed(2), 0, with_fee),
+			Error::<Test>::FeeExceedsDeposit
+		);
+
+		// The proof is made against a root still in the root history
+		assert_ok!(MerkleTrees::add_members(Origin::signed(1), 0, vec![ScalarData::from(
+			key_bytes(1)
+		)]));
+		assert_eq!(MerkleTrees::dry_run_withdraw(0, valid.encode()), WithdrawCheck::Valid);
+		assert_ok!(MerkleTrees::verify_zk_membership(Origin::signed(2), 0, valid.clone()));
+		assert_eq!(
+			MerkleTrees::dry_run_withdraw(0, valid.encode()),
+			WithdrawCheck::NullifierUsed
+		);
 	});
 }
 
@@ -845,7 +2912,10 @@ fn should_verify_large_zk_proof_of_membership() {
 		let leaf = ftree.generate_secrets();
 		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
 
-		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), false, Some(32)));
+		assert_ok!(MerkleTrees::create_tree(Origin::signed(1), TreeParams {
+			depth: Some(32),
+			..Default::default()
+		}));
 		let tree_id = 0;
 		let key_data = get_bp_gen_bytes(&BulletproofGens::new(16400, 1));
 		assert_ok!(MerkleTrees::add_verifying_key(Origin::root(), key_data));
@@ -883,6 +2953,47 @@ fn should_verify_large_zk_proof_of_membership() {
 	});
 }
 
+#[test]
+fn scalar_data_bytes_and_hex_round_trip() {
+	let data = ScalarData(Scalar::from(42u64));
+	assert_eq!(ScalarData::from_bytes(data.to_bytes()), Some(data));
+	assert_eq!(ScalarData::from_hex(&data.to_hex()), Some(data));
+	assert_eq!(
+		data.to_hex(),
+		"0x2a00000000000000000000000000000000000000000000000000000000000000"
+	);
+	assert_eq!(
+		ScalarData::from_hex("2a00000000000000000000000000000000000000000000000000000000000000"),
+		Some(data)
+	);
+
+	// Non-canonical scalars are rejected
+	assert_eq!(ScalarData::from_bytes([255u8; 32]), None);
+	assert_eq!(ScalarData::from_hex(&format!("0x{}", "ff".repeat(32))), None);
+	// Malformed hex is rejected
+	assert_eq!(ScalarData::from_hex("0x2a"), None);
+	assert_eq!(ScalarData::from_hex(&format!("0x{}", "zz".repeat(32))), None);
+	assert_eq!(ScalarData::from_hex(&format!("0x+1{}", "00".repeat(31))), None);
+}
+
+#[test]
+fn should_compare_scalars_in_constant_time() {
+	let data = ScalarData::from(key_bytes(1));
+	let same = ScalarData::from(key_bytes(1));
+	let other = ScalarData::from(key_bytes(2));
+
+	assert_eq!(data.ct_eq(&same), data == same);
+	assert!(data.ct_eq(&same));
+	assert_eq!(data.ct_eq(&other), data == other);
+	assert!(!data.ct_eq(&other));
+	// scalars which only differ in the first byte
+	let mut first = key_bytes(1);
+	first[0] ^= 1;
+	let first = ScalarData::from(first);
+	assert_eq!(data.ct_eq(&first), data == first);
+	assert!(!data.ct_eq(&first));
+}
+
 #[test]
 fn encode_bulletproof_gens_and_back() {
 	let gens = BulletproofGens::new(16400, 1);
@@ -1,21 +1,42 @@
 //! All the traits exposed to be used in other custom pallets
 use crate::{
 	utils::keys::{Commitment, ScalarData},
-	Config,
+	Config, HashFunction, OnFull, PublicInput, TreeParamsOf,
 };
 use bulletproofs::PedersenGens;
 use bulletproofs_gadgets::poseidon::builder::Poseidon;
 pub use frame_support::dispatch;
-use sp_std::vec::Vec;
+use sp_std::prelude::*;
 
 /// Tree trait definition to be used in other pallets
 pub trait Tree<T: Config> {
 	/// Check if nullifier is already used, in which case return an error
 	fn has_used_nullifier(id: T::TreeId, nullifier: ScalarData) -> Result<(), dispatch::DispatchError>;
-	/// Sets stopped flag in storage. This flag doesn't do much by itself, it is
-	/// up to higher-level pallet to find the use for it
+	/// Sets stopped flag in storage. No leaves can be added to a stopped tree,
+	/// it is up to higher-level pallet to find any other use for it
 	/// Can only be called by the manager, regardless if the manager is required
 	fn set_stopped(sender: T::AccountId, tree_id: T::TreeId, stopped: bool) -> Result<(), dispatch::DispatchError>;
+	/// Clears the leaves and nodes of a stopped tree, keeping its root
+	/// Can only be called by the manager, regardless if the manager is required
+	fn prune_tree(sender: T::AccountId, tree_id: T::TreeId) -> Result<(), dispatch::DispatchError>;
+	/// Sets what happens to the leaves inserted into the tree once it's full
+	/// Can only be called by the manager, regardless if the manager is required
+	fn set_on_full(sender: T::AccountId, tree_id: T::TreeId, on_full: OnFull) -> Result<(), dispatch::DispatchError>;
+	/// Sets the order the public inputs of the proofs of the tree are bound in
+	/// Can only be called by the manager, regardless if the manager is required
+	fn set_public_input_layout(
+		sender: T::AccountId,
+		tree_id: T::TreeId,
+		layout: Vec<PublicInput>,
+	) -> Result<(), dispatch::DispatchError>;
+	/// Replaces the leaf at the index of a tree which isn't append-only
+	/// Can only be called by the manager, regardless if the manager is required
+	fn update_leaf(
+		sender: T::AccountId,
+		id: T::TreeId,
+		index: u32,
+		leaf: ScalarData,
+	) -> Result<(), dispatch::DispatchError>;
 	/// Sets whether the manager is required for guarded calls.
 	/// Can only be called by the current manager
 	fn set_manager_required(
@@ -30,12 +51,15 @@ pub trait Tree<T: Config> {
 		id: T::TreeId,
 		new_manager: T::AccountId,
 	) -> Result<(), dispatch::DispatchError>;
-	/// Creates a new Tree tree, including a manager for that tree
-	fn create_tree(
-		sender: T::AccountId,
-		is_manager_required: bool,
-		depth: u8,
-	) -> Result<T::TreeId, dispatch::DispatchError>;
+	/// Creates a new Tree tree, including a manager for that tree. Every leaf
+	/// of the tree locks the denomination as a deposit, if it's provided, and
+	/// is encoded in the commitment version. The leaves of an append-only tree
+	/// can't be updated. The name of the tree, if any, must be unique. The
+	/// withdrawals are rejected until the tree has `min_anonymity_set` leaves.
+	/// If `hash_leaf_on_insert` is set, the inputs are hashed into the leaves.
+	/// The deposits are made in the asset, if it's provided along with the
+	/// denomination
+	fn create_tree(sender: T::AccountId, params: TreeParamsOf<T>) -> Result<T::TreeId, dispatch::DispatchError>;
 	/// Initializes the tree with the root hash and edge nodes, must happen
 	/// after keys are set
 	fn initialize_tree(tree_id: T::TreeId, key_id: T::KeyId) -> Result<(), dispatch::DispatchError>;
@@ -47,6 +71,10 @@ pub trait Tree<T: Config> {
 		id: T::TreeId,
 		members: Vec<ScalarData>,
 	) -> Result<(), dispatch::DispatchError>;
+	/// Adds a single leaf to the tree, returning the index it was inserted at
+	fn add_leaf(sender: T::AccountId, id: T::TreeId, leaf: ScalarData) -> Result<u32, dispatch::DispatchError>;
+	/// Adds a batch of leaves to the tree, calculating the new root only once
+	fn add_leaves(sender: T::AccountId, id: T::TreeId, leaves: Vec<ScalarData>) -> Result<(), dispatch::DispatchError>;
 	/// Adds a nullifier to the storage
 	/// Can only be called by the manager if the manager is required
 	fn add_nullifier(sender: T::AccountId, id: T::TreeId, nullifier: ScalarData)
@@ -83,6 +111,28 @@ pub trait Tree<T: Config> {
 		proof_commitments: Vec<Commitment>,
 		recipient: ScalarData,
 		relayer: ScalarData,
+		public_input_layout: &[PublicInput],
 		hash_params: &Poseidon,
 	) -> Result<(), dispatch::DispatchError>;
 }
+
+/// Hashing backend of the trees, every node of every tree is hashed with it.
+/// The hash function of the tree and its Poseidon parameters are passed along,
+/// the implementations are free to ignore them
+pub trait MerkleHasher {
+	/// Hashes the two children into their parent node, the only hash applied
+	/// to the nodes, so the paths can be checked with `hash_two` alone
+	fn hash_two(hasher: &HashFunction, left: ScalarData, right: ScalarData, hash_params: &Poseidon) -> ScalarData;
+	/// Hashes the input into the leaf stored in the tree, only called once
+	/// the leaf is created, for the trees hashing their leaves on insert
+	fn hash_leaf(hasher: &HashFunction, data: ScalarData, hash_params: &Poseidon) -> ScalarData;
+	/// Roots of the empty subtrees of each depth, up to the depth of 32
+	fn zero_tree(hasher: &HashFunction, hash_params: &Poseidon) -> Vec<[u8; 32]> {
+		let mut zero_tree = vec![ScalarData::zero().to_bytes()];
+		for i in 0..32 {
+			let node = ScalarData::from(zero_tree[i]);
+			zero_tree.push(Self::hash_two(hasher, node, node, hash_params).to_bytes());
+		}
+		zero_tree
+	}
+}
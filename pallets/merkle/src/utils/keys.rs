@@ -10,6 +10,7 @@ use curve25519_dalek::{
 	scalar::Scalar,
 };
 use sp_std::vec::Vec;
+use subtle::ConstantTimeEq;
 
 #[derive(Eq, PartialEq, Clone, Default, Debug, Copy)]
 pub struct Commitment(pub CompressedRistretto);
@@ -133,6 +134,47 @@ impl ScalarData {
 	pub fn to_scalar(&self) -> Scalar {
 		self.0
 	}
+
+	/// Constructor from the canonical bytes of the scalar, fails for the
+	/// bytes which aren't reduced modulo the group order
+	pub fn from_bytes(bytes: [u8; 32]) -> Option<Self> {
+		Scalar::from_canonical_bytes(bytes).map(ScalarData)
+	}
+
+	/// Serialize the scalar to its 32 canonical bytes
+	pub fn to_bytes(&self) -> [u8; 32] {
+		self.0.to_bytes()
+	}
+
+	/// Compares the scalars in constant time, so the time taken doesn't
+	/// depend on where they differ
+	pub fn ct_eq(&self, other: &Self) -> bool {
+		self.0.ct_eq(&other.0).into()
+	}
+
+	/// Constructor from the hex string of the canonical bytes, with or
+	/// without the `0x` prefix
+	#[cfg(feature = "std")]
+	pub fn from_hex(hex: &str) -> Option<Self> {
+		let hex = hex.strip_prefix("0x").unwrap_or(hex);
+		// `from_str_radix` takes a leading sign, so the digits are checked first
+		if hex.len() != 2 * SIZE || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+			return None;
+		}
+		let mut bytes = [0u8; SIZE];
+		for (i, byte) in bytes.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+		}
+		Self::from_bytes(bytes)
+	}
+
+	/// Serialize the scalar to the lowercase, `0x`-prefixed hex string of its
+	/// canonical bytes
+	#[cfg(feature = "std")]
+	pub fn to_hex(&self) -> String {
+		let hex: String = self.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+		format!("0x{}", hex)
+	}
 }
 
 pub fn get_bp_gen_bytes(bp_gens: &BulletproofGens) -> Vec<u8> {
@@ -1,7 +1,7 @@
 //! Autogenerated weights for pallet_merkle
 //!
 //! THIS FILE WAS AUTO-GENERATED USING THE SUBSTRATE BENCHMARK CLI VERSION 3.0.0
-//! DATE: 2021-02-17, STEPS: [20, ], REPEAT: 5, LOW RANGE: [], HIGH RANGE: []
+//! DATE: 2021-06-08, STEPS: [20, ], REPEAT: 5, LOW RANGE: [], HIGH RANGE: []
 //! EXECUTION: None, WASM-EXECUTION: Interpreted, CHAIN: Some("dev"), DB CACHE:
 //! 128
 
@@ -37,8 +37,17 @@ pub trait WeightInfo {
 	fn set_manager_required() -> Weight;
 	fn set_manager() -> Weight;
 	fn set_stopped() -> Weight;
+	fn stop_tree() -> Weight;
+	fn resume_tree() -> Weight;
+	fn set_on_full() -> Weight;
+	fn set_public_input_layout(n: u32) -> Weight;
+	fn prune_tree(n: u32) -> Weight;
 	fn add_members(n: u32) -> Weight;
+	fn add_leaf(d: u32) -> Weight;
+	fn add_leaves(n: u32) -> Weight;
+	fn update_leaf(d: u32) -> Weight;
 	fn verify_path(n: u32) -> Weight;
+	fn verify_zk_membership() -> Weight;
 	fn on_finalize() -> Weight;
 }
 
@@ -49,8 +58,8 @@ impl<T: frame_system::Config + Config> WeightInfo for Weights<T> {
 		(8_356_000 as Weight)
 			// Standard Error: 4_000
 			.saturating_add((151_000 as Weight).saturating_mul(d as Weight))
-			.saturating_add(T::DbWeight::get().reads(1 as Weight))
-			.saturating_add(T::DbWeight::get().writes(3 as Weight))
+			.saturating_add(T::DbWeight::get().reads(2 as Weight))
+			.saturating_add(T::DbWeight::get().writes(7 as Weight))
 	}
 
 	fn set_manager_required() -> Weight {
@@ -71,12 +80,75 @@ impl<T: frame_system::Config + Config> WeightInfo for Weights<T> {
 			.saturating_add(T::DbWeight::get().writes(1 as Weight))
 	}
 
+	fn stop_tree() -> Weight {
+		(8_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+
+	fn resume_tree() -> Weight {
+		(9_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(2 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+
+	fn set_on_full() -> Weight {
+		(9_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(2 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+
+	fn set_public_input_layout(n: u32) -> Weight {
+		(9_214_000 as Weight)
+			// Standard Error: 21_000
+			.saturating_add((118_000 as Weight).saturating_mul(n as Weight))
+			.saturating_add(T::DbWeight::get().reads(2 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+
+	fn prune_tree(n: u32) -> Weight {
+		(10_412_000 as Weight)
+			// Standard Error: 3_000
+			.saturating_add((1_186_000 as Weight).saturating_mul(n as Weight))
+			.saturating_add(T::DbWeight::get().reads(3 as Weight))
+			.saturating_add(T::DbWeight::get().writes(34 as Weight))
+			.saturating_add(T::DbWeight::get().writes((3 as Weight).saturating_mul(n as Weight)))
+	}
+
 	fn add_members(n: u32) -> Weight {
 		(384_629_956_000 as Weight)
 			// Standard Error: 141_117_000
 			.saturating_add((20_135_984_000 as Weight).saturating_mul(n as Weight))
-			.saturating_add(T::DbWeight::get().reads(3 as Weight))
-			.saturating_add(T::DbWeight::get().writes(2 as Weight))
+			.saturating_add(T::DbWeight::get().reads(4 as Weight))
+			.saturating_add(T::DbWeight::get().writes(3 as Weight))
+	}
+
+	fn add_leaf(d: u32) -> Weight {
+		(386_204_117_000 as Weight)
+			// Standard Error: 158_902_000
+			.saturating_add((836_412_000 as Weight).saturating_mul(d as Weight))
+			.saturating_add(T::DbWeight::get().reads(14 as Weight))
+			.saturating_add(T::DbWeight::get().writes(8 as Weight))
+			.saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(d as Weight)))
+	}
+
+	fn add_leaves(n: u32) -> Weight {
+		(386_961_004_000 as Weight)
+			// Standard Error: 97_455_000
+			.saturating_add((2_106_318_000 as Weight).saturating_mul(n as Weight))
+			.saturating_add(T::DbWeight::get().reads(11 as Weight))
+			.saturating_add(T::DbWeight::get().writes(6 as Weight))
+			.saturating_add(T::DbWeight::get().writes((3 as Weight).saturating_mul(n as Weight)))
+	}
+
+	fn update_leaf(d: u32) -> Weight {
+		(385_870_552_000 as Weight)
+			// Standard Error: 161_037_000
+			.saturating_add((845_127_000 as Weight).saturating_mul(d as Weight))
+			.saturating_add(T::DbWeight::get().reads(8 as Weight))
+			.saturating_add(T::DbWeight::get().writes(7 as Weight))
+			.saturating_add(T::DbWeight::get().reads((1 as Weight).saturating_mul(d as Weight)))
+			.saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(d as Weight)))
 	}
 
 	fn verify_path(d: u32) -> Weight {
@@ -86,6 +158,12 @@ impl<T: frame_system::Config + Config> WeightInfo for Weights<T> {
 			.saturating_add(T::DbWeight::get().reads(1 as Weight))
 	}
 
+	fn verify_zk_membership() -> Weight {
+		(1_078_562_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(7 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+
 	fn on_finalize() -> Weight {
 		(14_000_000 as Weight)
 			.saturating_add(T::DbWeight::get().reads(2 as Weight))
@@ -63,7 +63,7 @@ benchmarks! {
 			root.0,
 			leaf,
 			ScalarData::from_slice(&caller.encode()).to_scalar(),
-			ScalarData::from_slice(&caller.encode()).to_scalar(),
+			Mixer::<T>::relayer_input(&caller, Zero::zero()).to_scalar(),
 			&ftree.hash_params.bp_gens, prover
 		);
 
@@ -83,7 +83,8 @@ benchmarks! {
 			leaf_index_comms,
 			proof_comms,
 			None,
-			None
+			None,
+			Zero::zero()
 		);
 	}: _(
 		RawOrigin::Signed(caller.clone()),
@@ -65,9 +65,9 @@ use merkle::{
 		keys::{get_bp_gen_bytes, Commitment, ScalarData},
 		permissions::ensure_admin,
 	},
-	Pallet as MerklePallet, Tree as TreeTrait,
+	HashFunction, Pallet as MerklePallet, Tree as TreeTrait, TreeParams,
 };
-use sp_runtime::traits::{AccountIdConversion, Zero};
+use sp_runtime::traits::{AccountIdConversion, BlakeTwo256, Hash, Zero};
 use sp_std::prelude::*;
 use traits::ExtendedMixer;
 use webb_traits::MultiCurrency;
@@ -162,6 +162,8 @@ pub mod pallet {
 			T::AccountId,
 			/// Account id of the relayer
 			T::AccountId,
+			/// Fee paid to the relayer
+			BalanceOf<T>,
 			/// Merkle root
 			ScalarData,
 		),
@@ -183,6 +185,8 @@ pub mod pallet {
 		UnauthorizedCall,
 		/// Mixer is stopped
 		MixerStopped,
+		/// Relayer fee is larger than the deposit
+		FeeExceedsDeposit,
 	}
 
 	#[pallet::pallet]
@@ -301,13 +305,18 @@ pub mod pallet {
 		/// Withdraws a deposited amount from the mixer. Can only withdraw one
 		/// deposit. Accepts proof of membership along with the mixer id.
 		///
-		/// Fails if the mixer is stopped or not initialized.
+		/// The relayer is paid the fee out of the deposit and the recipient
+		/// gets the remainder. Both the relayer and the fee are bound to the
+		/// proof, so they can't be changed by whoever submits it.
+		///
+		/// Fails if the mixer is stopped or not initialized, or if the fee is
+		/// larger than the deposit.
 		///
 		/// Weights:
 		/// - Independent of the arguments.
 		///
 		/// - Base weight: 1_078_562_000_000
-		/// - DB weights: 9 reads, 3 writes
+		/// - DB weights: 10 reads, 4 writes
 		#[pallet::weight(<T as Config>::WeightInfo::withdraw())]
 		pub fn withdraw(origin: OriginFor<T>, withdraw_proof: WithdrawProof<T>) -> DispatchResultWithPostInfo {
 			let sender = ensure_signed(origin)?;
@@ -320,6 +329,10 @@ pub mod pallet {
 			let relayer = withdraw_proof.relayer.unwrap_or(sender.clone());
 			// get mixer info, should fail if tree isn't initialized
 			let mixer_info = Self::get_mixer(withdraw_proof.mixer_id)?;
+			ensure!(
+				withdraw_proof.fee <= mixer_info.fixed_deposit_size,
+				Error::<T>::FeeExceedsDeposit
+			);
 			// check if the nullifier has been used
 			T::Tree::has_used_nullifier(withdraw_proof.mixer_id.into(), withdraw_proof.nullifier_hash)?;
 			// Verify the zero-knowledge proof of membership provided
@@ -333,14 +346,23 @@ pub mod pallet {
 				withdraw_proof.leaf_index_commitments,
 				withdraw_proof.proof_commitments,
 				ScalarData::from_slice(&recipient.encode()),
-				ScalarData::from_slice(&relayer.encode()),
+				Self::relayer_input(&relayer, withdraw_proof.fee),
 			)?;
-			// transfer the fixed deposit size to the sender
+			// pay the relayer and transfer the rest of the fixed deposit size to
+			// the recipient
+			if !withdraw_proof.fee.is_zero() {
+				T::Currency::transfer(
+					mixer_info.currency_id,
+					&Self::account_id(),
+					&relayer,
+					withdraw_proof.fee,
+				)?;
+			}
 			T::Currency::transfer(
 				mixer_info.currency_id,
 				&Self::account_id(),
 				&recipient,
-				mixer_info.fixed_deposit_size,
+				mixer_info.fixed_deposit_size - withdraw_proof.fee,
 			)?;
 			// update the total value locked
 			let tvl = Self::total_value_locked(withdraw_proof.mixer_id);
@@ -357,6 +379,7 @@ pub mod pallet {
 				sender,
 				recipient,
 				relayer,
+				withdraw_proof.fee,
 				withdraw_proof.cached_root,
 			));
 			Ok(().into())
@@ -448,8 +471,10 @@ pub struct WithdrawProof<T: Config> {
 	proof_commitments: Vec<Commitment>,
 	/// The recipient to withdraw amount of currency to
 	recipient: Option<T::AccountId>,
-	/// The recipient to withdraw amount of currency to
+	/// The relayer to pay the fee to
 	relayer: Option<T::AccountId>,
+	/// The fee paid to the relayer out of the deposit
+	fee: BalanceOf<T>,
 }
 
 impl<T: Config> WithdrawProof<T> {
@@ -464,6 +489,7 @@ impl<T: Config> WithdrawProof<T> {
 		proof_commitments: Vec<Commitment>,
 		recipient: Option<T::AccountId>,
 		relayer: Option<T::AccountId>,
+		fee: BalanceOf<T>,
 	) -> Self {
 		Self {
 			mixer_id,
@@ -476,6 +502,7 @@ impl<T: Config> WithdrawProof<T> {
 			proof_commitments,
 			recipient,
 			relayer,
+			fee,
 		}
 	}
 }
@@ -534,6 +561,12 @@ impl<T: Config> Pallet<T> {
 		T::PalletId::get().into_account()
 	}
 
+	/// Public input of the withdrawal proof binding the relayer and the fee,
+	/// so neither can be tampered with after the proof is made.
+	pub fn relayer_input(relayer: &T::AccountId, fee: BalanceOf<T>) -> ScalarData {
+		ScalarData::from(BlakeTwo256::hash_of(&(relayer, fee)).to_fixed_bytes())
+	}
+
 	pub fn get_mixer(mixer_id: T::TreeId) -> Result<MixerInfo<T>, dispatch::DispatchError> {
 		let mixer_info = MixerTrees::<T>::get(mixer_id);
 		// ensure mixer_info has a non-zero deposit, otherwise, the mixer doesn't exist
@@ -564,7 +597,13 @@ impl<T: Config> Pallet<T> {
 			// Iterating over configured sizes and initializing the mixers
 			for size in sizes.into_iter() {
 				// Creating a new merkle group and getting the id back
-				let mixer_id: T::TreeId = T::Tree::create_tree(Self::account_id(), true, depth)?;
+				let params = TreeParams {
+					manager_required: true,
+					depth: Some(depth),
+					hasher: Some(HashFunction::PoseidonDefault),
+					..Default::default()
+				};
+				let mixer_id: T::TreeId = T::Tree::create_tree(Self::account_id(), params)?;
 				// Creating mixer info data
 				let mixer_info = MixerInfo::<T>::new(T::DepositLength::get(), size, T::NativeCurrencyId::get());
 				// Saving the mixer group to storage
@@ -603,7 +642,13 @@ impl<T: Config> ExtendedMixer<T> for Pallet<T> {
 		size: BalanceOf<T>,
 	) -> Result<T::TreeId, dispatch::DispatchError> {
 		let depth: u8 = <T as merkle::Config>::MaxTreeDepth::get();
-		let mixer_id: T::TreeId = T::Tree::create_tree(account_id, true, depth)?;
+		let params = TreeParams {
+			manager_required: true,
+			depth: Some(depth),
+			hasher: Some(HashFunction::PoseidonDefault),
+			..Default::default()
+		};
+		let mixer_id: T::TreeId = T::Tree::create_tree(account_id, params)?;
 		let mixer_info = MixerInfo::<T>::new(T::DepositLength::get(), size, currency_id);
 		MixerTrees::<T>::insert(mixer_id, mixer_info);
 		Ok(mixer_id)
@@ -79,6 +79,11 @@ parameter_types! {
 	pub const MaxLocks: u32 = 50;
 	pub const MaxTreeDepth: u8 = 32;
 	pub const CacheBlockLength: u64 = 5;
+	pub const MaxTrees: u32 = 32;
+	pub const MaxTreeNameLength: u32 = 32;
+	pub const MaxLeavesPerCall: u32 = 32;
+	pub const RootHistorySize: u32 = 30;
+	pub const MerklePalletId: PalletId = PalletId(*b"py/merkl");
 	// Minimum deposit length is 1 month w/ 6 second blocks
 	pub const MinimumDepositLength: u64 = 10 * 60 * 24 * 28;
 }
@@ -137,11 +142,20 @@ impl webb_currencies::Config for Test {
 }
 
 impl merkle::Config for Test {
+	type AssetId = CurrencyId;
+	type Assets = Currencies;
 	type CacheBlockLength = CacheBlockLength;
+	type DepositCurrency = Balances;
+	type DepositPalletId = MerklePalletId;
 	type Event = Event;
+	type Hasher = merkle::DefaultHasher;
 	type KeyId = u32;
+	type MaxLeavesPerCall = MaxLeavesPerCall;
 	type MaxTreeDepth = MaxTreeDepth;
+	type MaxTreeNameLength = MaxTreeNameLength;
+	type MaxTrees = MaxTrees;
 	type Randomness = Randomness;
+	type RootHistorySize = RootHistorySize;
 	type TreeId = u32;
 	type WeightInfo = MerkleWeights<Self>;
 }
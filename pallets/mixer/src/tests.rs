@@ -130,6 +130,7 @@ fn should_stop_and_start_mixer() {
 					Vec::new(),
 					None,
 					None,
+					0,
 				)
 			),
 			Error::<Test>::MixerStopped
@@ -214,7 +215,7 @@ fn should_withdraw_from_each_mixer_successfully() {
 				root.0,
 				leaf,
 				Scalar::from(2u32),
-				Scalar::zero(),
+				Mixer::relayer_input(&0, 0).to_scalar(),
 				&ftree.hash_params.bp_gens,
 				prover,
 			);
@@ -242,6 +243,7 @@ fn should_withdraw_from_each_mixer_successfully() {
 					proof_comms,
 					Some(2),
 					Some(0),
+					0,
 				)
 			));
 			let balance_after = Balances::free_balance(2);
@@ -253,6 +255,81 @@ fn should_withdraw_from_each_mixer_successfully() {
 	})
 }
 
+#[test]
+fn should_pay_relayer_fee_on_withdraw() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Mixer::initialize());
+		assert_ok!(Mixer::initialize_mixer_trees());
+		let pc_gens = PedersenGens::default();
+
+		let tree_id = 0;
+		let recipient: AccountId = 2;
+		let relayer: AccountId = 3;
+		let fee: Balance = 100;
+		let poseidon = MerkleTrees::get_poseidon_hasher_for_tree(tree_id).unwrap();
+		let mut prover_transcript = Transcript::new(b"zk_membership_proof");
+		let prover = Prover::new(&pc_gens, &mut prover_transcript);
+		let mut ftree = FixedDepositTreeBuilder::new()
+			.hash_params(poseidon.clone())
+			.depth(32)
+			.build();
+
+		let leaf = ftree.generate_secrets();
+		ftree.tree.add_leaves(vec![leaf.to_bytes()], None);
+		assert_ok!(Mixer::deposit(Origin::signed(1), tree_id, vec![ScalarData(leaf)]));
+
+		let root = MerkleTrees::get_merkle_root(tree_id).unwrap();
+		let (proof, (comms_cr, nullifier_hash, leaf_index_comms_cr, proof_comms_cr)) = ftree.prove_zk(
+			root.0,
+			leaf,
+			Scalar::from(recipient),
+			Mixer::relayer_input(&relayer, fee).to_scalar(),
+			&ftree.hash_params.bp_gens,
+			prover,
+		);
+
+		let comms: Vec<Commitment> = comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let leaf_index_comms: Vec<Commitment> = leaf_index_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let proof_comms: Vec<Commitment> = proof_comms_cr.iter().map(|x| Commitment(*x)).collect();
+		let withdraw_proof = |fee| {
+			WithdrawProof::new(
+				tree_id,
+				0,
+				root,
+				comms.clone(),
+				ScalarData(nullifier_hash),
+				proof.to_bytes(),
+				leaf_index_comms.clone(),
+				proof_comms.clone(),
+				Some(recipient),
+				Some(relayer),
+				fee,
+			)
+		};
+
+		let m = Mixer::get_mixer(tree_id).unwrap();
+		assert_err!(
+			Mixer::withdraw(Origin::signed(relayer), withdraw_proof(m.fixed_deposit_size + 1)),
+			Error::<Test>::FeeExceedsDeposit
+		);
+		// the fee is bound to the proof, so the relayer can't raise it
+		assert_err!(
+			Mixer::withdraw(Origin::signed(relayer), withdraw_proof(fee + 1)),
+			merkle::Error::<Test>::ZkVericationFailed
+		);
+
+		let recipient_balance_before = Balances::free_balance(recipient);
+		let relayer_balance_before = Balances::free_balance(relayer);
+		assert_ok!(Mixer::withdraw(Origin::signed(relayer), withdraw_proof(fee)));
+		assert_eq!(
+			Balances::free_balance(recipient),
+			recipient_balance_before + m.fixed_deposit_size - fee
+		);
+		assert_eq!(Balances::free_balance(relayer), relayer_balance_before + fee);
+		assert_eq!(Mixer::total_value_locked(tree_id), 0);
+	})
+}
+
 #[test]
 fn should_cache_roots_if_no_new_deposits_show() {
 	new_test_ext().execute_with(|| {
@@ -392,7 +469,7 @@ fn should_make_mixer_with_non_native_token() {
 			root.0,
 			leaf,
 			Scalar::from(recipient),
-			Scalar::zero(),
+			Mixer::relayer_input(&0, 0).to_scalar(),
 			&ftree.hash_params.bp_gens,
 			prover,
 		);
@@ -421,6 +498,7 @@ fn should_make_mixer_with_non_native_token() {
 				proof_comms,
 				Some(recipient),
 				Some(0),
+				0,
 			)
 		));
 		let balance_after = Tokens::free_balance(currency_id, &recipient);
@@ -55,8 +55,8 @@ impl<T: frame_system::Config + Config + merkle::Config> WeightInfo for Weights<T
 
 	fn withdraw() -> Weight {
 		(1_078_562_000_000 as Weight)
-			.saturating_add(T::DbWeight::get().reads(9 as Weight))
-			.saturating_add(T::DbWeight::get().writes(3 as Weight))
+			.saturating_add(T::DbWeight::get().reads(10 as Weight))
+			.saturating_add(T::DbWeight::get().writes(4 as Weight))
 	}
 
 	fn set_stopped() -> Weight {
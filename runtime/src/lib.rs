@@ -143,10 +143,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("webb-node"),
 	impl_name: create_runtime_str!("webb-node"),
 	authoring_version: 1,
-	spec_version: 1,
+	spec_version: 2,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 2,
 };
 
 pub const MILLISECS_PER_BLOCK: u64 = 6000;
@@ -346,14 +346,28 @@ impl pallet_sudo::Config for Runtime {
 parameter_types! {
 	pub const MaxTreeDepth: u8 = 32;
 	pub const CacheBlockLength: BlockNumber = 100;
+	pub const MaxTrees: u32 = 1_000;
+	pub const MaxTreeNameLength: u32 = 32;
+	pub const MaxLeavesPerCall: u32 = 100;
+	pub const RootHistorySize: u32 = 30;
+	pub const MerklePalletId: PalletId = PalletId(*b"py/merkl");
 }
 
 impl merkle::Config for Runtime {
+	type AssetId = CurrencyId;
+	type Assets = Currencies;
 	type CacheBlockLength = CacheBlockLength;
+	type DepositCurrency = Balances;
+	type DepositPalletId = MerklePalletId;
 	type Event = Event;
+	type Hasher = merkle::DefaultHasher;
 	type KeyId = u32;
+	type MaxLeavesPerCall = MaxLeavesPerCall;
 	type MaxTreeDepth = MaxTreeDepth;
+	type MaxTreeNameLength = MaxTreeNameLength;
+	type MaxTrees = MaxTrees;
 	type Randomness = RandomnessCollectiveFlip;
+	type RootHistorySize = RootHistorySize;
 	type TreeId = u32;
 	type WeightInfo = MerkleWeights<Self>;
 }
@@ -522,7 +536,7 @@ construct_runtime!(
 		Currencies: webb_currencies::{Pallet, Storage, Event<T>},
 		Tokens: webb_tokens::{Pallet, Storage, Event<T>},
 		Mixer: mixer::{Pallet, Call, Storage, Event<T>},
-		Merkle: merkle::{Pallet, Call, Storage, Event<T>},
+		Merkle: merkle::{Pallet, Call, Storage, Event<T>, Config<T>},
 	}
 );
 
@@ -858,6 +872,112 @@ impl_runtime_apis! {
 				Some(v)
 			}
 		}
+
+		fn get_leaves_range(tree_id: u32, from: u32, to: u32) -> Vec<Option<ScalarData>> {
+			Merkle::get_leaves_range(tree_id, from, to)
+		}
+
+		fn get_leaves_checksum(tree_id: u32, from: u32, to: u32) -> Option<ScalarData> {
+			Merkle::get_leaves_checksum(tree_id, from, to)
+		}
+
+		fn get_tree_nodes(tree_id: u32, level: u8, from: u32, to: u32) -> Option<Vec<Option<ScalarData>>> {
+			Merkle::get_tree_nodes(tree_id, level, from, to)
+		}
+
+		fn get_leaf_count(tree_id: u32) -> Option<u32> {
+			Merkle::get_leaf_count(tree_id)
+		}
+
+		fn get_tree_count() -> u32 {
+			Merkle::next_tree_id()
+		}
+
+		fn get_tree_id_by_name(name: Vec<u8>) -> Option<u32> {
+			Merkle::get_tree_id_by_name(name)
+		}
+
+		fn get_root(tree_id: u32) -> Option<ScalarData> {
+			Merkle::trees(tree_id).and_then(|tree| tree.root_hash)
+		}
+
+		fn get_membership_proof(tree_id: u32, leaf_index: u32) -> Option<Vec<(bool, ScalarData)>> {
+			Merkle::get_membership_proof(tree_id, leaf_index).ok()
+		}
+
+		fn rebuild_path(tree_id: u32, leaf_index: u32) -> Option<(Vec<(bool, ScalarData)>, ScalarData)> {
+			Merkle::rebuild_path(tree_id, leaf_index)
+		}
+
+		fn get_default_nodes(tree_id: u32) -> Option<Vec<ScalarData>> {
+			Merkle::get_default_nodes(tree_id)
+		}
+
+		fn get_tree_metadata(tree_id: u32) -> Option<merkle::TreeMetadata> {
+			Merkle::get_tree_metadata(tree_id)
+		}
+
+		fn get_trees_by_asset(asset_id: u64) -> Vec<u32> {
+			Merkle::get_trees_by_asset(asset_id)
+		}
+
+		fn get_tree_chain(tree_id: u32) -> Vec<u32> {
+			Merkle::get_tree_chain(tree_id)
+		}
+
+		fn get_storage_status() -> merkle::StorageStatus {
+			Merkle::get_storage_status()
+		}
+
+		fn get_verifying_key(tree_id: u32) -> Option<Vec<u8>> {
+			Merkle::get_verifying_key(tree_id)
+		}
+
+		fn get_public_input_layout(tree_id: u32) -> Vec<merkle::PublicInput> {
+			Merkle::public_input_layout(tree_id)
+		}
+
+		fn get_root_at_index(tree_id: u32, root_index: u32) -> Option<ScalarData> {
+			Merkle::get_root_at_index(tree_id, root_index)
+		}
+
+		fn get_cached_root_count(tree_id: u32) -> u32 {
+			Merkle::get_cached_root_count(tree_id)
+		}
+
+		fn is_known_root(tree_id: u32, root: ScalarData) -> bool {
+			Merkle::is_known_root(tree_id, root)
+		}
+
+		fn is_nullifier_used(tree_id: u32, nullifier: ScalarData) -> bool {
+			Merkle::used_nullifiers((tree_id, nullifier))
+		}
+
+		fn get_leaf_index(tree_id: u32, leaf: ScalarData) -> Option<u32> {
+			Merkle::leaf_index(tree_id, leaf)
+		}
+
+		fn verify_proofs_batch(tree_id: u32, proofs: Vec<Vec<u8>>) -> Vec<bool> {
+			Merkle::verify_zk_membership_proofs(tree_id, proofs)
+		}
+
+		fn dry_run_withdraw(tree_id: u32, proof: Vec<u8>) -> merkle::WithdrawCheck {
+			Merkle::dry_run_withdraw(tree_id, proof)
+		}
+
+		fn get_block_leaves() -> Vec<merkle::LeafChange<u32>> {
+			Merkle::get_block_leaves()
+		}
+
+		fn get_root_history_size() -> u32 {
+			Merkle::get_root_history_size()
+		}
+	}
+
+	impl merkle::MerkleManagerApi<Block, AccountId> for Runtime {
+		fn get_manager(tree_id: u32) -> Option<(AccountId, bool)> {
+			Merkle::get_manager(tree_id).map(|manager| (manager.account_id, manager.required))
+		}
 	}
 
 	#[cfg(feature = "runtime-benchmarks")]